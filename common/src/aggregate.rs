@@ -0,0 +1,45 @@
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use curve25519_dalek::Scalar;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{hex_serde, snapshot::RecordRangeProof};
+
+/// The records of a member in a community starting within `[start, end)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberPeriod {
+    pub user_id: Uuid,
+    pub community_id: Uuid,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+/// Claim that a member's records over a period add up to these totals
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodTotals {
+    pub period: MemberPeriod,
+    pub record_count: u64,
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub generated: BigDecimal,
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub consumed: BigDecimal,
+}
+
+/// Proof of [`PeriodTotals`], covering a whole billing period at once.
+///
+/// The records over the period are proven to be in a signed snapshot through their commitments
+/// only. The sums of their commitments are opened to the totals with the summed blindings, which
+/// reveals nothing about any single record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodTotalsProof {
+    pub statement: PeriodTotals,
+    pub records: RecordRangeProof,
+    #[serde(with = "hex_serde::scalar")]
+    pub generated_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub consumed_blinding: Scalar,
+}
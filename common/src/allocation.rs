@@ -0,0 +1,307 @@
+use bigdecimal::BigDecimal;
+use curve25519_dalek::Scalar;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    aggregate::PeriodTotals,
+    hex_serde,
+    merkle::{Hash, MerkleProof, MerkleRangeProof},
+    snapshot::{MemberAggregate, RecordCommitment, RecordRangeProof, SignedSnapshot},
+    to_fixed_point,
+};
+
+const ATTESTATION_SIGNATURE_CONTEXT: &[u8] = b"petall/allocation-attestation/v1";
+
+/// How a community shares the energy generated by its members over a period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "distribution_rule", rename_all = "snake_case")]
+pub enum DistributionRule {
+    /// Every member with records in the period gets the same share
+    Equal,
+    /// Members get a share proportional to how much they consumed
+    #[default]
+    ProportionalToConsumption,
+}
+
+impl DistributionRule {
+    /// Identifier of the rule in signed snapshot headers
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Equal => 0,
+            Self::ProportionalToConsumption => 1,
+        }
+    }
+
+    /// Share of the community generation allocated to a member, in fixed point. Rounds down, so
+    /// the shares never add up to more than was generated.
+    pub fn allocate(&self, community: &CommunityTotals, member: &MemberShareInputs) -> u64 {
+        if member.record_count == 0 {
+            return 0;
+        }
+
+        match self {
+            Self::Equal => community
+                .generated
+                .checked_div(community.active_members)
+                .unwrap_or(0),
+            Self::ProportionalToConsumption => (community.generated as u128
+                * member.consumed as u128)
+                .checked_div(community.consumed as u128)
+                .map_or(0, |share| share as u64),
+        }
+    }
+}
+
+/// How a community's allocations are proven. It is picked per community alongside the
+/// distribution rule, since a rule computed by an outside provider may need its own proofs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "proof_scheme", rename_all = "snake_case")]
+pub enum ProofScheme {
+    /// Every member's commitments are opened to the community totals, so anyone can check them.
+    /// Other members' quantities stay hidden, but who they are and when they have records does
+    /// not, see [`OpenedAllocationProof`].
+    CommitmentOpening,
+    /// The service signs the allocation with its snapshot key. Proofs stay small and reveal
+    /// nothing about other members, but verifiers have to trust the service with the totals.
+    Attestation,
+    /// Every member's daily totals are committed to in an anonymous, blinded leaf, and only the
+    /// member's own is opened. Other members stay hidden, but allocations are only proven over
+    /// whole days, see [`AggregatedAllocationProof`].
+    #[default]
+    AggregateCommitment,
+}
+
+impl ProofScheme {
+    /// Identifier of the scheme in signed snapshot headers
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::CommitmentOpening => 0,
+            Self::Attestation => 1,
+            Self::AggregateCommitment => 2,
+        }
+    }
+}
+
+/// Fixed point community totals over a period
+pub struct CommunityTotals {
+    pub generated: u64,
+    pub consumed: u64,
+    /// Members with at least one record in the period
+    pub active_members: u64,
+}
+
+/// Fixed point member totals a share depends on
+pub struct MemberShareInputs {
+    pub record_count: u64,
+    pub consumed: u64,
+}
+
+/// Claim that a member's allocation over a period is the community's rule applied to the
+/// community totals
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Allocation {
+    /// The member's own totals, which the rule takes as input
+    pub member: PeriodTotals,
+    pub rule: DistributionRule,
+    pub active_members: u64,
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub community_generated: BigDecimal,
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub community_consumed: BigDecimal,
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub allocated: BigDecimal,
+}
+
+impl Allocation {
+    /// Bytes covered by an attestation of the allocation against the snapshot with root
+    /// `snapshot_root`. Returns `None` if an amount can't be represented in fixed point.
+    pub fn attestation_bytes(&self, snapshot_root: &Hash) -> Option<Vec<u8>> {
+        let period = &self.member.period;
+        let mut bytes = Vec::with_capacity(ATTESTATION_SIGNATURE_CONTEXT.len() + 137);
+        bytes.extend_from_slice(ATTESTATION_SIGNATURE_CONTEXT);
+        bytes.extend_from_slice(snapshot_root.as_bytes());
+        bytes.extend_from_slice(period.user_id.as_bytes());
+        bytes.extend_from_slice(period.community_id.as_bytes());
+        for time in [period.start, period.end] {
+            bytes.extend_from_slice(&time.and_utc().timestamp_micros().to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.member.record_count.to_be_bytes());
+        bytes.push(self.rule.id());
+        bytes.extend_from_slice(&self.active_members.to_be_bytes());
+        for amount in [
+            &self.member.generated,
+            &self.member.consumed,
+            &self.community_generated,
+            &self.community_consumed,
+            &self.allocated,
+        ] {
+            bytes.extend_from_slice(&to_fixed_point(amount)?.to_be_bytes());
+        }
+        Some(bytes)
+    }
+}
+
+/// Proof of an [`Allocation`], made with the scheme committed for the community
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "camelCase")]
+pub enum AllocationProof {
+    CommitmentOpening(OpenedAllocationProof),
+    Attestation(AttestedAllocationProof),
+    AggregateCommitment(Box<AggregatedAllocationProof>),
+}
+
+impl AllocationProof {
+    pub fn scheme(&self) -> ProofScheme {
+        match self {
+            Self::CommitmentOpening(_) => ProofScheme::CommitmentOpening,
+            Self::Attestation(_) => ProofScheme::Attestation,
+            Self::AggregateCommitment(_) => ProofScheme::AggregateCommitment,
+        }
+    }
+
+    pub fn statement(&self) -> &Allocation {
+        match self {
+            Self::CommitmentOpening(proof) => &proof.statement,
+            Self::Attestation(proof) => &proof.statement,
+            Self::AggregateCommitment(proof) => &proof.statement,
+        }
+    }
+
+    pub fn snapshot(&self) -> &SignedSnapshot {
+        match self {
+            Self::CommitmentOpening(proof) => &proof.snapshot,
+            Self::Attestation(proof) => &proof.snapshot,
+            Self::AggregateCommitment(proof) => &proof.records.snapshot,
+        }
+    }
+}
+
+/// Records of one member over a period, revealing their commitments but not their quantities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberRecordRange {
+    pub user_id: Uuid,
+    pub records: Vec<RecordCommitment>,
+    pub record_path: MerkleRangeProof,
+}
+
+/// Proof of an [`Allocation`] under [`ProofScheme::CommitmentOpening`].
+///
+/// Every member's records over the period are given through their commitments, which rebuilds the
+/// whole snapshot root and so shows no member was left out. The summed commitments are opened to
+/// the community totals and the member's own totals, while other members' quantities and prices
+/// stay hidden.
+///
+/// Rebuilding the root takes the leaves in the clear though, so the proof discloses for every
+/// member of the community:
+///
+/// - their user ID,
+/// - how many records they have in the snapshot (the leaf count of their record path),
+/// - the ID and start time of each of their records over the period, and of the records right
+///   before and after it, and so whether they were active over the period.
+///
+/// Communities that can't disclose this pick [`ProofScheme::AggregateCommitment`] or
+/// [`ProofScheme::Attestation`] instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenedAllocationProof {
+    pub statement: Allocation,
+    pub snapshot: SignedSnapshot,
+    /// Every member of the snapshot, in snapshot order
+    pub members: Vec<MemberRecordRange>,
+    #[serde(with = "hex_serde::scalar")]
+    pub member_generated_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub member_consumed_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub community_generated_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub community_consumed_blinding: Scalar,
+}
+
+/// Proof of an [`Allocation`] under [`ProofScheme::AggregateCommitment`].
+///
+/// Snapshots under this scheme commit to a tree of days, each with a leaf per member active that
+/// day holding commitments to their daily totals (see [`MemberAggregate`]). The commitments are
+/// blinded again on top of the member's record commitments, and the leaves are in no particular
+/// order, so they can't be told apart or linked to a member.
+///
+/// The whole day tree is given, which shows no member was left out, and the sum of its
+/// commitments is opened to the community totals. Only the member's own records and aggregate
+/// are opened. Besides the statement, the proof discloses how many members the snapshot has and
+/// how many days it covers.
+///
+/// Other members' aggregates are only tied to their records by the service signing the snapshot,
+/// which verifiers trust with their records already.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregatedAllocationProof {
+    pub statement: Allocation,
+    /// The member's records over the day
+    pub records: RecordRangeProof,
+    #[serde(with = "hex_serde::scalar")]
+    pub member_generated_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub member_consumed_blinding: Scalar,
+    /// Every member aggregate of the day, in tree order
+    pub aggregates: Vec<MemberAggregate>,
+    /// The member's own aggregate, unless they have no records that day
+    pub member_aggregate: Option<AggregateOpening>,
+    /// Path from the day leaf to the root of the day tree
+    pub day_path: MerkleProof,
+    /// Path from the aggregates leaf, the last of the member tree, to the snapshot root
+    pub aggregates_path: MerkleProof,
+    #[serde(with = "hex_serde::scalar")]
+    pub community_generated_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub community_consumed_blinding: Scalar,
+}
+
+/// Position and blindings of a member's own aggregate among the aggregates of a day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregateOpening {
+    pub index: usize,
+    #[serde(with = "hex_serde::scalar")]
+    pub generated_blinding: Scalar,
+    #[serde(with = "hex_serde::scalar")]
+    pub consumed_blinding: Scalar,
+}
+
+/// Proof of an [`Allocation`] under [`ProofScheme::Attestation`]: the allocation signed with the
+/// key of the snapshot it was computed from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestedAllocationProof {
+    pub statement: Allocation,
+    pub snapshot: SignedSnapshot,
+    #[serde(with = "hex_serde::signature")]
+    pub signature: Signature,
+}
+
+impl AttestedAllocationProof {
+    /// Returns `None` if an amount of the statement can't be represented in fixed point
+    pub fn sign(
+        statement: Allocation,
+        snapshot: SignedSnapshot,
+        signing_key: &SigningKey,
+    ) -> Option<Self> {
+        let signature = signing_key.sign(&statement.attestation_bytes(&snapshot.header.root)?);
+        Some(Self {
+            statement,
+            snapshot,
+            signature,
+        })
+    }
+
+    pub fn verify_signature(&self, verifying_key: &VerifyingKey) -> bool {
+        self.statement
+            .attestation_bytes(&self.snapshot.header.root)
+            .is_some_and(|bytes| verifying_key.verify(&bytes, &self.signature).is_ok())
+    }
+}
@@ -0,0 +1,275 @@
+//! Proof bundles: a proof along with everything needed to check it, in a single file users can
+//! hand to an auditor, who checks it offline with `petall-verify`.
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    EnergyRecord,
+    aggregate::PeriodTotalsProof,
+    allocation::AllocationProof,
+    entitlement::GenerationThresholdProof,
+    hex_serde,
+    merkle::Hash,
+    snapshot::{RecordInclusionProof, SignedSnapshot, key_id},
+    verify::{
+        Verdict, verify_allocation, verify_generation_threshold, verify_period_totals,
+        verify_record_inclusion,
+    },
+};
+
+/// Version of the bundle format, bumped on incompatible changes
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum BundledProof {
+    /// A single record is part of a snapshot
+    #[serde(rename_all = "camelCase")]
+    Record {
+        energy_record: EnergyRecord,
+        proof: RecordInclusionProof,
+    },
+    /// A member's totals over a period
+    PeriodTotals { proof: PeriodTotalsProof },
+    /// A member generated at least a threshold over a period
+    GenerationThreshold { proof: GenerationThresholdProof },
+    /// A member's allocation over a period follows the community's rule
+    Allocation { proof: AllocationProof },
+}
+
+impl BundledProof {
+    /// Snapshot the proof is against
+    pub fn snapshot(&self) -> &SignedSnapshot {
+        match self {
+            Self::Record { proof, .. } => &proof.snapshot,
+            Self::PeriodTotals { proof } => &proof.records.snapshot,
+            Self::GenerationThreshold { proof } => &proof.records.snapshot,
+            Self::Allocation { proof } => proof.snapshot(),
+        }
+    }
+}
+
+/// Public key a snapshot is signed with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundledKey {
+    pub key_id: String,
+    #[serde(with = "hex_serde::verifying_key")]
+    pub public_key: VerifyingKey,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofBundle {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// Root of the snapshot the proof is against, to compare with the roots the ZK service
+    /// published
+    pub snapshot_root: Hash,
+    pub snapshot_key: BundledKey,
+    pub proof: BundledProof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BundleError {
+    UnsupportedVersion(u32),
+    /// The bundled key doesn't have the ID it claims
+    KeyIdMismatch {
+        claimed: String,
+        actual: String,
+    },
+    /// The bundle is signed with another key than the one the verifier trusts
+    UntrustedKey {
+        key_id: String,
+    },
+}
+
+impl std::fmt::Display for BundleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported bundle version {version}")
+            }
+            Self::KeyIdMismatch { claimed, actual } => {
+                write!(f, "bundled key claims ID {claimed} but has ID {actual}")
+            }
+            Self::UntrustedKey { key_id } => {
+                write!(f, "bundle is signed with untrusted key {key_id}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BundleError {}
+
+impl ProofBundle {
+    /// Bundles a proof with the key its snapshot is signed with
+    pub fn new(proof: BundledProof, verifying_key: VerifyingKey) -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            exported_at: Utc::now(),
+            snapshot_root: proof.snapshot().header.root,
+            snapshot_key: BundledKey {
+                key_id: key_id(&verifying_key),
+                public_key: verifying_key,
+            },
+            proof,
+        }
+    }
+
+    /// Checks the proof with the bundled key. Without a `trusted_key` this only shows the bundle
+    /// is consistent: whoever made it could have signed the snapshot themselves, so the key ID
+    /// must still be compared with the one the ZK service publishes.
+    pub fn verify(&self, trusted_key: Option<&VerifyingKey>) -> Result<Verdict, BundleError> {
+        if self.version != BUNDLE_VERSION {
+            return Err(BundleError::UnsupportedVersion(self.version));
+        }
+
+        let key = &self.snapshot_key.public_key;
+        let actual = key_id(key);
+        if actual != self.snapshot_key.key_id {
+            return Err(BundleError::KeyIdMismatch {
+                claimed: self.snapshot_key.key_id.clone(),
+                actual,
+            });
+        }
+        if trusted_key.is_some_and(|trusted| trusted != key) {
+            return Err(BundleError::UntrustedKey { key_id: actual });
+        }
+
+        let root = &self.snapshot_root;
+        Ok(match &self.proof {
+            BundledProof::Record {
+                energy_record,
+                proof,
+            } => verify_record_inclusion(proof, energy_record, root, key),
+            BundledProof::PeriodTotals { proof } => verify_period_totals(proof, root, key),
+            BundledProof::GenerationThreshold { proof } => {
+                verify_generation_threshold(proof, root, key)
+            }
+            BundledProof::Allocation { proof } => verify_allocation(proof, root, key),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+    use chrono::NaiveDateTime;
+    use ed25519_dalek::SigningKey;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        allocation::{DistributionRule, ProofScheme},
+        merkle::MerkleTree,
+        snapshot::{SnapshotHeader, member_leaf, record_leaf},
+        verify::VerificationFailure,
+    };
+
+    fn record_bundle(signing_key: &SigningKey) -> ProofBundle {
+        let mut record =
+            EnergyRecord::random(Uuid::new_v4(), Uuid::new_v4(), NaiveDateTime::default());
+        record.generated = record.generated.with_scale(4);
+        record.consumed = record.consumed.with_scale(4);
+        record.consumer_price = record.consumer_price.with_scale(4);
+        record.seller_price = record.seller_price.with_scale(4);
+
+        let salt = Hash::digest(b"salt");
+        let record_tree = MerkleTree::new(vec![record_leaf(&salt, &record).unwrap()]);
+        let member_tree = MerkleTree::new(vec![member_leaf(
+            record.user_id,
+            &record_tree.root(),
+            record_tree.len(),
+        )]);
+        let header = SnapshotHeader {
+            community_id: record.community_id,
+            sequence: 1,
+            taken_at: Utc::now(),
+            record_count: 1,
+            distribution_rule: DistributionRule::default(),
+            proof_scheme: ProofScheme::default(),
+            root: member_tree.root(),
+        };
+
+        ProofBundle::new(
+            BundledProof::Record {
+                energy_record: record,
+                proof: RecordInclusionProof {
+                    snapshot: SignedSnapshot::sign(header, signing_key),
+                    salt,
+                    record_path: record_tree.proof(0).unwrap(),
+                    member_path: member_tree.proof(0).unwrap(),
+                },
+            },
+            signing_key.verifying_key(),
+        )
+    }
+
+    #[test]
+    fn exported_bundle_verifies() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let other_key = SigningKey::from_bytes(&[2; 32]);
+        let json = serde_json::to_string(&record_bundle(&signing_key)).unwrap();
+        let bundle: ProofBundle = serde_json::from_str(&json).unwrap();
+
+        assert!(bundle.verify(None).unwrap().valid);
+        assert!(
+            bundle
+                .verify(Some(&signing_key.verifying_key()))
+                .unwrap()
+                .valid
+        );
+        assert_eq!(
+            bundle.verify(Some(&other_key.verifying_key())).unwrap_err(),
+            BundleError::UntrustedKey {
+                key_id: key_id(&signing_key.verifying_key())
+            }
+        );
+    }
+
+    #[test]
+    fn tampered_bundles_are_rejected() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let other_key = SigningKey::from_bytes(&[2; 32]);
+        let bundle = record_bundle(&signing_key);
+
+        let mut tampered = bundle.clone();
+        if let BundledProof::Record { energy_record, .. } = &mut tampered.proof {
+            energy_record.generated += BigDecimal::from(1);
+        }
+        let verdict = tampered.verify(None).unwrap();
+        assert!(matches!(
+            verdict.failures[..],
+            [VerificationFailure::RootMismatch { .. }]
+        ));
+
+        // Swapping in another key doesn't make its signature valid
+        let mut tampered = bundle.clone();
+        tampered.snapshot_key = BundledKey {
+            key_id: key_id(&other_key.verifying_key()),
+            public_key: other_key.verifying_key(),
+        };
+        let verdict = tampered.verify(None).unwrap();
+        assert!(matches!(
+            verdict.failures[..],
+            [VerificationFailure::UnknownSigningKey { .. }]
+        ));
+
+        let mut tampered = bundle.clone();
+        tampered.snapshot_key.key_id = key_id(&other_key.verifying_key());
+        assert!(matches!(
+            tampered.verify(None),
+            Err(BundleError::KeyIdMismatch { .. })
+        ));
+
+        let mut tampered = bundle;
+        tampered.version = BUNDLE_VERSION + 1;
+        assert_eq!(
+            tampered.verify(None).unwrap_err(),
+            BundleError::UnsupportedVersion(BUNDLE_VERSION + 1)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use bigdecimal::BigDecimal;
+use bulletproofs::{BulletproofGens, PedersenGens, RangeProof};
+use chrono::NaiveDateTime;
+use curve25519_dalek::{RistrettoPoint, Scalar};
+use merlin::Transcript;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    hex_serde,
+    merkle::Hash,
+    snapshot::{RecordCommitment, RecordRangeProof, sum_commitments},
+    to_fixed_point,
+};
+
+/// Bit size of range proofs. Totals are fixed point `u64`s, so 64 bits cover any of them.
+pub const RANGE_PROOF_BITS: usize = 64;
+
+/// Claim that a member generated at least `threshold` kWh over `[start, end)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationThreshold {
+    pub user_id: Uuid,
+    pub community_id: Uuid,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub threshold: BigDecimal,
+}
+
+impl GenerationThreshold {
+    /// Transcript binding a range proof to this statement and the snapshot it's proven against.
+    /// Returns `None` if the threshold can't be represented in fixed point.
+    pub fn transcript(&self, snapshot_root: &Hash) -> Option<Transcript> {
+        let mut transcript = Transcript::new(b"petall/generation-threshold/v1");
+        transcript.append_message(b"root", snapshot_root.as_bytes());
+        transcript.append_message(b"user", self.user_id.as_bytes());
+        transcript.append_message(b"community", self.community_id.as_bytes());
+        transcript.append_u64(b"start", self.start.and_utc().timestamp_micros() as u64);
+        transcript.append_u64(b"end", self.end.and_utc().timestamp_micros() as u64);
+        transcript.append_u64(b"threshold", to_fixed_point(&self.threshold)?);
+        Some(transcript)
+    }
+}
+
+/// Zero-knowledge proof of a [`GenerationThreshold`].
+///
+/// The member's records over the period are proven to be in a signed snapshot through their
+/// commitments only. The commitments add up to a commitment to the total generated, and the range
+/// proof shows that total minus the threshold is not negative, without revealing any value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationThresholdProof {
+    pub statement: GenerationThreshold,
+    pub records: RecordRangeProof,
+    #[serde(with = "hex_serde::range_proof")]
+    pub range_proof: RangeProof,
+}
+
+/// Generators for range proofs over a single value
+pub fn range_proof_generators() -> (BulletproofGens, PedersenGens) {
+    (
+        BulletproofGens::new(RANGE_PROOF_BITS, 1),
+        PedersenGens::default(),
+    )
+}
+
+/// Sum of the generated commitments of `records`, minus `threshold`. The range proof of a
+/// [`GenerationThresholdProof`] is made over this commitment. Returns `None` if a commitment is not
+/// a valid point.
+pub fn generated_surplus_commitment(
+    records: &[RecordCommitment],
+    threshold: u64,
+) -> Option<RistrettoPoint> {
+    let total = sum_commitments(records.iter().map(|record| &record.generated))?;
+    Some(total - PedersenGens::default().B * Scalar::from(threshold))
+}
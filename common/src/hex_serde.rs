@@ -0,0 +1,95 @@
+//! Hex string serialization for cryptographic types, for use with `#[serde(with = "...")]`
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+fn decode<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+    let hex_string = String::deserialize(deserializer)?;
+    let mut bytes = [0u8; N];
+    hex::decode_to_slice(hex_string, &mut bytes).map_err(serde::de::Error::custom)?;
+    Ok(bytes)
+}
+
+pub mod signature {
+    use ed25519_dalek::Signature;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        signature: &Signature,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(signature.to_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Signature, D::Error> {
+        Ok(Signature::from_bytes(&decode(deserializer)?))
+    }
+}
+
+/// Compressed Ristretto points. Decompression is left to the verifier, so an invalid point is
+/// reported as a failed check rather than a parse error.
+pub mod point {
+    use curve25519_dalek::ristretto::CompressedRistretto;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        point: &CompressedRistretto,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(point.as_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<CompressedRistretto, D::Error> {
+        Ok(CompressedRistretto(decode(deserializer)?))
+    }
+}
+
+pub mod range_proof {
+    use bulletproofs::RangeProof;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(proof: &RangeProof, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(proof.to_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RangeProof, D::Error> {
+        let bytes =
+            hex::decode(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        RangeProof::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+pub mod scalar {
+    use curve25519_dalek::Scalar;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(scalar: &Scalar, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(scalar.as_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Scalar, D::Error> {
+        Option::from(Scalar::from_canonical_bytes(decode(deserializer)?))
+            .ok_or_else(|| serde::de::Error::custom("non-canonical scalar"))
+    }
+}
+
+pub mod verifying_key {
+    use ed25519_dalek::VerifyingKey;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(key: &VerifyingKey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(key.as_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<VerifyingKey, D::Error> {
+        VerifyingKey::from_bytes(&decode(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
@@ -1,12 +1,24 @@
 use std::ops::Range;
 
-use bigdecimal::{BigDecimal, FromPrimitive};
+use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive};
 use chrono::{Duration, NaiveDateTime, Timelike};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub mod aggregate;
+pub mod allocation;
+pub mod bundle;
+pub mod entitlement;
+mod hex_serde;
+pub mod merkle;
+pub mod service_auth;
+pub mod snapshot;
+pub mod transparency;
+pub mod validation;
+pub mod verify;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct EnergyRecord {
     pub id: Uuid,
@@ -23,6 +35,24 @@ pub struct EnergyRecord {
     pub start: NaiveDateTime,
 }
 
+/// Energy record quantities are stored as `NUMERIC(11,4)`
+pub const FIXED_POINT_DECIMALS: u32 = 4;
+
+/// Converts a record quantity to an integer number of 10^-4 units.
+/// Returns `None` for negative values or values with more than 4 decimal places.
+pub fn to_fixed_point(value: &BigDecimal) -> Option<u64> {
+    let scaled = value * BigDecimal::from(10u64.pow(FIXED_POINT_DECIMALS));
+    if !scaled.is_integer() {
+        return None;
+    }
+    scaled.to_u64()
+}
+
+/// Inverse of [`to_fixed_point`]
+pub fn from_fixed_point(value: u64) -> BigDecimal {
+    BigDecimal::new(value.into(), FIXED_POINT_DECIMALS.into())
+}
+
 pub fn rng_big_decimal_range(range: &Range<BigDecimal>) -> BigDecimal {
     let mut rng = rand::thread_rng();
     let diff = &range.end - &range.start;
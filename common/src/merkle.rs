@@ -0,0 +1,320 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// SHA-256 digest, serialized as a lowercase hex string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+    pub fn digest(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex_string = String::deserialize(deserializer)?;
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(hex_string, &mut bytes).map_err(serde::de::Error::custom)?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Hashes leaf data. Leaves and inner nodes use different prefixes so one can't be passed off as the other
+pub fn hash_leaf(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(data);
+    Hash(hasher.finalize().into())
+}
+
+pub fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left.0);
+    hasher.update(right.0);
+    Hash(hasher.finalize().into())
+}
+
+/// Binary Merkle tree. A node without a sibling is promoted to the next level unchanged,
+/// so leaves are never duplicated to fill a level.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: Vec<Hash>) -> Self {
+        let mut levels = vec![leaves];
+
+        while let Some(level) = levels.last()
+            && level.len() > 1
+        {
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks(2) yields one or two elements"),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Root of the tree. The root of an empty tree is the hash of the empty string.
+    pub fn root(&self) -> Hash {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => *root,
+            None => Hash::digest(&[]),
+        }
+    }
+
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut position = index;
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            index,
+            leaf_count: self.len(),
+            siblings,
+        })
+    }
+
+    /// Proof for the `count` consecutive leaves starting at `first`
+    pub fn range_proof(&self, first: usize, count: usize) -> Option<MerkleRangeProof> {
+        if count == 0 || first + count > self.len() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let (mut low, mut high) = (first, first + count - 1);
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            if !low.is_multiple_of(2) {
+                siblings.push(level[low - 1]);
+            }
+            if high.is_multiple_of(2)
+                && let Some(sibling) = level.get(high + 1)
+            {
+                siblings.push(*sibling);
+            }
+            low /= 2;
+            high /= 2;
+        }
+
+        Some(MerkleRangeProof {
+            first,
+            leaf_count: self.len(),
+            siblings,
+        })
+    }
+}
+
+/// Path from a leaf to the root of a [`MerkleTree`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Recomputes the root from a leaf hash. Returns `None` if the proof is malformed
+    /// (index out of range or the wrong number of siblings for the tree size).
+    pub fn root_from_leaf(&self, leaf: Hash) -> Option<Hash> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf;
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            if (position ^ 1) < width {
+                let sibling = siblings.next()?;
+                hash = if position.is_multiple_of(2) {
+                    hash_node(&hash, sibling)
+                } else {
+                    hash_node(sibling, &hash)
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+
+        Some(hash)
+    }
+}
+
+/// Path from a run of consecutive leaves to the root of a [`MerkleTree`]. Siblings are ordered
+/// bottom-up, with the left sibling of a level before the right one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleRangeProof {
+    pub first: usize,
+    pub leaf_count: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleRangeProof {
+    /// Recomputes the root from the leaves starting at `first`. Returns `None` if the proof is
+    /// malformed for the number of leaves given.
+    pub fn root_from_leaves(&self, leaves: &[Hash]) -> Option<Hash> {
+        if leaves.is_empty() || self.first + leaves.len() > self.leaf_count {
+            return None;
+        }
+
+        let mut siblings = self.siblings.iter();
+        let mut nodes = leaves.to_vec();
+        let mut low = self.first;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            if !low.is_multiple_of(2) {
+                nodes.insert(0, *siblings.next()?);
+                low -= 1;
+            }
+            let high = low + nodes.len() - 1;
+            if high.is_multiple_of(2) && high + 1 < width {
+                nodes.push(*siblings.next()?);
+            }
+
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks(2) yields one or two elements"),
+                })
+                .collect();
+            low /= 2;
+            width = width.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+
+        Some(nodes[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<Hash> {
+        (0..count).map(|i| hash_leaf(&[i])).collect()
+    }
+
+    #[test]
+    fn every_leaf_proves_against_root() {
+        for count in 1..=9 {
+            let tree = MerkleTree::new(leaves(count));
+
+            for (index, leaf) in leaves(count).into_iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert_eq!(proof.root_from_leaf(leaf), Some(tree.root()));
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let tree = MerkleTree::new(leaves(5));
+        let proof = tree.proof(2).unwrap();
+
+        assert_ne!(proof.root_from_leaf(hash_leaf(&[42])), Some(tree.root()));
+
+        let mut wrong_index = proof.clone();
+        wrong_index.index = 3;
+        assert_ne!(
+            wrong_index.root_from_leaf(hash_leaf(&[2])),
+            Some(tree.root())
+        );
+
+        let mut extra_sibling = proof.clone();
+        extra_sibling.siblings.push(tree.root());
+        assert_eq!(extra_sibling.root_from_leaf(hash_leaf(&[2])), None);
+
+        let mut out_of_range = proof;
+        out_of_range.index = 5;
+        assert_eq!(out_of_range.root_from_leaf(hash_leaf(&[2])), None);
+    }
+
+    #[test]
+    fn every_range_proves_against_root() {
+        for count in 1..=9 {
+            let tree = MerkleTree::new(leaves(count));
+            let leaves = leaves(count);
+
+            for first in 0..leaves.len() {
+                for end in first + 1..=leaves.len() {
+                    let proof = tree.range_proof(first, end - first).unwrap();
+                    assert_eq!(
+                        proof.root_from_leaves(&leaves[first..end]),
+                        Some(tree.root())
+                    );
+
+                    if end - first > 1 {
+                        // Dropping a leaf from the run must not prove
+                        assert_ne!(
+                            proof.root_from_leaves(&leaves[first..end - 1]),
+                            Some(tree.root())
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! Authentication between services with shared HMAC keys.
+//!
+//! A request carries a timestamp, a random nonce and an HMAC-SHA256 signature over its method,
+//! path, timestamp, nonce and body. The receiving end remembers the nonces of the requests it let
+//! through while their timestamps are fresh, so a captured request can't be replayed. The
+//! response is signed in turn over its status, timestamp, body and the request signature, so the
+//! caller also knows it reached the service holding the key and not just whatever answers at the
+//! URL.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json,
+    body::{Body, Bytes, to_bytes},
+    extract::{OriginalUri, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub const TIMESTAMP_HEADER: &str = "x-petall-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-petall-signature";
+pub const NONCE_HEADER: &str = "x-petall-nonce";
+
+/// How many seconds a timestamp may be away from the current time. Nonces are remembered for as
+/// long as their request could still be accepted.
+pub const MAX_CLOCK_SKEW_SECONDS: i64 = 300;
+
+/// Largest request body the middleware buffers to check its signature
+const MAX_REQUEST_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Secret shared by the two ends of a service link
+#[derive(Clone)]
+pub struct ServiceKey {
+    secret: Vec<u8>,
+    /// Nonces of the requests verified with this key, along with when they stop being accepted
+    seen_nonces: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl ServiceKey {
+    /// Keys must be at least as long as the HMAC output
+    pub const MIN_LENGTH: usize = 32;
+
+    pub fn from_hex(hex_secret: &str) -> anyhow::Result<Self> {
+        let secret = hex::decode(hex_secret.trim())?;
+        anyhow::ensure!(
+            secret.len() >= Self::MIN_LENGTH,
+            "service keys must be at least {} bytes",
+            Self::MIN_LENGTH
+        );
+        Ok(Self {
+            secret,
+            seen_nonces: Arc::default(),
+        })
+    }
+
+    /// Loads a hex encoded key, as generated by `openssl rand -hex 32`
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_hex(&std::fs::read_to_string(path)?)
+    }
+
+    fn mac(&self, context: &str, fields: &[&[u8]]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(context.as_bytes());
+        for field in fields {
+            mac.update(b"\n");
+            mac.update(field);
+        }
+        mac
+    }
+
+    fn request_mac(
+        &self,
+        method: &Method,
+        path_and_query: &str,
+        timestamp: i64,
+        nonce: &str,
+        body: &[u8],
+    ) -> Hmac<Sha256> {
+        self.mac(
+            "petall/service-request/v2",
+            &[
+                method.as_str().as_bytes(),
+                path_and_query.as_bytes(),
+                timestamp.to_string().as_bytes(),
+                nonce.as_bytes(),
+                hex::encode(Sha256::digest(body)).as_bytes(),
+            ],
+        )
+    }
+
+    /// Remembers the nonce of a request with a valid signature. Returns `false` if it was seen
+    /// before.
+    fn remember_nonce(&self, nonce: &str, timestamp: i64) -> bool {
+        let now = Utc::now().timestamp();
+        let mut seen_nonces = self
+            .seen_nonces
+            .lock()
+            .expect("nonce cache is not poisoned");
+        seen_nonces.retain(|_, &mut expires_at| expires_at >= now);
+
+        seen_nonces
+            .insert(nonce.to_string(), timestamp + MAX_CLOCK_SKEW_SECONDS)
+            .is_none()
+    }
+
+    fn response_mac(
+        &self,
+        request_signature: &str,
+        status: StatusCode,
+        timestamp: i64,
+        body: &[u8],
+    ) -> Hmac<Sha256> {
+        self.mac(
+            "petall/service-response/v1",
+            &[
+                request_signature.as_bytes(),
+                status.as_str().as_bytes(),
+                timestamp.to_string().as_bytes(),
+                hex::encode(Sha256::digest(body)).as_bytes(),
+            ],
+        )
+    }
+}
+
+impl fmt::Debug for ServiceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ServiceKey(..)")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceAuthError {
+    MissingHeaders,
+    StaleTimestamp,
+    InvalidSignature,
+    ReplayedRequest,
+}
+
+impl fmt::Display for ServiceAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeaders => write!(f, "missing service signature headers"),
+            Self::StaleTimestamp => write!(f, "service signature timestamp is too old"),
+            Self::InvalidSignature => write!(f, "invalid service signature"),
+            Self::ReplayedRequest => write!(f, "service request was already received"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceAuthError {}
+
+impl IntoResponse for ServiceAuthError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct ErrorBody {
+            error: String,
+        }
+
+        let body = ErrorBody {
+            error: self.to_string(),
+        };
+        (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+    }
+}
+
+/// Signs an outgoing request. Returns the headers to send along with it, and the signature the
+/// response has to be checked against.
+pub fn sign_request(
+    key: &ServiceKey,
+    method: &Method,
+    path_and_query: &str,
+    body: &[u8],
+) -> (HeaderMap, String) {
+    let timestamp = Utc::now().timestamp();
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let signature = hex::encode(
+        key.request_mac(method, path_and_query, timestamp, &nonce, body)
+            .finalize()
+            .into_bytes(),
+    );
+
+    let mut headers = signature_headers(timestamp, &signature);
+    headers.insert(
+        NONCE_HEADER,
+        HeaderValue::from_str(&nonce).expect("hex is a valid header value"),
+    );
+    (headers, signature)
+}
+
+/// Checks the signature of an incoming request, and that it was not received before. Returns the
+/// signature so the response can be signed.
+pub fn verify_request(
+    key: &ServiceKey,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<String, ServiceAuthError> {
+    let (timestamp, signature) = read_signature_headers(headers)?;
+    let nonce = headers
+        .get(NONCE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(ServiceAuthError::MissingHeaders)?;
+
+    key.request_mac(method, path_and_query, timestamp, nonce, body)
+        .verify_slice(&hex::decode(&signature).map_err(|_| ServiceAuthError::InvalidSignature)?)
+        .map_err(|_| ServiceAuthError::InvalidSignature)?;
+    if !key.remember_nonce(nonce, timestamp) {
+        return Err(ServiceAuthError::ReplayedRequest);
+    }
+
+    Ok(signature)
+}
+
+/// Headers signing a response to the request with `request_signature`
+pub fn sign_response(
+    key: &ServiceKey,
+    request_signature: &str,
+    status: StatusCode,
+    body: &[u8],
+) -> HeaderMap {
+    let timestamp = Utc::now().timestamp();
+    let signature = key
+        .response_mac(request_signature, status, timestamp, body)
+        .finalize()
+        .into_bytes();
+
+    signature_headers(timestamp, &hex::encode(signature))
+}
+
+/// Checks that a response was signed by the service the request was sent to
+pub fn verify_response(
+    key: &ServiceKey,
+    request_signature: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), ServiceAuthError> {
+    let (timestamp, signature) = read_signature_headers(headers)?;
+
+    key.response_mac(request_signature, status, timestamp, body)
+        .verify_slice(&hex::decode(&signature).map_err(|_| ServiceAuthError::InvalidSignature)?)
+        .map_err(|_| ServiceAuthError::InvalidSignature)
+}
+
+fn signature_headers(timestamp: i64, signature: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+    headers.insert(
+        SIGNATURE_HEADER,
+        HeaderValue::from_str(signature).expect("hex is a valid header value"),
+    );
+    headers
+}
+
+fn read_signature_headers(headers: &HeaderMap) -> Result<(i64, String), ServiceAuthError> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .ok_or(ServiceAuthError::MissingHeaders)
+    };
+
+    let timestamp: i64 = header(TIMESTAMP_HEADER)?
+        .parse()
+        .map_err(|_| ServiceAuthError::MissingHeaders)?;
+    if (Utc::now().timestamp() - timestamp).abs() > MAX_CLOCK_SKEW_SECONDS {
+        return Err(ServiceAuthError::StaleTimestamp);
+    }
+
+    Ok((timestamp, header(SIGNATURE_HEADER)?.to_string()))
+}
+
+/// Middleware only letting through requests signed with `key`, and signing their responses.
+///
+/// Each request is let through once: its nonce is remembered in `key` until its timestamp goes
+/// stale. The nonces are kept in memory, so a request could still be replayed against another
+/// instance of the service, or across a restart within [`MAX_CLOCK_SKEW_SECONDS`].
+pub async fn authenticate_service(
+    State(key): State<Arc<ServiceKey>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_REQUEST_BODY_BYTES).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+
+    // Nested routers only see the rest of the path, but the caller signed all of it
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |original| &original.0);
+    let path_and_query = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    let request_signature =
+        match verify_request(&key, &parts.method, path_and_query, &parts.headers, &body) {
+            Ok(signature) => signature,
+            Err(e) => return e.into_response(),
+        };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (mut parts, body) = response.into_parts();
+    let body: Bytes = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    parts
+        .headers
+        .extend(sign_response(&key, &request_signature, parts.status, &body));
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> ServiceKey {
+        ServiceKey::from_hex(&hex::encode([byte; 32])).unwrap()
+    }
+
+    #[test]
+    fn signed_exchange_is_accepted() {
+        let key = key(1);
+        let (headers, request_signature) = sign_request(&key, &Method::POST, "/prove?x=1", b"{}");
+
+        let verified = verify_request(&key, &Method::POST, "/prove?x=1", &headers, b"{}").unwrap();
+        assert_eq!(verified, request_signature);
+
+        // Each request is only let through once, by any clone of the key
+        assert_eq!(
+            verify_request(&key.clone(), &Method::POST, "/prove?x=1", &headers, b"{}"),
+            Err(ServiceAuthError::ReplayedRequest)
+        );
+
+        let response_headers = sign_response(&key, &verified, StatusCode::OK, b"proof");
+        verify_response(
+            &key,
+            &request_signature,
+            StatusCode::OK,
+            &response_headers,
+            b"proof",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn tampered_requests_are_rejected() {
+        let key = key(1);
+        let (headers, request_signature) = sign_request(&key, &Method::POST, "/prove", b"{}");
+
+        for (key, method, path, body) in [
+            (&key, Method::GET, "/prove", b"{}"),
+            (&key, Method::POST, "/other", b"{}"),
+            (&key, Method::POST, "/prove", b"[]"),
+        ] {
+            assert_eq!(
+                verify_request(key, &method, path, &headers, body),
+                Err(ServiceAuthError::InvalidSignature)
+            );
+        }
+        assert_eq!(
+            verify_request(&self::key(2), &Method::POST, "/prove", &headers, b"{}"),
+            Err(ServiceAuthError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_request(&key, &Method::POST, "/prove", &HeaderMap::new(), b"{}"),
+            Err(ServiceAuthError::MissingHeaders)
+        );
+
+        let mut stale = signature_headers(
+            Utc::now().timestamp() - MAX_CLOCK_SKEW_SECONDS - 1,
+            &request_signature,
+        );
+        stale.insert(NONCE_HEADER, headers[NONCE_HEADER].clone());
+        assert_eq!(
+            verify_request(&key, &Method::POST, "/prove", &stale, b"{}"),
+            Err(ServiceAuthError::StaleTimestamp)
+        );
+
+        // A request signature can't be passed off as a response signature
+        assert_eq!(
+            verify_response(&key, &request_signature, StatusCode::OK, &headers, b"{}"),
+            Err(ServiceAuthError::InvalidSignature)
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use bulletproofs::PedersenGens;
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use curve25519_dalek::{RistrettoPoint, Scalar, ristretto::CompressedRistretto};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use uuid::Uuid;
+
+use crate::{
+    EnergyRecord,
+    allocation::{DistributionRule, ProofScheme},
+    hex_serde,
+    merkle::{Hash, MerkleProof, MerkleRangeProof, hash_leaf},
+    to_fixed_point,
+};
+
+const SNAPSHOT_SIGNATURE_CONTEXT: &[u8] = b"petall/snapshot/v1";
+
+/// Commitment to every energy record of a community at a point in time.
+///
+/// The root is a Merkle tree over the community members (ordered by user ID), where each member
+/// leaf commits to a Merkle tree over that member's records (ordered by start time). Under
+/// [`ProofScheme::AggregateCommitment`] the tree ends with one more leaf, committing to the
+/// members' daily totals (see [`aggregates_leaf`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotHeader {
+    pub community_id: Uuid,
+    /// Increases by one with every snapshot committed for the community
+    pub sequence: u64,
+    pub taken_at: DateTime<Utc>,
+    pub record_count: u64,
+    /// Rule the community shares generation by, which allocation proofs are checked against
+    pub distribution_rule: DistributionRule,
+    /// Scheme allocation proofs against the snapshot are made with
+    pub proof_scheme: ProofScheme,
+    pub root: Hash,
+}
+
+impl SnapshotHeader {
+    /// Bytes covered by the snapshot signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SNAPSHOT_SIGNATURE_CONTEXT.len() + 74);
+        bytes.extend_from_slice(SNAPSHOT_SIGNATURE_CONTEXT);
+        bytes.extend_from_slice(self.community_id.as_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.taken_at.timestamp_micros().to_be_bytes());
+        bytes.extend_from_slice(&self.record_count.to_be_bytes());
+        bytes.push(self.distribution_rule.id());
+        bytes.push(self.proof_scheme.id());
+        bytes.extend_from_slice(self.root.as_bytes());
+        bytes
+    }
+}
+
+/// Snapshot header signed by the service that committed it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedSnapshot {
+    pub header: SnapshotHeader,
+    pub key_id: String,
+    #[serde(with = "hex_serde::signature")]
+    pub signature: Signature,
+}
+
+impl SignedSnapshot {
+    pub fn sign(header: SnapshotHeader, signing_key: &SigningKey) -> Self {
+        let signature = signing_key.sign(&header.signing_bytes());
+        Self {
+            header,
+            key_id: key_id(&signing_key.verifying_key()),
+            signature,
+        }
+    }
+
+    pub fn verify_signature(&self, verifying_key: &VerifyingKey) -> bool {
+        self.key_id == key_id(verifying_key)
+            && verifying_key
+                .verify(&self.header.signing_bytes(), &self.signature)
+                .is_ok()
+    }
+}
+
+/// Records that became provable in a round of snapshot commits, reported by the ZK service to the
+/// community backend so it only hands out validation tokens for records that can be proven
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommittedRecords {
+    pub committed_at: DateTime<Utc>,
+    /// When the next round of commits is expected
+    pub next_commit_at: DateTime<Utc>,
+    pub energy_record_ids: Vec<Uuid>,
+}
+
+/// Short identifier of a snapshot signing key: the first 8 bytes of the SHA-256 of the public key
+pub fn key_id(verifying_key: &VerifyingKey) -> String {
+    hex::encode(&Hash::digest(verifying_key.as_bytes()).as_bytes()[..8])
+}
+
+/// Blinding factors of the quantity commitments of a record. They are derived from the record
+/// salt, so revealing the salt opens the whole leaf.
+pub struct QuantityBlindings {
+    pub generated: Scalar,
+    pub consumed: Scalar,
+}
+
+impl QuantityBlindings {
+    pub fn derive(salt: &Hash) -> Self {
+        let derive = |quantity: &[u8]| {
+            let mut hasher = Sha512::new();
+            hasher.update(b"petall/blinding");
+            hasher.update(salt.as_bytes());
+            hasher.update(quantity);
+            Scalar::from_bytes_mod_order_wide(&hasher.finalize().into())
+        };
+
+        Self {
+            generated: derive(b"generated"),
+            consumed: derive(b"consumed"),
+        }
+    }
+}
+
+/// Public part of a record leaf. It reveals when the record starts, but only commits to its
+/// quantities: `generated` and `consumed` are Pedersen commitments to the fixed point values,
+/// which can be added up and range proven without opening them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordCommitment {
+    pub id: Uuid,
+    pub start: NaiveDateTime,
+    #[serde(with = "hex_serde::point")]
+    pub generated: CompressedRistretto,
+    #[serde(with = "hex_serde::point")]
+    pub consumed: CompressedRistretto,
+    /// Salted hash of the record quantities and prices, which keeps low-entropy values from being
+    /// brute-forced out of the leaf
+    pub values_digest: Hash,
+}
+
+impl RecordCommitment {
+    /// Returns `None` if a quantity can't be represented in fixed point
+    pub fn open(salt: &Hash, record: &EnergyRecord) -> Option<Self> {
+        let generated = to_fixed_point(&record.generated)?;
+        let consumed = to_fixed_point(&record.consumed)?;
+
+        let mut values = Vec::with_capacity(71);
+        values.extend_from_slice(b"values");
+        values.extend_from_slice(salt.as_bytes());
+        for value in [generated, consumed] {
+            values.extend_from_slice(&value.to_be_bytes());
+        }
+        for price in [&record.consumer_price, &record.seller_price] {
+            values.extend_from_slice(&to_fixed_point(price)?.to_be_bytes());
+        }
+
+        let pedersen = PedersenGens::default();
+        let blindings = QuantityBlindings::derive(salt);
+
+        Some(Self {
+            id: record.id,
+            start: record.start,
+            generated: pedersen
+                .commit(Scalar::from(generated), blindings.generated)
+                .compress(),
+            consumed: pedersen
+                .commit(Scalar::from(consumed), blindings.consumed)
+                .compress(),
+            values_digest: Hash::digest(&values),
+        })
+    }
+
+    pub fn leaf(&self, user_id: Uuid, community_id: Uuid) -> Hash {
+        let mut data = Vec::with_capacity(158);
+        data.extend_from_slice(b"record");
+        data.extend_from_slice(self.id.as_bytes());
+        data.extend_from_slice(user_id.as_bytes());
+        data.extend_from_slice(community_id.as_bytes());
+        data.extend_from_slice(&self.start.and_utc().timestamp_micros().to_be_bytes());
+        data.extend_from_slice(self.generated.as_bytes());
+        data.extend_from_slice(self.consumed.as_bytes());
+        data.extend_from_slice(self.values_digest.as_bytes());
+        hash_leaf(&data)
+    }
+}
+
+/// Adds up commitments, which yields a commitment to the sum of their values. Returns `None` if a
+/// commitment is not a valid point.
+pub fn sum_commitments<'a>(
+    commitments: impl IntoIterator<Item = &'a CompressedRistretto>,
+) -> Option<RistrettoPoint> {
+    let mut sum = RistrettoPoint::default();
+    for commitment in commitments {
+        sum += commitment.decompress()?;
+    }
+    Some(sum)
+}
+
+/// Leaf committing to a single energy record. Returns `None` if a quantity can't be represented
+/// in fixed point.
+pub fn record_leaf(salt: &Hash, record: &EnergyRecord) -> Option<Hash> {
+    Some(RecordCommitment::open(salt, record)?.leaf(record.user_id, record.community_id))
+}
+
+/// Leaf of the community tree committing to the records of one member
+pub fn member_leaf(user_id: Uuid, member_root: &Hash, record_count: usize) -> Hash {
+    let mut data = Vec::with_capacity(62);
+    data.extend_from_slice(b"member");
+    data.extend_from_slice(user_id.as_bytes());
+    data.extend_from_slice(member_root.as_bytes());
+    data.extend_from_slice(&(record_count as u64).to_be_bytes());
+    hash_leaf(&data)
+}
+
+/// Commitments to a member's totals over a day, blinded on top of their record commitments so
+/// they can't be linked back to them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberAggregate {
+    #[serde(with = "hex_serde::point")]
+    pub generated: CompressedRistretto,
+    #[serde(with = "hex_serde::point")]
+    pub consumed: CompressedRistretto,
+}
+
+impl MemberAggregate {
+    /// Leaf of the day tree. It doesn't name the member, so the leaves of a day are only told
+    /// apart by their commitments.
+    pub fn leaf(&self) -> Hash {
+        let mut data = Vec::with_capacity(73);
+        data.extend_from_slice(b"aggregate");
+        data.extend_from_slice(self.generated.as_bytes());
+        data.extend_from_slice(self.consumed.as_bytes());
+        hash_leaf(&data)
+    }
+}
+
+/// Day `[start, end)` spans, if it is exactly one day starting at midnight. Members' totals are
+/// only aggregated over such days.
+pub fn aggregate_day(start: NaiveDateTime, end: NaiveDateTime) -> Option<NaiveDate> {
+    let day = start.date();
+    (start == day.and_time(NaiveTime::MIN)
+        && end == day.checked_add_days(Days::new(1))?.and_time(NaiveTime::MIN))
+    .then_some(day)
+}
+
+/// Leaf of the tree of days, committing to the aggregates of the members active that day
+pub fn day_leaf(
+    community_id: Uuid,
+    day: NaiveDate,
+    aggregates_root: &Hash,
+    aggregate_count: usize,
+) -> Hash {
+    let mut data = Vec::with_capacity(67);
+    data.extend_from_slice(b"day");
+    data.extend_from_slice(community_id.as_bytes());
+    data.extend_from_slice(
+        &day.and_time(NaiveTime::MIN)
+            .and_utc()
+            .timestamp_micros()
+            .to_be_bytes(),
+    );
+    data.extend_from_slice(aggregates_root.as_bytes());
+    data.extend_from_slice(&(aggregate_count as u64).to_be_bytes());
+    hash_leaf(&data)
+}
+
+/// Last leaf of the member tree under [`ProofScheme::AggregateCommitment`], committing to the tree
+/// of days
+pub fn aggregates_leaf(days_root: &Hash) -> Hash {
+    let mut data = Vec::with_capacity(42);
+    data.extend_from_slice(b"aggregates");
+    data.extend_from_slice(days_root.as_bytes());
+    hash_leaf(&data)
+}
+
+/// Proof that an energy record is included in a signed snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordInclusionProof {
+    pub snapshot: SignedSnapshot,
+    pub salt: Hash,
+    /// Path from the record leaf to the member root
+    pub record_path: MerkleProof,
+    /// Path from the member leaf to the snapshot root
+    pub member_path: MerkleProof,
+}
+
+impl RecordInclusionProof {
+    /// Recomputes the snapshot root this proof implies for `record`
+    pub fn compute_root(&self, record: &EnergyRecord) -> Option<Hash> {
+        let leaf = record_leaf(&self.salt, record)?;
+        let member_root = self.record_path.root_from_leaf(leaf)?;
+        let member = member_leaf(record.user_id, &member_root, self.record_path.leaf_count);
+        self.member_path.root_from_leaf(member)
+    }
+}
+
+/// Proof that `records` are consecutive records of one member in a signed snapshot, revealing
+/// only their commitments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordRangeProof {
+    pub snapshot: SignedSnapshot,
+    pub records: Vec<RecordCommitment>,
+    /// Path from the records to the member root
+    pub record_path: MerkleRangeProof,
+    /// Path from the member leaf to the snapshot root
+    pub member_path: MerkleProof,
+}
+
+impl RecordRangeProof {
+    /// Recomputes the snapshot root this proof implies for the records of `user_id`
+    pub fn compute_root(&self, user_id: Uuid) -> Option<Hash> {
+        let member = member_leaf_from_records(
+            user_id,
+            self.snapshot.header.community_id,
+            &self.records,
+            &self.record_path,
+        )?;
+        self.member_path.root_from_leaf(member)
+    }
+
+    /// See [`records_in_period`]
+    pub fn records_in_period(
+        &self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Option<&[RecordCommitment]> {
+        records_in_period(&self.records, &self.record_path, start, end)
+    }
+}
+
+/// Member leaf implied by a run of consecutive records of the member
+pub fn member_leaf_from_records(
+    user_id: Uuid,
+    community_id: Uuid,
+    records: &[RecordCommitment],
+    record_path: &MerkleRangeProof,
+) -> Option<Hash> {
+    let leaves: Vec<_> = records
+        .iter()
+        .map(|record| record.leaf(user_id, community_id))
+        .collect();
+
+    let member_root = record_path.root_from_leaves(&leaves)?;
+    Some(member_leaf(user_id, &member_root, record_path.leaf_count))
+}
+
+/// Records of a run starting within `[start, end)`. Returns `None` unless the run covers the whole
+/// period: it has to be in order, and begin with the member's first record or one from before the
+/// period, and end with the member's last record or one from after it.
+pub fn records_in_period<'a>(
+    records: &'a [RecordCommitment],
+    record_path: &MerkleRangeProof,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Option<&'a [RecordCommitment]> {
+    let (first, last) = (records.first()?, records.last()?);
+
+    let in_order = records
+        .windows(2)
+        .all(|pair| (pair[0].start, pair[0].id) < (pair[1].start, pair[1].id));
+    let covers_start = record_path.first == 0 || first.start < start;
+    let covers_end =
+        record_path.first + records.len() == record_path.leaf_count || last.start >= end;
+
+    if start > end || !in_order || !covers_start || !covers_end {
+        return None;
+    }
+
+    let low = records.partition_point(|record| record.start < start);
+    let high = records.partition_point(|record| record.start < end);
+    Some(&records[low..high])
+}
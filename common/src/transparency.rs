@@ -0,0 +1,152 @@
+//! Transparency log of snapshot roots.
+//!
+//! Every snapshot the ZK service commits is appended to a log in which each entry chains onto
+//! the head before it, and the service signs the resulting head. Clients keep the last head they
+//! saw and only accept a newer one along with a consistency proof from it, so a service showing
+//! different roots to different clients ends up signing two heads of the same size that differ,
+//! which anyone comparing notes can point at.
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hex_serde,
+    merkle::Hash,
+    snapshot::{SignedSnapshot, key_id},
+};
+
+const ENTRY_CONTEXT: &[u8] = b"petall/log-entry/v1";
+const HEAD_SIGNATURE_CONTEXT: &[u8] = b"petall/log-head/v1";
+
+/// Head of the empty log
+pub const EMPTY_HEAD: Hash = Hash([0; 32]);
+
+/// Digest of a signed snapshot, which is what the log commits to
+pub fn snapshot_digest(snapshot: &SignedSnapshot) -> Hash {
+    let mut data = snapshot.header.signing_bytes();
+    data.extend_from_slice(snapshot.key_id.as_bytes());
+    data.extend_from_slice(&snapshot.signature.to_bytes());
+    Hash::digest(&data)
+}
+
+/// Head of the log once the snapshot with `digest` is appended to the log with `head`
+pub fn chain(head: &Hash, digest: &Hash) -> Hash {
+    let mut data = Vec::with_capacity(ENTRY_CONTEXT.len() + 64);
+    data.extend_from_slice(ENTRY_CONTEXT);
+    data.extend_from_slice(head.as_bytes());
+    data.extend_from_slice(digest.as_bytes());
+    Hash::digest(&data)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    /// Position in the log, starting at 0
+    pub index: u64,
+    pub snapshot: SignedSnapshot,
+    /// Head of the log once this entry is appended
+    pub head: Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogHead {
+    /// Number of entries in the log
+    pub size: u64,
+    pub head: Hash,
+    pub signed_at: DateTime<Utc>,
+}
+
+impl LogHead {
+    /// Bytes covered by the head signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEAD_SIGNATURE_CONTEXT.len() + 48);
+        bytes.extend_from_slice(HEAD_SIGNATURE_CONTEXT);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(self.head.as_bytes());
+        bytes.extend_from_slice(&self.signed_at.timestamp_micros().to_be_bytes());
+        bytes
+    }
+}
+
+/// Log head signed with the key the snapshots are signed with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedLogHead {
+    pub head: LogHead,
+    pub key_id: String,
+    #[serde(with = "hex_serde::signature")]
+    pub signature: Signature,
+}
+
+impl SignedLogHead {
+    pub fn sign(head: LogHead, signing_key: &SigningKey) -> Self {
+        let signature = signing_key.sign(&head.signing_bytes());
+        Self {
+            head,
+            key_id: key_id(&signing_key.verifying_key()),
+            signature,
+        }
+    }
+
+    pub fn verify_signature(&self, verifying_key: &VerifyingKey) -> bool {
+        self.key_id == key_id(verifying_key)
+            && verifying_key
+                .verify(&self.head.signing_bytes(), &self.signature)
+                .is_ok()
+    }
+
+    /// Whether the two heads prove the log was forked: both are signed, have the same size,
+    /// and differ. Heads of different sizes are checked with a [`ConsistencyProof`] instead.
+    pub fn equivocates(&self, other: &SignedLogHead, verifying_key: &VerifyingKey) -> bool {
+        self.verify_signature(verifying_key)
+            && other.verify_signature(verifying_key)
+            && self.head.size == other.head.size
+            && self.head.head != other.head.head
+    }
+}
+
+/// Proof that the log at `to_size` extends the log at `from_size`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsistencyProof {
+    pub from_size: u64,
+    pub to_size: u64,
+    /// Digests of the snapshots appended in between, oldest first
+    pub digests: Vec<Hash>,
+}
+
+impl ConsistencyProof {
+    pub fn verify(&self, from: &LogHead, to: &LogHead) -> bool {
+        self.from_size == from.size
+            && self.to_size == to.size
+            && self.to_size.checked_sub(self.from_size) == Some(self.digests.len() as u64)
+            && self
+                .digests
+                .iter()
+                .fold(from.head, |head, digest| chain(&head, digest))
+                == to.head
+    }
+}
+
+/// Proof that a snapshot is an entry of the log at a given size
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionProof {
+    pub index: u64,
+    /// Head of the log before the entry was appended
+    pub previous_head: Hash,
+    /// Digests of the snapshots appended after it, oldest first
+    pub later: Vec<Hash>,
+}
+
+impl InclusionProof {
+    pub fn verify(&self, snapshot: &SignedSnapshot, head: &LogHead) -> bool {
+        self.index.checked_add(1 + self.later.len() as u64) == Some(head.size)
+            && self.later.iter().fold(
+                chain(&self.previous_head, &snapshot_digest(snapshot)),
+                |head, digest| chain(&head, digest),
+            ) == head.head
+    }
+}
@@ -0,0 +1,85 @@
+//! Claims of the validation tokens the community backend signs for the trusted entity.
+
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::aggregate::{MemberPeriod, PeriodTotals};
+
+/// Claims common to every validation token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredClaims {
+    /// Token ID, which the verifier consumes so the token can only be redeemed once
+    pub jti: Uuid,
+    /// Issuer
+    pub iss: String,
+    /// Audience, the verifier the token is meant for
+    pub aud: String,
+    /// Issued At Timestamp
+    pub iat: i64,
+    /// Expiration Timestamp
+    pub exp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationClaims {
+    /// User ID
+    pub uid: Uuid,
+    /// Energy Record ID
+    pub eri: Uuid,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+}
+
+/// Claims of a batch of energy records, validated with a single token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchValidationClaims {
+    /// User ID
+    pub uid: Uuid,
+    /// Energy Record IDs
+    pub eris: Vec<Uuid>,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+}
+
+/// Claims of a range statement: the totals of a user's records in a community starting within
+/// `[start, end)`, so a single proof covers a whole billing period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeValidationClaims {
+    /// User ID
+    pub uid: Uuid,
+    /// Community ID
+    pub cid: Uuid,
+    /// Period start (inclusive)
+    pub start: NaiveDateTime,
+    /// Period end (exclusive)
+    pub end: NaiveDateTime,
+    /// Number of records in the period
+    pub cnt: u64,
+    /// Total generated over the period
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub generated: BigDecimal,
+    /// Total consumed over the period
+    #[serde(with = "bigdecimal::serde::json_num")]
+    pub consumed: BigDecimal,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+}
+
+impl RangeValidationClaims {
+    /// The totals the backend signed, which a proof of the period must match
+    pub fn statement(&self) -> PeriodTotals {
+        PeriodTotals {
+            period: MemberPeriod {
+                user_id: self.uid,
+                community_id: self.cid,
+                start: self.start,
+                end: self.end,
+            },
+            record_count: self.cnt,
+            generated: self.generated.clone(),
+            consumed: self.consumed.clone(),
+        }
+    }
+}
@@ -0,0 +1,969 @@
+use std::fmt;
+
+use bigdecimal::BigDecimal;
+use bulletproofs::PedersenGens;
+use chrono::NaiveDateTime;
+use curve25519_dalek::{Scalar, ristretto::CompressedRistretto};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    EnergyRecord,
+    aggregate::{PeriodTotals, PeriodTotalsProof},
+    allocation::{
+        AggregatedAllocationProof, Allocation, AllocationProof, AttestedAllocationProof,
+        CommunityTotals, DistributionRule, MemberShareInputs, OpenedAllocationProof, ProofScheme,
+    },
+    entitlement::{
+        GenerationThresholdProof, RANGE_PROOF_BITS, generated_surplus_commitment,
+        range_proof_generators,
+    },
+    from_fixed_point,
+    merkle::{Hash, MerkleTree},
+    snapshot::{
+        MemberAggregate, RecordCommitment, RecordInclusionProof, RecordRangeProof, SignedSnapshot,
+        aggregate_day, aggregates_leaf, day_leaf, key_id, member_leaf_from_records, record_leaf,
+        records_in_period, sum_commitments,
+    },
+    to_fixed_point,
+};
+
+/// Outcome of checking a proof, with every reason it was rejected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verdict {
+    pub valid: bool,
+    pub failures: Vec<VerificationFailure>,
+}
+
+impl Verdict {
+    pub fn from_failures(failures: Vec<VerificationFailure>) -> Self {
+        Self {
+            valid: failures.is_empty(),
+            failures,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "camelCase")]
+pub enum VerificationFailure {
+    /// A record quantity is negative or has more than 4 decimal places
+    #[serde(rename_all = "camelCase")]
+    InvalidQuantities {
+        record_id: Uuid,
+    },
+    /// The Merkle paths don't have the shape their index and size require
+    MalformedPath,
+    /// The proof doesn't lead to the root of the snapshot it claims to be from
+    #[serde(rename_all = "camelCase")]
+    RootMismatch {
+        expected: Hash,
+        computed: Hash,
+    },
+    /// The proof is for a snapshot other than the one the caller trusts
+    #[serde(rename_all = "camelCase")]
+    SnapshotMismatch {
+        expected: Hash,
+        actual: Hash,
+    },
+    #[serde(rename_all = "camelCase")]
+    CommunityMismatch {
+        record_community_id: Uuid,
+        snapshot_community_id: Uuid,
+    },
+    #[serde(rename_all = "camelCase")]
+    UnknownSigningKey {
+        key_id: String,
+    },
+    InvalidSignature,
+    /// The snapshot the proof is against is of another community than the statement is about
+    #[serde(rename_all = "camelCase")]
+    StatementCommunityMismatch {
+        statement_community_id: Uuid,
+        snapshot_community_id: Uuid,
+    },
+    /// The statement threshold is negative or has more than 4 decimal places
+    InvalidThreshold,
+    /// The proven records are out of order or don't cover the whole period
+    IncompletePeriod,
+    /// A record commitment is not a valid point
+    InvalidCommitment,
+    /// The range proof doesn't hold for the committed total
+    InvalidRangeProof,
+    #[serde(rename_all = "camelCase")]
+    RecordCountMismatch {
+        claimed: u64,
+        proven: u64,
+    },
+    /// The claimed total doesn't open the sum of the record commitments
+    #[serde(rename_all = "camelCase")]
+    TotalMismatch {
+        quantity: Quantity,
+    },
+    /// The statement is about another rule than the one committed for the community
+    #[serde(rename_all = "camelCase")]
+    RuleMismatch {
+        claimed: DistributionRule,
+        committed: DistributionRule,
+    },
+    /// The proof is made with another scheme than the one committed for the community
+    #[serde(rename_all = "camelCase")]
+    SchemeMismatch {
+        claimed: ProofScheme,
+        committed: ProofScheme,
+    },
+    /// The verifier doesn't know how to check proofs of this scheme
+    #[serde(rename_all = "camelCase")]
+    UnsupportedScheme {
+        scheme: ProofScheme,
+    },
+    /// The attestation of the statement was not signed with the snapshot key
+    InvalidAttestation,
+    /// The member the statement is about is not in the snapshot
+    UnknownMember,
+    #[serde(rename_all = "camelCase")]
+    ActiveMembersMismatch {
+        claimed: u64,
+        proven: u64,
+    },
+    /// The claimed community total doesn't open the sum of every member's commitments
+    #[serde(rename_all = "camelCase")]
+    CommunityTotalMismatch {
+        quantity: Quantity,
+    },
+    /// The period is not a whole day, which members' totals are aggregated over
+    UnalignedPeriod,
+    /// The allocation is not the rule applied to the totals
+    #[serde(rename_all = "camelCase")]
+    AllocationMismatch {
+        #[serde(with = "bigdecimal::serde::json_num")]
+        expected: BigDecimal,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Quantity {
+    Generated,
+    Consumed,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Generated => write!(f, "generated"),
+            Self::Consumed => write!(f, "consumed"),
+        }
+    }
+}
+
+impl fmt::Display for VerificationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidQuantities { record_id } => {
+                write!(f, "record {record_id} has invalid quantities")
+            }
+            Self::MalformedPath => write!(f, "malformed Merkle path"),
+            Self::RootMismatch { expected, computed } => {
+                write!(f, "proof leads to root {computed}, expected {expected}")
+            }
+            Self::SnapshotMismatch { expected, actual } => {
+                write!(f, "proof is for snapshot {actual}, expected {expected}")
+            }
+            Self::CommunityMismatch {
+                record_community_id,
+                snapshot_community_id,
+            } => write!(
+                f,
+                "record belongs to community {record_community_id}, snapshot is of community {snapshot_community_id}"
+            ),
+            Self::UnknownSigningKey { key_id } => write!(f, "unknown signing key {key_id}"),
+            Self::InvalidSignature => write!(f, "invalid snapshot signature"),
+            Self::StatementCommunityMismatch {
+                statement_community_id,
+                snapshot_community_id,
+            } => write!(
+                f,
+                "statement is about community {statement_community_id}, snapshot is of community {snapshot_community_id}"
+            ),
+            Self::InvalidThreshold => write!(f, "invalid threshold"),
+            Self::IncompletePeriod => write!(f, "proven records don't cover the whole period"),
+            Self::InvalidCommitment => write!(f, "invalid record commitment"),
+            Self::InvalidRangeProof => write!(f, "invalid range proof"),
+            Self::RecordCountMismatch { claimed, proven } => {
+                write!(f, "claimed {claimed} records, proof has {proven}")
+            }
+            Self::TotalMismatch { quantity } => {
+                write!(f, "claimed {quantity} total doesn't match the records")
+            }
+            Self::RuleMismatch { claimed, committed } => write!(
+                f,
+                "statement is about rule {claimed:?}, community committed to {committed:?}"
+            ),
+            Self::SchemeMismatch { claimed, committed } => write!(
+                f,
+                "proof uses scheme {claimed:?}, community committed to {committed:?}"
+            ),
+            Self::UnsupportedScheme { scheme } => write!(f, "unsupported proof scheme {scheme:?}"),
+            Self::InvalidAttestation => write!(f, "invalid allocation attestation"),
+            Self::UnknownMember => write!(f, "member is not in the snapshot"),
+            Self::ActiveMembersMismatch { claimed, proven } => {
+                write!(f, "claimed {claimed} active members, proof has {proven}")
+            }
+            Self::CommunityTotalMismatch { quantity } => {
+                write!(
+                    f,
+                    "claimed community {quantity} total doesn't match the records"
+                )
+            }
+            Self::UnalignedPeriod => write!(f, "period is not a whole day"),
+            Self::AllocationMismatch { expected } => {
+                write!(f, "allocation doesn't follow the rule, expected {expected}")
+            }
+        }
+    }
+}
+
+/// Checks that `record` is included in the snapshot with root `snapshot_root`, and that the
+/// snapshot was signed by `verifying_key`
+pub fn verify_record_inclusion(
+    proof: &RecordInclusionProof,
+    record: &EnergyRecord,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    let mut failures = check_snapshot(&proof.snapshot, snapshot_root, verifying_key);
+    let header = &proof.snapshot.header;
+
+    if record.community_id != header.community_id {
+        failures.push(VerificationFailure::CommunityMismatch {
+            record_community_id: record.community_id,
+            snapshot_community_id: header.community_id,
+        });
+    }
+
+    if record_leaf(&proof.salt, record).is_none() {
+        failures.push(VerificationFailure::InvalidQuantities {
+            record_id: record.id,
+        });
+    } else {
+        match proof.compute_root(record) {
+            None => failures.push(VerificationFailure::MalformedPath),
+            Some(computed) if computed != header.root => {
+                failures.push(VerificationFailure::RootMismatch {
+                    expected: header.root,
+                    computed,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    Verdict::from_failures(failures)
+}
+
+/// Checks that the member generated at least the threshold over the period of the statement,
+/// according to the snapshot with root `snapshot_root` signed by `verifying_key`
+pub fn verify_generation_threshold(
+    proof: &GenerationThresholdProof,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    let statement = &proof.statement;
+    let mut failures = check_snapshot(&proof.records.snapshot, snapshot_root, verifying_key);
+
+    let Some(threshold) = to_fixed_point(&statement.threshold) else {
+        failures.push(VerificationFailure::InvalidThreshold);
+        return Verdict::from_failures(failures);
+    };
+
+    let Some(records) = check_member_records(
+        &proof.records,
+        statement.user_id,
+        statement.community_id,
+        (statement.start, statement.end),
+        &mut failures,
+    ) else {
+        return Verdict::from_failures(failures);
+    };
+
+    let Some(surplus) = generated_surplus_commitment(records, threshold) else {
+        failures.push(VerificationFailure::InvalidCommitment);
+        return Verdict::from_failures(failures);
+    };
+
+    let (bp_gens, pc_gens) = range_proof_generators();
+    let mut transcript = statement
+        .transcript(&proof.records.snapshot.header.root)
+        .expect("threshold was checked above");
+
+    if proof
+        .range_proof
+        .verify_single(
+            &bp_gens,
+            &pc_gens,
+            &mut transcript,
+            &surplus.compress(),
+            RANGE_PROOF_BITS,
+        )
+        .is_err()
+    {
+        failures.push(VerificationFailure::InvalidRangeProof);
+    }
+
+    Verdict::from_failures(failures)
+}
+
+/// Checks that a member's records over a period add up to the claimed totals, according to the
+/// snapshot with root `snapshot_root` signed by `verifying_key`
+pub fn verify_period_totals(
+    proof: &PeriodTotalsProof,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    let statement = &proof.statement;
+    let period = &statement.period;
+    let mut failures = check_snapshot(&proof.records.snapshot, snapshot_root, verifying_key);
+
+    let Some(records) = check_member_records(
+        &proof.records,
+        period.user_id,
+        period.community_id,
+        (period.start, period.end),
+        &mut failures,
+    ) else {
+        return Verdict::from_failures(failures);
+    };
+
+    check_member_totals(
+        records,
+        statement,
+        &proof.generated_blinding,
+        &proof.consumed_blinding,
+        &mut failures,
+    );
+
+    Verdict::from_failures(failures)
+}
+
+/// Checks that a member's allocation over a period is the community's committed rule applied to
+/// the community totals, according to the snapshot with root `snapshot_root` signed by
+/// `verifying_key`. Dispatches on the scheme the proof was made with.
+pub fn verify_allocation(
+    proof: &AllocationProof,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    match proof {
+        AllocationProof::CommitmentOpening(proof) => {
+            verify_opened_allocation(proof, snapshot_root, verifying_key)
+        }
+        AllocationProof::Attestation(proof) => {
+            verify_attested_allocation(proof, snapshot_root, verifying_key)
+        }
+        AllocationProof::AggregateCommitment(proof) => {
+            verify_aggregated_allocation(proof, snapshot_root, verifying_key)
+        }
+    }
+}
+
+/// Checks what every allocation proof has to hold, whatever its scheme
+fn check_allocation_statement(
+    statement: &Allocation,
+    scheme: ProofScheme,
+    snapshot: &SignedSnapshot,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Vec<VerificationFailure> {
+    let period = &statement.member.period;
+    let header = &snapshot.header;
+    let mut failures = check_snapshot(snapshot, snapshot_root, verifying_key);
+
+    if period.community_id != header.community_id {
+        failures.push(VerificationFailure::StatementCommunityMismatch {
+            statement_community_id: period.community_id,
+            snapshot_community_id: header.community_id,
+        });
+    }
+
+    if statement.rule != header.distribution_rule {
+        failures.push(VerificationFailure::RuleMismatch {
+            claimed: statement.rule,
+            committed: header.distribution_rule,
+        });
+    }
+
+    if scheme != header.proof_scheme {
+        failures.push(VerificationFailure::SchemeMismatch {
+            claimed: scheme,
+            committed: header.proof_scheme,
+        });
+    }
+
+    failures
+}
+
+/// Checks an allocation proof made under [`ProofScheme::Attestation`]
+pub fn verify_attested_allocation(
+    proof: &AttestedAllocationProof,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    let mut failures = check_allocation_statement(
+        &proof.statement,
+        ProofScheme::Attestation,
+        &proof.snapshot,
+        snapshot_root,
+        verifying_key,
+    );
+
+    // The snapshot check already reported a key other than the snapshot key
+    if proof.snapshot.key_id == key_id(verifying_key) && !proof.verify_signature(verifying_key) {
+        failures.push(VerificationFailure::InvalidAttestation);
+    }
+
+    Verdict::from_failures(failures)
+}
+
+/// Checks an allocation proof made under [`ProofScheme::CommitmentOpening`]. The proof shows the
+/// verifier every member's user ID and record times, see [`OpenedAllocationProof`].
+pub fn verify_opened_allocation(
+    proof: &OpenedAllocationProof,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    let statement = &proof.statement;
+    let period = &statement.member.period;
+    let header = &proof.snapshot.header;
+    let mut failures = check_allocation_statement(
+        statement,
+        ProofScheme::CommitmentOpening,
+        &proof.snapshot,
+        snapshot_root,
+        verifying_key,
+    );
+
+    // Rebuilding the root from every member shows none was left out
+    let mut member_leaves = Vec::with_capacity(proof.members.len());
+    let mut member_records = Vec::with_capacity(proof.members.len());
+    for member in &proof.members {
+        let Some(leaf) = member_leaf_from_records(
+            member.user_id,
+            header.community_id,
+            &member.records,
+            &member.record_path,
+        ) else {
+            failures.push(VerificationFailure::MalformedPath);
+            return Verdict::from_failures(failures);
+        };
+        let Some(records) = records_in_period(
+            &member.records,
+            &member.record_path,
+            period.start,
+            period.end,
+        ) else {
+            failures.push(VerificationFailure::IncompletePeriod);
+            return Verdict::from_failures(failures);
+        };
+
+        member_leaves.push(leaf);
+        member_records.push((member.user_id, records));
+    }
+
+    let computed = MerkleTree::new(member_leaves).root();
+    if computed != header.root {
+        failures.push(VerificationFailure::RootMismatch {
+            expected: header.root,
+            computed,
+        });
+    }
+
+    let Some(&(_, records)) = member_records
+        .iter()
+        .find(|(user_id, _)| *user_id == period.user_id)
+    else {
+        failures.push(VerificationFailure::UnknownMember);
+        return Verdict::from_failures(failures);
+    };
+
+    check_member_totals(
+        records,
+        &statement.member,
+        &proof.member_generated_blinding,
+        &proof.member_consumed_blinding,
+        &mut failures,
+    );
+
+    let active_members = member_records
+        .iter()
+        .filter(|(_, records)| !records.is_empty())
+        .count() as u64;
+    if active_members != statement.active_members {
+        failures.push(VerificationFailure::ActiveMembersMismatch {
+            claimed: statement.active_members,
+            proven: active_members,
+        });
+    }
+
+    let community_records = || {
+        member_records
+            .iter()
+            .flat_map(|(_, records)| records.iter())
+    };
+    check_community_totals(
+        statement,
+        community_records()
+            .map(|record| &record.generated)
+            .collect(),
+        community_records().map(|record| &record.consumed).collect(),
+        (
+            &proof.community_generated_blinding,
+            &proof.community_consumed_blinding,
+        ),
+        &mut failures,
+    );
+    check_allocated(
+        statement,
+        header.distribution_rule,
+        active_members,
+        records.len() as u64,
+        &mut failures,
+    );
+
+    Verdict::from_failures(failures)
+}
+
+/// Checks an allocation proof made under [`ProofScheme::AggregateCommitment`]. Only the member's
+/// own records and aggregate are opened, see [`AggregatedAllocationProof`].
+pub fn verify_aggregated_allocation(
+    proof: &AggregatedAllocationProof,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Verdict {
+    let statement = &proof.statement;
+    let period = &statement.member.period;
+    let header = &proof.records.snapshot.header;
+    let mut failures = check_allocation_statement(
+        statement,
+        ProofScheme::AggregateCommitment,
+        &proof.records.snapshot,
+        snapshot_root,
+        verifying_key,
+    );
+
+    let Some(day) = aggregate_day(period.start, period.end) else {
+        failures.push(VerificationFailure::UnalignedPeriod);
+        return Verdict::from_failures(failures);
+    };
+
+    let Some(records) = check_member_records(
+        &proof.records,
+        period.user_id,
+        period.community_id,
+        (period.start, period.end),
+        &mut failures,
+    ) else {
+        return Verdict::from_failures(failures);
+    };
+    check_member_totals(
+        records,
+        &statement.member,
+        &proof.member_generated_blinding,
+        &proof.member_consumed_blinding,
+        &mut failures,
+    );
+
+    // Rebuilding the day tree from every aggregate shows none was left out. There is a single
+    // aggregates leaf, the last of the member tree.
+    let day_tree = MerkleTree::new(proof.aggregates.iter().map(MemberAggregate::leaf).collect());
+    let computed = proof
+        .day_path
+        .root_from_leaf(day_leaf(
+            header.community_id,
+            day,
+            &day_tree.root(),
+            day_tree.len(),
+        ))
+        .and_then(|days_root| {
+            proof
+                .aggregates_path
+                .root_from_leaf(aggregates_leaf(&days_root))
+        });
+    match computed {
+        Some(_) if proof.aggregates_path.index + 1 != proof.aggregates_path.leaf_count => {
+            failures.push(VerificationFailure::MalformedPath)
+        }
+        None => failures.push(VerificationFailure::MalformedPath),
+        Some(computed) if computed != header.root => {
+            failures.push(VerificationFailure::RootMismatch {
+                expected: header.root,
+                computed,
+            })
+        }
+        Some(_) => {}
+    }
+
+    // The member's own aggregate opens to the totals of their records
+    match (&proof.member_aggregate, records.is_empty()) {
+        (Some(opening), _) => match proof.aggregates.get(opening.index) {
+            Some(aggregate) => {
+                for (quantity, total, blinding, commitment) in [
+                    (
+                        Quantity::Generated,
+                        &statement.member.generated,
+                        &opening.generated_blinding,
+                        &aggregate.generated,
+                    ),
+                    (
+                        Quantity::Consumed,
+                        &statement.member.consumed,
+                        &opening.consumed_blinding,
+                        &aggregate.consumed,
+                    ),
+                ] {
+                    match opens(vec![commitment], total, blinding) {
+                        None => failures.push(VerificationFailure::InvalidCommitment),
+                        Some(false) => {
+                            failures.push(VerificationFailure::TotalMismatch { quantity })
+                        }
+                        Some(true) => {}
+                    }
+                }
+            }
+            None => failures.push(VerificationFailure::UnknownMember),
+        },
+        // Only members without records that day have no aggregate
+        (None, false) => failures.push(VerificationFailure::UnknownMember),
+        (None, true) => {}
+    }
+
+    let active_members = proof.aggregates.len() as u64;
+    if active_members != statement.active_members {
+        failures.push(VerificationFailure::ActiveMembersMismatch {
+            claimed: statement.active_members,
+            proven: active_members,
+        });
+    }
+
+    check_community_totals(
+        statement,
+        proof
+            .aggregates
+            .iter()
+            .map(|aggregate| &aggregate.generated)
+            .collect(),
+        proof
+            .aggregates
+            .iter()
+            .map(|aggregate| &aggregate.consumed)
+            .collect(),
+        (
+            &proof.community_generated_blinding,
+            &proof.community_consumed_blinding,
+        ),
+        &mut failures,
+    );
+    check_allocated(
+        statement,
+        header.distribution_rule,
+        active_members,
+        records.len() as u64,
+        &mut failures,
+    );
+
+    Verdict::from_failures(failures)
+}
+
+/// Checks that the community totals open the sums of `generated` and `consumed`
+fn check_community_totals(
+    statement: &Allocation,
+    generated: Vec<&CompressedRistretto>,
+    consumed: Vec<&CompressedRistretto>,
+    (generated_blinding, consumed_blinding): (&Scalar, &Scalar),
+    failures: &mut Vec<VerificationFailure>,
+) {
+    for (quantity, total, blinding, commitments) in [
+        (
+            Quantity::Generated,
+            &statement.community_generated,
+            generated_blinding,
+            generated,
+        ),
+        (
+            Quantity::Consumed,
+            &statement.community_consumed,
+            consumed_blinding,
+            consumed,
+        ),
+    ] {
+        match opens(commitments, total, blinding) {
+            None => failures.push(VerificationFailure::InvalidCommitment),
+            Some(false) => failures.push(VerificationFailure::CommunityTotalMismatch { quantity }),
+            Some(true) => {}
+        }
+    }
+}
+
+/// Checks that the allocation is `rule` applied to the totals of the statement
+fn check_allocated(
+    statement: &Allocation,
+    rule: DistributionRule,
+    active_members: u64,
+    record_count: u64,
+    failures: &mut Vec<VerificationFailure>,
+) {
+    // The totals were checked already, so a value that can't be represented already failed
+    if let (Some(generated), Some(consumed), Some(member_consumed)) = (
+        to_fixed_point(&statement.community_generated),
+        to_fixed_point(&statement.community_consumed),
+        to_fixed_point(&statement.member.consumed),
+    ) {
+        let community = CommunityTotals {
+            generated,
+            consumed,
+            active_members,
+        };
+        let member = MemberShareInputs {
+            record_count,
+            consumed: member_consumed,
+        };
+        let expected = rule.allocate(&community, &member);
+
+        if to_fixed_point(&statement.allocated) != Some(expected) {
+            failures.push(VerificationFailure::AllocationMismatch {
+                expected: from_fixed_point(expected),
+            });
+        }
+    }
+}
+
+/// Checks that a member's records add up to the claimed totals
+fn check_member_totals(
+    records: &[RecordCommitment],
+    statement: &PeriodTotals,
+    generated_blinding: &Scalar,
+    consumed_blinding: &Scalar,
+    failures: &mut Vec<VerificationFailure>,
+) {
+    if statement.record_count != records.len() as u64 {
+        failures.push(VerificationFailure::RecordCountMismatch {
+            claimed: statement.record_count,
+            proven: records.len() as u64,
+        });
+    }
+
+    for (quantity, total, blinding, commitments) in [
+        (
+            Quantity::Generated,
+            &statement.generated,
+            generated_blinding,
+            records
+                .iter()
+                .map(|record| &record.generated)
+                .collect::<Vec<_>>(),
+        ),
+        (
+            Quantity::Consumed,
+            &statement.consumed,
+            consumed_blinding,
+            records.iter().map(|record| &record.consumed).collect(),
+        ),
+    ] {
+        match opens(commitments, total, blinding) {
+            None => failures.push(VerificationFailure::InvalidCommitment),
+            Some(false) => failures.push(VerificationFailure::TotalMismatch { quantity }),
+            Some(true) => {}
+        }
+    }
+}
+
+/// Whether `total` and `blinding` open the sum of `commitments`. Returns `None` if a commitment is
+/// not a valid point.
+fn opens(
+    commitments: Vec<&CompressedRistretto>,
+    total: &BigDecimal,
+    blinding: &Scalar,
+) -> Option<bool> {
+    let sum = sum_commitments(commitments)?;
+    Some(
+        to_fixed_point(total).is_some_and(|total| {
+            PedersenGens::default().commit(Scalar::from(total), *blinding) == sum
+        }),
+    )
+}
+
+/// Checks that `proof` proves the records of a member in the snapshot, and returns the ones
+/// within `period`. Returns `None` if there's no point checking anything about the records.
+fn check_member_records<'a>(
+    proof: &'a RecordRangeProof,
+    user_id: Uuid,
+    community_id: Uuid,
+    (start, end): (NaiveDateTime, NaiveDateTime),
+    failures: &mut Vec<VerificationFailure>,
+) -> Option<&'a [RecordCommitment]> {
+    let header = &proof.snapshot.header;
+
+    if community_id != header.community_id {
+        failures.push(VerificationFailure::StatementCommunityMismatch {
+            statement_community_id: community_id,
+            snapshot_community_id: header.community_id,
+        });
+    }
+
+    match proof.compute_root(user_id) {
+        None => failures.push(VerificationFailure::MalformedPath),
+        Some(computed) if computed != header.root => {
+            failures.push(VerificationFailure::RootMismatch {
+                expected: header.root,
+                computed,
+            })
+        }
+        Some(_) => {}
+    }
+
+    let records = proof.records_in_period(start, end);
+    if records.is_none() {
+        failures.push(VerificationFailure::IncompletePeriod);
+    }
+    records
+}
+
+/// Checks the snapshot signature and that the snapshot is the one the caller trusts
+fn check_snapshot(
+    snapshot: &SignedSnapshot,
+    snapshot_root: &Hash,
+    verifying_key: &VerifyingKey,
+) -> Vec<VerificationFailure> {
+    let mut failures = Vec::new();
+
+    if snapshot.key_id != key_id(verifying_key) {
+        failures.push(VerificationFailure::UnknownSigningKey {
+            key_id: snapshot.key_id.clone(),
+        });
+    } else if !snapshot.verify_signature(verifying_key) {
+        failures.push(VerificationFailure::InvalidSignature);
+    }
+
+    if snapshot.header.root != *snapshot_root {
+        failures.push(VerificationFailure::SnapshotMismatch {
+            expected: *snapshot_root,
+            actual: snapshot.header.root,
+        });
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDateTime, Utc};
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+    use crate::{
+        merkle::MerkleTree,
+        snapshot::{SignedSnapshot, SnapshotHeader, member_leaf},
+    };
+
+    fn record(user_id: Uuid, community_id: Uuid) -> EnergyRecord {
+        let mut record = EnergyRecord::random(user_id, community_id, NaiveDateTime::default());
+        record.generated = record.generated.with_scale(4);
+        record.consumed = record.consumed.with_scale(4);
+        record.consumer_price = record.consumer_price.with_scale(4);
+        record.seller_price = record.seller_price.with_scale(4);
+        record
+    }
+
+    /// Single member snapshot containing `records`, proving the first one
+    fn prove_first(records: &[EnergyRecord], signing_key: &SigningKey) -> RecordInclusionProof {
+        let salt = Hash::digest(b"salt");
+        let record_tree = MerkleTree::new(
+            records
+                .iter()
+                .map(|record| record_leaf(&salt, record).unwrap())
+                .collect(),
+        );
+        let member_tree = MerkleTree::new(vec![member_leaf(
+            records[0].user_id,
+            &record_tree.root(),
+            record_tree.len(),
+        )]);
+
+        let header = SnapshotHeader {
+            community_id: records[0].community_id,
+            sequence: 1,
+            taken_at: Utc::now(),
+            record_count: records.len() as u64,
+            distribution_rule: DistributionRule::default(),
+            proof_scheme: ProofScheme::default(),
+            root: member_tree.root(),
+        };
+
+        RecordInclusionProof {
+            snapshot: SignedSnapshot::sign(header, signing_key),
+            salt,
+            record_path: record_tree.proof(0).unwrap(),
+            member_path: member_tree.proof(0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn valid_proof_is_accepted() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let (user_id, community_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let records = [record(user_id, community_id), record(user_id, community_id)];
+        let proof = prove_first(&records, &signing_key);
+
+        let verdict = verify_record_inclusion(
+            &proof,
+            &records[0],
+            &proof.snapshot.header.root,
+            &signing_key.verifying_key(),
+        );
+
+        assert!(verdict.valid, "{verdict:?}");
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let other_key = SigningKey::from_bytes(&[2; 32]);
+        let (user_id, community_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let records = [record(user_id, community_id), record(user_id, community_id)];
+        let proof = prove_first(&records, &signing_key);
+        let root = proof.snapshot.header.root;
+
+        let verdict =
+            verify_record_inclusion(&proof, &records[1], &root, &signing_key.verifying_key());
+        assert!(matches!(
+            verdict.failures[..],
+            [VerificationFailure::RootMismatch { .. }]
+        ));
+
+        let verdict = verify_record_inclusion(
+            &proof,
+            &records[0],
+            &Hash::digest(b"other"),
+            &signing_key.verifying_key(),
+        );
+        assert!(matches!(
+            verdict.failures[..],
+            [VerificationFailure::SnapshotMismatch { .. }]
+        ));
+
+        let verdict =
+            verify_record_inclusion(&proof, &records[0], &root, &other_key.verifying_key());
+        assert!(matches!(
+            verdict.failures[..],
+            [VerificationFailure::UnknownSigningKey { .. }]
+        ));
+
+        let mut forged = proof.clone();
+        forged.snapshot.header.sequence = 2;
+        let verdict =
+            verify_record_inclusion(&forged, &records[0], &root, &signing_key.verifying_key());
+        assert_eq!(verdict.failures, [VerificationFailure::InvalidSignature]);
+    }
+}
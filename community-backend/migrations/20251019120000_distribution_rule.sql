@@ -0,0 +1,7 @@
+CREATE TYPE distribution_rule AS ENUM (
+    'equal',
+    'proportional_to_consumption'
+);
+
+ALTER TABLE "community"
+    ADD COLUMN "distribution_rule" distribution_rule NOT NULL DEFAULT 'proportional_to_consumption';
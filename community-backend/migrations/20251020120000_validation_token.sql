@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS "validation_token" (
+    "id" UUID NOT NULL,
+    "user_id" UUID NOT NULL,
+    "issued_at" TIMESTAMPTZ NOT NULL,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    "consumed_at" TIMESTAMPTZ,
+    PRIMARY KEY ("id"),
+    CONSTRAINT fk_validation_token_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE
+);
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS "validation_request" (
+    "id" UUID NOT NULL DEFAULT gen_random_uuid(),
+    "user_id" UUID NOT NULL,
+    "energy_record_id" UUID NOT NULL,
+    "token_id" UUID NOT NULL,
+    "requested_at" TIMESTAMPTZ NOT NULL,
+    "client_ip" INET,
+    PRIMARY KEY ("id"),
+    CONSTRAINT fk_validation_request_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE,
+    CONSTRAINT fk_validation_request_energy_record
+        FOREIGN KEY ("energy_record_id")
+        REFERENCES "energy_record"("id")
+        ON DELETE CASCADE,
+    CONSTRAINT fk_validation_request_token
+        FOREIGN KEY ("token_id")
+        REFERENCES "validation_token"("id")
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "validation_request_user_idx" ON "validation_request" ("user_id", "requested_at");
+CREATE INDEX IF NOT EXISTS "validation_request_energy_record_idx" ON "validation_request" ("energy_record_id");
@@ -0,0 +1,8 @@
+ALTER TABLE "energy_record"
+    ADD COLUMN "verifiable_since" TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS "snapshot_commit" (
+    "committed_at" TIMESTAMPTZ NOT NULL,
+    "next_commit_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("committed_at")
+);
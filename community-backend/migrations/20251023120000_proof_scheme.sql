@@ -0,0 +1,7 @@
+CREATE TYPE proof_scheme AS ENUM (
+    'commitment_opening',
+    'attestation'
+);
+
+ALTER TABLE "community"
+    ADD COLUMN "proof_scheme" proof_scheme NOT NULL DEFAULT 'commitment_opening';
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS "oauth_state" (
+    "state" VARCHAR(255) NOT NULL,
+    "provider" auth_provider NOT NULL,
+    "pkce_verifier" VARCHAR(255) NOT NULL,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("state")
+);
@@ -0,0 +1,5 @@
+ALTER TYPE auth_provider ADD VALUE IF NOT EXISTS 'oidc';
+
+ALTER TABLE "oauth_state"
+    ADD COLUMN "oidc_provider" VARCHAR(255),
+    ADD COLUMN "nonce" VARCHAR(255);
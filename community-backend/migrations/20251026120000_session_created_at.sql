@@ -0,0 +1,2 @@
+ALTER TABLE "session"
+    ADD COLUMN "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,6 @@
+ALTER TABLE "session"
+    ADD COLUMN "last_seen_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN "user_agent" VARCHAR(512),
+    ADD COLUMN "client_ip" INET;
+
+CREATE INDEX IF NOT EXISTS "session_user_idx" ON "session" ("user_id");
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS "password_reset_token" (
+    "token_hash" CHAR(64) NOT NULL,
+    "user_id" UUID NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("token_hash"),
+    CONSTRAINT fk_password_reset_token_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "password_reset_token_user_idx" ON "password_reset_token" ("user_id");
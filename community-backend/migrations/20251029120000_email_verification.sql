@@ -0,0 +1,22 @@
+ALTER TABLE "user" ADD COLUMN "email_verified_at" TIMESTAMPTZ;
+
+-- Providers only hand out verified emails, so users who signed in with one own theirs
+UPDATE "user" SET "email_verified_at" = NOW()
+WHERE EXISTS (
+    SELECT 1 FROM "key"
+    WHERE "key"."user_id" = "user"."id" AND "key"."provider" <> 'email'
+);
+
+CREATE TABLE IF NOT EXISTS "email_verification_token" (
+    "token_hash" CHAR(64) NOT NULL,
+    "user_id" UUID NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("token_hash"),
+    CONSTRAINT fk_email_verification_token_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "email_verification_token_user_idx" ON "email_verification_token" ("user_id");
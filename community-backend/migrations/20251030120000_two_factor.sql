@@ -0,0 +1,44 @@
+-- TOTP secrets stay pending until the user proves their authenticator works
+CREATE TABLE IF NOT EXISTS "user_totp" (
+    "user_id" UUID NOT NULL,
+    "secret" BYTEA NOT NULL,
+    "enabled_at" TIMESTAMPTZ,
+    "last_used_step" BIGINT,
+    PRIMARY KEY ("user_id"),
+    CONSTRAINT fk_user_totp_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "totp_recovery_code" (
+    "user_id" UUID NOT NULL,
+    "code_hash" CHAR(64) NOT NULL,
+    PRIMARY KEY ("user_id", "code_hash"),
+    CONSTRAINT fk_totp_recovery_code_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE
+);
+
+-- Logins whose password was checked, waiting for the second factor
+CREATE TABLE IF NOT EXISTS "login_challenge" (
+    "id" UUID NOT NULL,
+    "user_id" UUID NOT NULL,
+    "failed_attempts" INTEGER NOT NULL DEFAULT 0,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("id"),
+    CONSTRAINT fk_login_challenge_user
+        FOREIGN KEY ("user_id")
+        REFERENCES "user"("id")
+        ON DELETE CASCADE
+);
+
+-- Single row of settings admins can change
+CREATE TABLE IF NOT EXISTS "auth_policy" (
+    "id" BOOLEAN NOT NULL DEFAULT TRUE CHECK ("id"),
+    "require_staff_two_factor" BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY ("id")
+);
+
+INSERT INTO "auth_policy" DEFAULT VALUES ON CONFLICT DO NOTHING;
@@ -0,0 +1,7 @@
+-- Wrong second factor codes are counted per user rather than per login challenge, since a new
+-- challenge only takes the password
+ALTER TABLE "user_totp"
+    ADD COLUMN IF NOT EXISTS "failed_attempts" INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS "locked_until" TIMESTAMPTZ;
+
+ALTER TABLE "login_challenge" DROP COLUMN IF EXISTS "failed_attempts";
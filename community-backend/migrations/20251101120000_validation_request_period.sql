@@ -0,0 +1,18 @@
+-- Range tokens are requested for the totals of a period instead of energy records
+ALTER TABLE "validation_request"
+    ALTER COLUMN "energy_record_id" DROP NOT NULL,
+    ADD COLUMN "community_id" UUID,
+    ADD COLUMN "period_start" TIMESTAMP,
+    ADD COLUMN "period_end" TIMESTAMP,
+    ADD CONSTRAINT fk_validation_request_community
+        FOREIGN KEY ("community_id")
+        REFERENCES "community"("id")
+        ON DELETE CASCADE,
+    ADD CONSTRAINT validation_request_subject CHECK (
+        ("energy_record_id" IS NOT NULL AND "community_id" IS NULL
+            AND "period_start" IS NULL AND "period_end" IS NULL)
+        OR ("energy_record_id" IS NULL AND "community_id" IS NOT NULL
+            AND "period_start" IS NOT NULL AND "period_end" IS NOT NULL)
+    );
+
+CREATE INDEX IF NOT EXISTS "validation_request_community_idx" ON "validation_request" ("community_id");
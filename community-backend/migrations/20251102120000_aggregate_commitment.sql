@@ -0,0 +1 @@
+ALTER TYPE proof_scheme ADD VALUE 'aggregate_commitment';
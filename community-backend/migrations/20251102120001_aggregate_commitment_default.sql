@@ -0,0 +1,3 @@
+-- Opened commitments disclose every member of the community, so new communities hide them
+ALTER TABLE "community"
+    ALTER COLUMN "proof_scheme" SET DEFAULT 'aggregate_commitment';
@@ -0,0 +1,60 @@
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{hash_token, password_reset_store::new_token},
+    error::AppResult,
+};
+
+/// How long the link to verify an email stays valid after it was sent
+pub const EMAIL_VERIFICATION_TTL: chrono::Duration = chrono::Duration::hours(24);
+
+impl AppState {
+    /// Issues a token to verify the user's email, replacing any earlier one, and returns it
+    pub async fn create_email_verification_token(&self, user_id: Uuid) -> AppResult<String> {
+        let token = new_token();
+
+        // Also drop expired tokens of other users while at it
+        sqlx::query!(
+            r#"
+            DELETE FROM email_verification_token
+            WHERE user_id = $1 OR expires_at <= NOW()
+            "#,
+            user_id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO email_verification_token (token_hash, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            hash_token(&token),
+            user_id,
+            chrono::Utc::now() + EMAIL_VERIFICATION_TTL
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Removes the token so it can only be used once, returning its user if it has not expired
+    pub async fn consume_email_verification_token(&self, token: &str) -> AppResult<Option<Uuid>> {
+        let verification = sqlx::query!(
+            r#"
+            DELETE FROM email_verification_token
+            WHERE token_hash = $1
+            RETURNING user_id, expires_at
+            "#,
+            hash_token(token)
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+
+        Ok(verification
+            .filter(|verification| verification.expires_at > chrono::Utc::now())
+            .map(|verification| verification.user_id))
+    }
+}
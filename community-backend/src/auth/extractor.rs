@@ -1,7 +1,22 @@
-use axum::{extract::FromRequestParts, http::request::Parts};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
 use uuid::Uuid;
 
-use crate::{AppState, auth::Session, error::AppError};
+use crate::{
+    AppState,
+    auth::{Session, SessionDevice},
+    error::AppError,
+};
+
+/// Longest user agent kept with a session
+const MAX_USER_AGENT_LENGTH: usize = 512;
 
 pub struct ExtractSession(pub Session);
 
@@ -28,3 +43,60 @@ impl FromRequestParts<AppState> for ExtractSession {
         Ok(ExtractSession(session))
     }
 }
+
+/// IP address of the client, if known.
+///
+/// Behind the frontend proxy the peer is the proxy itself, so when `trust_forwarded_for` is set the
+/// address the proxy appended last to `X-Forwarded-For` is taken instead.
+pub struct ExtractClientIp(pub Option<IpAddr>);
+
+impl FromRequestParts<AppState> for ExtractClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded_for = parts
+            .headers
+            .get("X-Forwarded-For")
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        if state.trust_forwarded_for
+            && let Some(ip) = forwarded_for
+        {
+            return Ok(ExtractClientIp(Some(ip)));
+        }
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+        Ok(ExtractClientIp(peer))
+    }
+}
+
+/// User agent and IP address of the client, to record with the sessions it starts
+pub struct ExtractSessionDevice(pub SessionDevice);
+
+impl FromRequestParts<AppState> for ExtractSessionDevice {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ExtractClientIp(client_ip) = ExtractClientIp::from_request_parts(parts, state).await?;
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|header| header.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        Ok(ExtractSessionDevice(SessionDevice {
+            user_agent,
+            client_ip,
+        }))
+    }
+}
@@ -1,28 +1,72 @@
 use serde::{Deserialize, Serialize};
 
+pub mod email_verification_store;
 pub mod extractor;
 pub mod key_store;
 pub mod oauth;
+pub mod oauth_state_store;
+pub mod oidc;
 pub mod password;
+pub mod password_reset_store;
 pub mod router;
 pub mod session_store;
+pub mod totp;
+pub mod two_factor_store;
 
 #[derive(Serialize, Deserialize)]
 pub struct Session {
     pub id: uuid::Uuid,
     pub user_id: uuid::Uuid,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
     pub expiration: chrono::DateTime<chrono::Utc>,
+    pub user_agent: Option<String>,
+    pub client_ip: Option<String>,
 }
 
 impl Session {
-    pub fn new_random_from(user_id: uuid::Uuid) -> Self {
+    pub fn new_random_from(
+        user_id: uuid::Uuid,
+        policy: &SessionPolicy,
+        device: SessionDevice,
+    ) -> Self {
         let id = uuid::Uuid::new_v4();
-        // TODO: make this configurable ?
-        let expiration = chrono::Utc::now() + chrono::Duration::hours(24);
+        let created_at = chrono::Utc::now();
         Self {
             id,
             user_id,
-            expiration,
+            created_at,
+            last_seen_at: created_at,
+            expiration: created_at + policy.lifetime.min(policy.max_lifetime),
+            user_agent: device.user_agent,
+            client_ip: device.client_ip.map(|ip| ip.to_string()),
         }
     }
+
+    /// When the session ends even if it is used until then
+    pub fn max_expiration(&self, policy: &SessionPolicy) -> chrono::DateTime<chrono::Utc> {
+        self.created_at + policy.max_lifetime
+    }
+}
+
+/// What a session was started from, so users can tell their sessions apart
+#[derive(Debug, Clone, Default)]
+pub struct SessionDevice {
+    pub user_agent: Option<String>,
+    pub client_ip: Option<std::net::IpAddr>,
+}
+
+/// How long sessions last. Each request extends a session by `lifetime`, so only idle sessions
+/// expire, but none outlives `max_lifetime` after its login.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionPolicy {
+    pub lifetime: chrono::Duration,
+    pub max_lifetime: chrono::Duration,
+}
+
+/// Only the hashes of emailed tokens are stored, so a leaked table can't be used in their place
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
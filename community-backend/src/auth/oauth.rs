@@ -1,84 +1,134 @@
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope, TokenUrl,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenUrl,
     basic::{BasicClient, BasicTokenResponse},
     reqwest::async_http_client,
 };
+use reqwest::header::{ACCEPT, USER_AGENT};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 #[derive(Clone)]
-pub struct GoogleOAuthClient {
+pub struct GitHubOAuthClient {
     client: BasicClient,
+    api_url: Url,
+    http: reqwest::Client,
 }
 
-impl GoogleOAuthClient {
+impl GitHubOAuthClient {
+    /// `oauth_url` is where GitHub's authorization and token endpoints live, and `api_url` the
+    /// base of its REST API. Both can point at a mock provider in tests.
     pub fn new(
         client_id: String,
         client_secret: String,
         redirect_url: String,
+        oauth_url: &Url,
+        api_url: Url,
     ) -> Result<Self, oauth2::url::ParseError> {
-        let google_client_id = ClientId::new(client_id);
-        let google_client_secret = ClientSecret::new(client_secret);
-        let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".to_string())
-            .expect("Invalid authorization endpoint URL");
-        let token_url = TokenUrl::new("https://www.googleapis.com/oauth2/v3/token".to_string())
-            .expect("Invalid token endpoint URL");
+        let auth_url = AuthUrl::from_url(oauth_url.join("login/oauth/authorize")?);
+        let token_url = TokenUrl::from_url(oauth_url.join("login/oauth/access_token")?);
 
         let client = BasicClient::new(
-            google_client_id,
-            Some(google_client_secret),
+            ClientId::new(client_id),
+            Some(ClientSecret::new(client_secret)),
             auth_url,
             Some(token_url),
         )
         .set_redirect_uri(RedirectUrl::new(redirect_url)?);
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            api_url,
+            http: reqwest::Client::new(),
+        })
     }
 
-    pub fn get_authorization_url(&self) -> (Url, CsrfToken) {
-        self.client
+    /// Returns the URL to send the user to, along with the CSRF state and the PKCE verifier the
+    /// callback has to be checked against
+    pub fn get_authorization_url(&self) -> (Url, CsrfToken, PkceCodeVerifier) {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf_token) = self
+            .client
             .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("openid".to_string()))
-            .add_scope(Scope::new("email".to_string()))
-            .add_scope(Scope::new("profile".to_string()))
-            .url()
+            .add_scope(Scope::new("read:user".to_string()))
+            .add_scope(Scope::new("user:email".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        (url, csrf_token, pkce_verifier)
     }
 
-    pub async fn exchange_code(&self, code: String) -> Result<BasicTokenResponse, String> {
+    pub async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<BasicTokenResponse, String> {
         self.client
             .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
             .request_async(async_http_client)
             .await
             .map_err(|e| format!("Failed to exchange code: {}", e))
     }
+
+    async fn get_api<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        access_token: &str,
+    ) -> Result<T, String> {
+        let url = self
+            .api_url
+            .join(path)
+            .map_err(|e| format!("Invalid GitHub API URL: {}", e))?;
+
+        // GitHub rejects API requests without a user agent
+        let response = self
+            .http
+            .get(url)
+            .bearer_auth(access_token)
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(USER_AGENT, "petall")
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch {}: {}", path, e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("GitHub API returned error: {}", response.status()));
+        }
+
+        response
+            .json::<T>()
+            .await
+            .map_err(|e| format!("Failed to parse {}: {}", path, e))
+    }
+
+    pub async fn get_user_info(&self, access_token: &str) -> Result<GitHubUserInfo, String> {
+        self.get_api("user", access_token).await
+    }
+
+    /// The user's primary email if GitHub verified it, or else any verified email. The email on
+    /// the user profile is whatever the user chose to make public, and may not be verified.
+    pub async fn get_verified_email(&self, access_token: &str) -> Result<Option<String>, String> {
+        let emails: Vec<GitHubEmail> = self.get_api("user/emails", access_token).await?;
+
+        Ok(emails
+            .iter()
+            .find(|email| email.primary && email.verified)
+            .or_else(|| emails.iter().find(|email| email.verified))
+            .map(|email| email.email.clone()))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct GoogleUserInfo {
-    pub sub: String, //Google user ID
-    pub email: String,
-    #[serde(default)]
-    pub email_verified: bool,
+pub struct GitHubUserInfo {
+    pub id: u64, // GitHub user ID, which unlike the login never changes
+    pub login: String,
     #[serde(default)]
     pub name: Option<String>,
 }
 
-pub async fn get_google_user_info(access_token: &str) -> Result<GoogleUserInfo, String> {
-    let client = reqwest::Client::new();
-
-    let response = client
-        .get("https://www.googleapis.com/oauth2/v3/userinfo")
-        .bearer_auth(access_token)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to fetch user info: {}", e))?;
-
-    if !response.status().is_success() {
-        return Err(format!("Google API returned error: {}", response.status()));
-    }
-
-    response
-        .json::<GoogleUserInfo>()
-        .await
-        .map_err(|e| format!("Failed to parse user info: {}", e))
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GitHubEmail {
+    pub email: String,
+    pub primary: bool,
+    pub verified: bool,
 }
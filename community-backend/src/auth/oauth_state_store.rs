@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, auth::oidc, error::AppResult, models::AuthProvider};
+
+/// How long a user has to complete an OAuth login after starting it
+pub const OAUTH_STATE_TTL: chrono::Duration = chrono::Duration::minutes(10);
+
+/// CSRF state of an OAuth login in progress, along with the PKCE verifier its code has to be
+/// exchanged with
+#[derive(Serialize, Deserialize)]
+pub struct OAuthState {
+    pub state: String,
+    pub provider: AuthProvider,
+    /// Name of the configured provider, for [`AuthProvider::Oidc`] logins
+    pub oidc_provider: Option<String>,
+    pub pkce_verifier: String,
+    /// Nonce the ID token has to carry, for OpenID Connect logins
+    pub nonce: Option<String>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl OAuthState {
+    pub fn new(provider: AuthProvider, state: String, pkce_verifier: String) -> Self {
+        Self {
+            state,
+            provider,
+            oidc_provider: None,
+            pkce_verifier,
+            nonce: None,
+            expires_at: chrono::Utc::now() + OAUTH_STATE_TTL,
+        }
+    }
+
+    /// State of an OpenID Connect login, whose ID token has to carry `nonce`
+    pub fn new_oidc(
+        client: &oidc::OidcClient,
+        state: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Self {
+        Self {
+            oidc_provider: client.configured_name().map(str::to_string),
+            nonce: Some(nonce),
+            ..Self::new(client.provider(), state, pkce_verifier)
+        }
+    }
+}
+
+impl AppState {
+    pub async fn store_oauth_state(&self, oauth_state: &OAuthState) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO oauth_state (state, provider, oidc_provider, pkce_verifier, nonce, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            oauth_state.state,
+            oauth_state.provider as AuthProvider,
+            oauth_state.oidc_provider,
+            oauth_state.pkce_verifier,
+            oauth_state.nonce,
+            oauth_state.expires_at
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes the state so it can only be used once, returning it if it was issued for `provider`
+    /// (and the configured `oidc_provider`, if any) and has not expired
+    pub async fn take_oauth_state(
+        &self,
+        state: &str,
+        provider: AuthProvider,
+        oidc_provider: Option<&str>,
+    ) -> AppResult<Option<OAuthState>> {
+        let oauth_state = sqlx::query_as!(
+            OAuthState,
+            r#"
+            DELETE FROM oauth_state
+            WHERE state = $1
+            RETURNING state, provider as "provider: AuthProvider", oidc_provider, pkce_verifier,
+                nonce, expires_at
+            "#,
+            state
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+
+        Ok(oauth_state.filter(|oauth_state| {
+            oauth_state.provider == provider
+                && oauth_state.oidc_provider.as_deref() == oidc_provider
+                && oauth_state.expires_at > chrono::Utc::now()
+        }))
+    }
+}
@@ -0,0 +1,441 @@
+//! Sign in with any OpenID Connect provider.
+//!
+//! Providers are found through their discovery document, and users are identified by the claims
+//! of the ID token they return, which is checked against the provider's published keys and the
+//! nonce the login started with.
+
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    jwk::{Jwk, JwkSet},
+};
+use oauth2::{
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, ExtraTokenFields,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, StandardRevocableToken,
+    StandardTokenResponse, TokenUrl,
+    basic::{
+        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+        BasicTokenType,
+    },
+    reqwest::async_http_client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OnceCell, RwLock};
+use url::Url;
+
+use crate::models::AuthProvider;
+
+/// How long to wait before fetching a provider's key set again for a key it didn't have, so ID
+/// tokens with made up key IDs can't make us hammer the provider
+const JWKS_REFRESH_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Algorithms ID tokens may be signed with. Symmetric ones are left out, since they would have the
+/// client secret double as a signing key.
+const SIGNING_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+fn default_scopes() -> Vec<String> {
+    ["openid", "email", "profile"].map(String::from).to_vec()
+}
+
+/// A provider as configured in the file passed with `--oidc-providers-file`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcProviderConfig {
+    /// Identifies the provider in URLs, such as `/auth/oidc/{name}`
+    pub name: String,
+    /// What the login button says
+    pub display_name: String,
+    /// Exactly as the provider states it in its ID tokens
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_url: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+impl OidcProviderConfig {
+    /// Loads the JSON array of providers in `path`
+    pub fn load_all(path: impl AsRef<Path>) -> anyhow::Result<Vec<Self>> {
+        let path = path.as_ref();
+        let providers: Vec<Self> = serde_json::from_slice(
+            &std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?,
+        )
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        for (i, provider) in providers.iter().enumerate() {
+            anyhow::ensure!(
+                !provider.name.is_empty()
+                    && provider
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+                "OIDC provider names may only contain lowercase letters, digits and dashes: {}",
+                provider.name
+            );
+            anyhow::ensure!(
+                providers[..i]
+                    .iter()
+                    .all(|other| other.name != provider.name),
+                "OIDC provider configured twice: {}",
+                provider.name
+            );
+        }
+
+        Ok(providers)
+    }
+}
+
+/// Where a provider's endpoints are, as stated in its discovery document
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    pub jwks_uri: Url,
+}
+
+/// The ID token the token endpoint returns along with the access token
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IdTokenFields {
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+type OidcTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+
+type OidcOAuthClient = oauth2::Client<
+    BasicErrorResponse,
+    OidcTokenResponse,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+/// Claims of a verified ID token
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// The email, if the provider verified it
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+struct CachedKeys {
+    keys: JwkSet,
+    fetched_at: Option<Instant>,
+}
+
+struct Discovered {
+    client: OidcOAuthClient,
+    jwks_uri: Url,
+}
+
+struct Inner {
+    provider: AuthProvider,
+    config: OidcProviderConfig,
+    http: reqwest::Client,
+    discovered: OnceCell<Discovered>,
+    cache: RwLock<CachedKeys>,
+}
+
+#[derive(Clone)]
+pub struct OidcClient {
+    inner: Arc<Inner>,
+}
+
+impl OidcClient {
+    /// A provider whose endpoints are looked up in its discovery document on first use, so the
+    /// backend still starts while a provider is down
+    pub fn new(config: OidcProviderConfig) -> Self {
+        Self::with_provider(AuthProvider::Oidc, config, OnceCell::new())
+    }
+
+    /// Google, whose endpoints are well known
+    pub fn google(
+        client_id: String,
+        client_secret: String,
+        redirect_url: String,
+    ) -> Result<Self, oauth2::url::ParseError> {
+        let config = OidcProviderConfig {
+            name: "google".to_string(),
+            display_name: "Google".to_string(),
+            issuer: "https://accounts.google.com".to_string(),
+            client_id,
+            client_secret,
+            redirect_url,
+            scopes: default_scopes(),
+        };
+        let metadata = ProviderMetadata {
+            issuer: config.issuer.clone(),
+            authorization_endpoint: "https://accounts.google.com/o/oauth2/v2/auth".parse()?,
+            token_endpoint: "https://oauth2.googleapis.com/token".parse()?,
+            jwks_uri: "https://www.googleapis.com/oauth2/v3/certs".parse()?,
+        };
+        let discovered = Self::discovered(&config, metadata)?;
+
+        Ok(Self::with_provider(
+            AuthProvider::Google,
+            config,
+            OnceCell::new_with(Some(discovered)),
+        ))
+    }
+
+    fn with_provider(
+        provider: AuthProvider,
+        config: OidcProviderConfig,
+        discovered: OnceCell<Discovered>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                provider,
+                config,
+                http: reqwest::Client::new(),
+                discovered,
+                cache: RwLock::new(CachedKeys {
+                    keys: JwkSet { keys: Vec::new() },
+                    fetched_at: None,
+                }),
+            }),
+        }
+    }
+
+    pub fn provider(&self) -> AuthProvider {
+        self.inner.provider
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.config.name
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.inner.config.display_name
+    }
+
+    /// Name OAuth states are bound to, for providers configured with `--oidc-providers-file`
+    pub fn configured_name(&self) -> Option<&str> {
+        (self.inner.provider == AuthProvider::Oidc).then_some(self.name())
+    }
+
+    /// ID of the key a user signs in with. Subjects are only unique per issuer, so those of
+    /// configured providers are qualified with it.
+    pub fn key_id(&self, claims: &IdTokenClaims) -> String {
+        match self.inner.provider {
+            AuthProvider::Oidc => format!("{}|{}", self.inner.config.issuer, claims.sub),
+            _ => claims.sub.clone(),
+        }
+    }
+
+    fn discovered(
+        config: &OidcProviderConfig,
+        metadata: ProviderMetadata,
+    ) -> Result<Discovered, oauth2::url::ParseError> {
+        let client = OidcOAuthClient::new(
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.client_secret.clone())),
+            AuthUrl::from_url(metadata.authorization_endpoint),
+            Some(TokenUrl::from_url(metadata.token_endpoint)),
+        )
+        .set_redirect_uri(RedirectUrl::new(config.redirect_url.clone())?);
+
+        Ok(Discovered {
+            client,
+            jwks_uri: metadata.jwks_uri,
+        })
+    }
+
+    async fn discover(&self) -> Result<&Discovered, String> {
+        self.inner
+            .discovered
+            .get_or_try_init(|| async {
+                let config = &self.inner.config;
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    config.issuer.trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = self
+                    .inner
+                    .http
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| format!("Failed to fetch discovery document: {}", e))?
+                    .json()
+                    .await
+                    .map_err(|e| format!("Failed to parse discovery document: {}", e))?;
+
+                // Otherwise whoever serves the document could speak for another issuer
+                if metadata.issuer != config.issuer {
+                    return Err(format!(
+                        "Discovery document is for issuer {}, not {}",
+                        metadata.issuer, config.issuer
+                    ));
+                }
+
+                Self::discovered(config, metadata)
+                    .map_err(|e| format!("Invalid redirect URL: {}", e))
+            })
+            .await
+    }
+
+    /// Returns the URL to send the user to, along with the CSRF state, the PKCE verifier and the
+    /// nonce the callback has to be checked against
+    pub async fn get_authorization_url(
+        &self,
+    ) -> Result<(Url, CsrfToken, PkceCodeVerifier, String), String> {
+        let discovered = self.discover().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let nonce = CsrfToken::new_random().secret().clone();
+
+        let mut request = discovered
+            .client
+            .authorize_url(CsrfToken::new_random)
+            .add_extra_param("nonce", nonce.clone())
+            .set_pkce_challenge(pkce_challenge);
+        if !self
+            .inner
+            .config
+            .scopes
+            .iter()
+            .any(|scope| scope == "openid")
+        {
+            request = request.add_scope(Scope::new("openid".to_string()));
+        }
+        let (url, csrf_token) = request
+            .add_scopes(self.inner.config.scopes.iter().cloned().map(Scope::new))
+            .url();
+
+        Ok((url, csrf_token, pkce_verifier, nonce))
+    }
+
+    /// Exchanges the code for an ID token, and returns its claims once it is verified to be from
+    /// the provider, for us, and for the login that started with `nonce`
+    pub async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, String> {
+        let discovered = self.discover().await?;
+        let token_response = discovered
+            .client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| format!("Failed to exchange code: {}", e))?;
+
+        let id_token = token_response
+            .extra_fields()
+            .id_token
+            .as_deref()
+            .ok_or("Provider returned no ID token")?;
+        let claims = self.verify_id_token(id_token).await?;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce does not match".to_string());
+        }
+
+        Ok(claims)
+    }
+
+    async fn verify_id_token(&self, id_token: &str) -> Result<IdTokenClaims, String> {
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|e| format!("Invalid ID token: {}", e))?;
+        if !SIGNING_ALGORITHMS.contains(&header.alg) {
+            return Err(format!("ID token signed with {:?}", header.alg));
+        }
+        let kid = header.kid.ok_or("ID token has no key ID")?;
+
+        let key = match self.find_key(&kid).await {
+            Some(key) => key,
+            None => {
+                // The provider may have rotated its keys since they were last fetched
+                self.refresh_keys().await?;
+                self.find_key(&kid)
+                    .await
+                    .ok_or_else(|| format!("Unknown ID token signing key: {}", kid))?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        let issuer = self.inner.config.issuer.as_str();
+        match self.inner.provider {
+            // Google still hands out some ID tokens with its issuer missing the scheme
+            AuthProvider::Google => validation.set_issuer(&[issuer, "accounts.google.com"]),
+            _ => validation.set_issuer(&[issuer]),
+        }
+        validation.set_audience(&[&self.inner.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let decoding_key =
+            DecodingKey::from_jwk(&key).map_err(|e| format!("Invalid signing key: {}", e))?;
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map(|token| token.claims)
+            .map_err(|e| format!("Invalid ID token: {}", e))
+    }
+
+    async fn find_key(&self, kid: &str) -> Option<Jwk> {
+        self.inner.cache.read().await.keys.find(kid).cloned()
+    }
+
+    /// Fetches the key set again, unless it was fetched too recently
+    async fn refresh_keys(&self) -> Result<(), String> {
+        let jwks_uri = &self.discover().await?.jwks_uri;
+        let mut cache = self.inner.cache.write().await;
+        if cache
+            .fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() < JWKS_REFRESH_COOLDOWN)
+        {
+            return Ok(());
+        }
+
+        cache.keys = self
+            .inner
+            .http
+            .get(jwks_uri.as_str())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch signing keys: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse signing keys: {}", e))?;
+        cache.fetched_at = Some(Instant::now());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use uuid::Uuid;
+
+use crate::{AppState, auth::hash_token, error::AppResult};
+
+/// How long a password reset link stays valid after it was emailed
+pub const PASSWORD_RESET_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+/// A random token to send by email, as in password reset and email verification links
+pub fn new_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+impl AppState {
+    /// Issues a reset token for the user, replacing any earlier one, and returns it
+    pub async fn create_password_reset_token(&self, user_id: Uuid) -> AppResult<String> {
+        let token = new_token();
+
+        // Also drop expired tokens of other users while at it
+        sqlx::query!(
+            r#"
+            DELETE FROM password_reset_token
+            WHERE user_id = $1 OR expires_at <= NOW()
+            "#,
+            user_id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO password_reset_token (token_hash, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            hash_token(&token),
+            user_id,
+            chrono::Utc::now() + PASSWORD_RESET_TTL
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Removes the token so it can only be used once, returning its user if it has not expired
+    pub async fn consume_password_reset_token(&self, token: &str) -> AppResult<Option<Uuid>> {
+        let reset = sqlx::query!(
+            r#"
+            DELETE FROM password_reset_token
+            WHERE token_hash = $1
+            RETURNING user_id, expires_at
+            "#,
+            hash_token(token)
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+
+        Ok(reset
+            .filter(|reset| reset.expires_at > chrono::Utc::now())
+            .map(|reset| reset.user_id))
+    }
+}
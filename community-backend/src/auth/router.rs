@@ -1,12 +1,27 @@
-use crate::auth::oauth;
 use crate::{
     AppState,
-    auth::{Session, extractor::ExtractSession, password},
-    error::{AppError, AppResult},
-    models::AuthProvider,
+    auth::{
+        SessionDevice,
+        email_verification_store::EMAIL_VERIFICATION_TTL,
+        extractor::{ExtractSession, ExtractSessionDevice},
+        oauth_state_store::OAuthState,
+        oidc::OidcClient,
+        password,
+        password_reset_store::PASSWORD_RESET_TTL,
+        totp,
+    },
+    error::{AppError, AppResult, ValidatedJson},
+    mail::Email,
+    models::{AuthProvider, User},
 };
-use axum::{Json, Router, debug_handler, extract::State, response::IntoResponse, routing::post};
-use oauth2::TokenResponse;
+use axum::{
+    Json, Router, debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use oauth2::{PkceCodeVerifier, TokenResponse};
 use uuid::Uuid;
 use validator::Validate;
 
@@ -14,15 +29,58 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .route("/register", post(register_handler))
         .route("/login", post(login_handler))
+        .route("/login/two-factor", post(two_factor_login_handler))
         .route("/revoke", post(revoke_handler))
+        .route(
+            "/sessions",
+            axum::routing::get(list_sessions_handler).delete(revoke_other_sessions_handler),
+        )
+        .route(
+            "/sessions/{id}",
+            axum::routing::delete(revoke_session_handler),
+        )
         .route("/changepassword", post(change_password_handler))
+        .route("/verify-email", post(verify_email_handler))
+        .route(
+            "/verify-email/resend",
+            post(resend_verification_email_handler),
+        )
+        .route(
+            "/password-reset/request",
+            post(request_password_reset_handler),
+        )
+        .route(
+            "/password-reset/confirm",
+            post(confirm_password_reset_handler),
+        )
         .route("/me", post(me_handler))
+        .route("/two-factor", axum::routing::get(two_factor_status_handler))
+        .route("/two-factor/setup", post(two_factor_setup_handler))
+        .route("/two-factor/enable", post(two_factor_enable_handler))
+        .route("/two-factor/disable", post(two_factor_disable_handler))
+        .route(
+            "/two-factor/recovery-codes",
+            post(regenerate_recovery_codes_handler),
+        )
         .route("/oauth/google", axum::routing::get(google_oauth_handler))
         .route("/callback", axum::routing::get(google_callback_handler))
+        .route("/oauth/github", axum::routing::get(github_oauth_handler))
+        .route(
+            "/oauth/github/callback",
+            axum::routing::get(github_callback_handler),
+        )
+        .route("/oidc", axum::routing::get(oidc_providers_handler))
+        .route("/oidc/{name}", axum::routing::get(oidc_handler))
+        .route(
+            "/oidc/{name}/callback",
+            axum::routing::get(oidc_callback_handler),
+        )
 }
 
+/// Registers a regular user. Unknown fields are refused, so roles can't be asked for here; admins
+/// are made with `petall create-admin` or by other admins.
 #[derive(serde::Deserialize, serde::Serialize, Validate)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct RegisterRequest {
     #[validate(length(
         min = 3,
@@ -38,7 +96,6 @@ pub struct RegisterRequest {
         message = "Password must be between 8 and 50 characters"
     ))]
     pub password: String,
-    pub is_admin: bool,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -53,6 +110,7 @@ pub struct RegisterResponse {
 #[debug_handler]
 async fn register_handler(
     State(state): State<AppState>,
+    ExtractSessionDevice(device): ExtractSessionDevice,
     Json(request): Json<RegisterRequest>,
 ) -> AppResult<impl IntoResponse> {
     request.validate()?;
@@ -63,16 +121,12 @@ async fn register_handler(
     }
 
     let user = state
-        .register_user(
-            &request.email,
-            &request.name,
-            &request.password,
-            request.is_admin,
-        )
+        .register_user(&request.email, &request.name, &request.password)
         .await?;
 
-    let session = Session::new_random_from(user.id);
-    state.store_session(&session).await?;
+    send_verification_email(&state, &user).await?;
+
+    let session = state.create_session(user.id, device).await?;
 
     Ok(Json(RegisterResponse {
         uuid: user.id,
@@ -98,30 +152,28 @@ struct LoginResponse {
     session_id: Uuid,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct OAuthUrlResponse {
     authorization_url: String,
+    /// CSRF state, which the frontend keeps in a cookie to check the callback came from the same
+    /// browser
+    state: String,
 }
 
 #[debug_handler]
 async fn google_oauth_handler(State(state): State<AppState>) -> AppResult<impl IntoResponse> {
-    let (auth_url, _csrf_token) = state.google_oauth.get_authorization_url();
-
-    //TODO: save CRSF TOKEN in user session and verify it in callbacks
-
-    Ok(Json(OAuthUrlResponse {
-        authorization_url: auth_url.to_string(),
-    }))
+    oidc_authorization(&state, &state.google_oauth).await
 }
 
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct OAuthCallbackQuery {
     code: String,
+    state: Option<String>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct OAuthCallbackResponse {
     session_id: Uuid,
@@ -134,52 +186,235 @@ struct OAuthCallbackResponse {
 #[debug_handler]
 async fn google_callback_handler(
     State(state): State<AppState>,
+    ExtractSessionDevice(device): ExtractSessionDevice,
     axum::extract::Query(query): axum::extract::Query<OAuthCallbackQuery>,
 ) -> AppResult<impl IntoResponse> {
-    // TODO: verify CRSF here
+    oidc_callback(&state, &state.google_oauth, query, device).await
+}
+
+#[debug_handler]
+async fn github_oauth_handler(State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let (auth_url, csrf_token, pkce_verifier) = state.github_oauth.get_authorization_url();
+
+    state
+        .store_oauth_state(&OAuthState::new(
+            AuthProvider::GitHub,
+            csrf_token.secret().clone(),
+            pkce_verifier.secret().clone(),
+        ))
+        .await?;
+
+    Ok(Json(OAuthUrlResponse {
+        authorization_url: auth_url.to_string(),
+        state: csrf_token.secret().clone(),
+    }))
+}
+
+#[debug_handler]
+async fn github_callback_handler(
+    State(state): State<AppState>,
+    ExtractSessionDevice(device): ExtractSessionDevice,
+    axum::extract::Query(query): axum::extract::Query<OAuthCallbackQuery>,
+) -> AppResult<impl IntoResponse> {
+    let oauth_state = state
+        .take_oauth_state(
+            query.state.as_deref().ok_or(AppError::InvalidOAuthState)?,
+            AuthProvider::GitHub,
+            None,
+        )
+        .await?
+        .ok_or(AppError::InvalidOAuthState)?;
+
     let token_response = state
-        .google_oauth
-        .exchange_code(query.code)
+        .github_oauth
+        .exchange_code(query.code, PkceCodeVerifier::new(oauth_state.pkce_verifier))
         .await
-        .map_err(|e| AppError::OAuthError(e))?;
+        .map_err(AppError::OAuthError)?;
 
     let access_token = token_response.access_token().secret();
 
-    let google_user = oauth::get_google_user_info(access_token)
+    let github_user = state
+        .github_oauth
+        .get_user_info(access_token)
         .await
-        .map_err(|e| AppError::OAuthError(e))?;
+        .map_err(AppError::OAuthError)?;
 
-    if !google_user.email_verified {
-        return Err(AppError::EmailNotVerified);
-    }
+    let email = state
+        .github_oauth
+        .get_verified_email(access_token)
+        .await
+        .map_err(AppError::OAuthError)?
+        .ok_or(AppError::EmailNotVerified)?;
+
+    let name = github_user.name.as_deref().unwrap_or(&github_user.login);
+    oauth_sign_in(
+        &state,
+        AuthProvider::GitHub,
+        &github_user.id.to_string(),
+        &email,
+        name,
+        device,
+    )
+    .await
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OidcProviderResponse {
+    name: String,
+    display_name: String,
+}
+
+/// Lists the configured OpenID Connect providers users can sign in with
+#[debug_handler]
+async fn oidc_providers_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(
+        state
+            .oidc_providers
+            .iter()
+            .map(|client| OidcProviderResponse {
+                name: client.name().to_string(),
+                display_name: client.display_name().to_string(),
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn find_oidc_provider<'a>(state: &'a AppState, name: &str) -> AppResult<&'a OidcClient> {
+    state
+        .oidc_providers
+        .iter()
+        .find(|client| client.name() == name)
+        .ok_or_else(|| AppError::UnknownOidcProvider(name.to_string()))
+}
 
-    let key_id = google_user.sub;
+#[debug_handler]
+async fn oidc_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    oidc_authorization(&state, find_oidc_provider(&state, &name)?).await
+}
+
+#[debug_handler]
+async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    ExtractSessionDevice(device): ExtractSessionDevice,
+    Path(name): Path<String>,
+    axum::extract::Query(query): axum::extract::Query<OAuthCallbackQuery>,
+) -> AppResult<impl IntoResponse> {
+    oidc_callback(&state, find_oidc_provider(&state, &name)?, query, device).await
+}
 
+async fn oidc_authorization(
+    state: &AppState,
+    client: &OidcClient,
+) -> AppResult<Json<OAuthUrlResponse>> {
+    let (auth_url, csrf_token, pkce_verifier, nonce) = client
+        .get_authorization_url()
+        .await
+        .map_err(AppError::OAuthError)?;
+
+    state
+        .store_oauth_state(&OAuthState::new_oidc(
+            client,
+            csrf_token.secret().clone(),
+            pkce_verifier.secret().clone(),
+            nonce,
+        ))
+        .await?;
+
+    Ok(Json(OAuthUrlResponse {
+        authorization_url: auth_url.to_string(),
+        state: csrf_token.secret().clone(),
+    }))
+}
+
+/// Signs in with the claims of the ID token the code is exchanged for
+async fn oidc_callback(
+    state: &AppState,
+    client: &OidcClient,
+    query: OAuthCallbackQuery,
+    device: SessionDevice,
+) -> AppResult<Response> {
+    let oauth_state = state
+        .take_oauth_state(
+            query.state.as_deref().ok_or(AppError::InvalidOAuthState)?,
+            client.provider(),
+            client.configured_name(),
+        )
+        .await?
+        .ok_or(AppError::InvalidOAuthState)?;
+    let nonce = oauth_state.nonce.ok_or(AppError::InvalidOAuthState)?;
+
+    let claims = client
+        .exchange_code(
+            query.code,
+            PkceCodeVerifier::new(oauth_state.pkce_verifier),
+            &nonce,
+        )
+        .await
+        .map_err(AppError::OAuthError)?;
+
+    let email = claims.verified_email().ok_or(AppError::EmailNotVerified)?;
+    let name = claims
+        .name
+        .as_deref()
+        .or(claims.preferred_username.as_deref())
+        .unwrap_or(email);
+    oauth_sign_in(
+        state,
+        client.provider(),
+        &client.key_id(&claims),
+        email,
+        name,
+        device,
+    )
+    .await
+}
+
+/// Signs in the user holding the provider key, linking it to the account with the same email or
+/// registering a new account if there is none. The provider must have verified the email.
+async fn oauth_sign_in(
+    state: &AppState,
+    provider: AuthProvider,
+    key_id: &str,
+    email: &str,
+    name: &str,
+    device: SessionDevice,
+) -> AppResult<Response> {
     let mut is_new_user = false;
-    let user = if let Some(key) = state.get_key(AuthProvider::Google, &key_id).await? {
+    let user = if let Some(key) = state.get_key(provider, key_id).await? {
         // oauth user already exists
         state
             .get_user_by_id(key.user_id)
             .await?
             .ok_or(AppError::InvalidSession)?
-    } else if let Some(existing_user) = state.get_user_by_email(&google_user.email).await? {
+    } else if let Some(existing_user) = state.get_user_by_email(email).await? {
+        // Whoever registered an unverified email may not own it, and linking would hand them
+        // the account of the provider's user
+        if !existing_user.is_email_verified() {
+            return Err(AppError::UnverifiedAccountExists(existing_user.email));
+        }
         //email user already exists -> used to link email account to oauth account
         state
-            .create_key(AuthProvider::Google, &key_id, existing_user.id, None)
+            .create_key(provider, key_id, existing_user.id, None)
             .await?;
         existing_user
     } else {
-        //no email account and no google account
+        //no email account and no account with this provider
         is_new_user = true;
-        let name = google_user.name.as_deref().unwrap_or(&google_user.email);
 
         state
-            .register_oauth_user(&google_user.email, name, AuthProvider::Google, &key_id)
+            .register_oauth_user(email, name, provider, key_id)
             .await?
     };
 
-    let session = Session::new_random_from(user.id);
-    state.store_session(&session).await?;
+    if let Some(challenge) = challenge_second_factor(state, user.id).await? {
+        return Ok(challenge);
+    }
+
+    let session = state.create_session(user.id, device).await?;
 
     Ok(Json(OAuthCallbackResponse {
         session_id: session.id,
@@ -187,14 +422,40 @@ async fn google_callback_handler(
         user_id: user.id,
         email: user.email,
         name: user.name,
-    }))
+    })
+    .into_response())
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorChallengeResponse {
+    challenge_id: Uuid,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// For users with two-factor authentication, starts a challenge to complete at
+/// `/login/two-factor` in place of a session, answered with 202 Accepted
+async fn challenge_second_factor(state: &AppState, user_id: Uuid) -> AppResult<Option<Response>> {
+    if !state.has_two_factor(user_id).await? {
+        return Ok(None);
+    }
+
+    let challenge = state.create_login_challenge(user_id).await?;
+    let response = Json(TwoFactorChallengeResponse {
+        challenge_id: challenge.id,
+        expires_at: challenge.expires_at,
+    });
+    Ok(Some((StatusCode::ACCEPTED, response).into_response()))
+}
+
+/// Signs in with a password. Users with two-factor authentication get a challenge to answer at
+/// `/login/two-factor` instead of a session.
 #[debug_handler]
 async fn login_handler(
     State(state): State<AppState>,
+    ExtractSessionDevice(device): ExtractSessionDevice,
     Json(request): Json<LoginRequest>,
-) -> AppResult<impl IntoResponse> {
+) -> AppResult<Response> {
     let user = state
         .get_user_by_email(&request.email)
         .await?
@@ -213,8 +474,57 @@ async fn login_handler(
         return Err(AppError::InvalidCredentials);
     }
 
-    let session = Session::new_random_from(user.id);
-    state.store_session(&session).await?;
+    if let Some(challenge) = challenge_second_factor(&state, user.id).await? {
+        return Ok(challenge);
+    }
+
+    let session = state.create_session(user.id, device).await?;
+
+    Ok(Json(LoginResponse {
+        uuid: user.id,
+        name: user.name,
+        email: user.email,
+        session_id: session.id,
+    })
+    .into_response())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorLoginRequest {
+    challenge_id: Uuid,
+    /// Code from the authenticator, or a recovery code
+    code: String,
+}
+
+/// Completes a login challenge with the user's second factor
+#[debug_handler]
+async fn two_factor_login_handler(
+    State(state): State<AppState>,
+    ExtractSessionDevice(device): ExtractSessionDevice,
+    Json(request): Json<TwoFactorLoginRequest>,
+) -> AppResult<impl IntoResponse> {
+    let challenge = state
+        .get_login_challenge(request.challenge_id)
+        .await?
+        .ok_or(AppError::InvalidLoginChallenge)?;
+
+    if !state
+        .verify_second_factor(challenge.user_id, &request.code)
+        .await?
+    {
+        return Err(AppError::InvalidTwoFactorCode);
+    }
+    // Only one request gets to complete the challenge
+    if !state.delete_login_challenge(challenge.id).await? {
+        return Err(AppError::InvalidLoginChallenge);
+    }
+
+    let user = state
+        .get_user_by_id(challenge.user_id)
+        .await?
+        .ok_or(AppError::InvalidLoginChallenge)?;
+    let session = state.create_session(user.id, device).await?;
 
     Ok(Json(LoginResponse {
         uuid: user.id,
@@ -244,6 +554,84 @@ async fn revoke_handler(
     }))
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    pub expiration: chrono::DateTime<chrono::Utc>,
+    /// When the session ends however often it is used
+    pub max_expiration: chrono::DateTime<chrono::Utc>,
+    pub user_agent: Option<String>,
+    pub client_ip: Option<String>,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+}
+
+/// Lists the user's active sessions, most recently used first
+#[debug_handler]
+async fn list_sessions_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let sessions = state
+        .get_user_sessions(session.user_id)
+        .await?
+        .into_iter()
+        .map(|other| SessionResponse {
+            current: other.id == session.id,
+            id: other.id,
+            created_at: other.created_at,
+            last_seen_at: other.last_seen_at,
+            expiration: other.expiration,
+            max_expiration: other.max_expiration(&state.session_policy),
+            user_agent: other.user_agent,
+            client_ip: other.client_ip,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(sessions))
+}
+
+/// Revokes one of the user's sessions, which may be the current one
+#[debug_handler]
+async fn revoke_session_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    if !state
+        .delete_user_session(session.user_id, session_id)
+        .await?
+    {
+        return Err(AppError::SessionNotFound(session_id));
+    }
+
+    Ok(Json(RevokeResponse {
+        message: "Session revoked".to_string(),
+    }))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeOtherSessionsResponse {
+    pub revoked: u64,
+}
+
+/// Revokes every session of the user but the current one
+#[debug_handler]
+async fn revoke_other_sessions_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let revoked = state
+        .delete_other_sessions(session.user_id, session.id)
+        .await?;
+
+    Ok(Json(RevokeOtherSessionsResponse { revoked }))
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ChangePasswordRequest {
@@ -261,6 +649,7 @@ struct ChangePasswordResponse {
 #[debug_handler]
 async fn change_password_handler(
     ExtractSession(session): ExtractSession,
+    ExtractSessionDevice(device): ExtractSessionDevice,
     State(state): State<AppState>,
     request: Json<ChangePasswordRequest>,
 ) -> AppResult<impl IntoResponse> {
@@ -287,8 +676,7 @@ async fn change_password_handler(
 
     state.delete_all_sessions(user.id).await?;
 
-    let session = Session::new_random_from(user.id);
-    state.store_session(&session).await?;
+    let session = state.create_session(user.id, device).await?;
 
     Ok(Json(ChangePasswordResponse {
         message: "Password changed successfully".to_string(),
@@ -296,6 +684,327 @@ async fn change_password_handler(
     }))
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PasswordResetRequest {
+    email: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PasswordResetResponse {
+    message: String,
+}
+
+/// Emails a link to reset the password of the account with this email, if there is one with a
+/// password. The answer is the same either way so it doesn't reveal who has an account.
+#[debug_handler]
+async fn request_password_reset_handler(
+    State(state): State<AppState>,
+    Json(request): Json<PasswordResetRequest>,
+) -> AppResult<impl IntoResponse> {
+    let response = Json(PasswordResetResponse {
+        message: "If an account uses this email, a link to reset its password was sent to it"
+            .to_string(),
+    });
+
+    let Some(user) = state.get_user_by_email(&request.email).await? else {
+        return Ok(response);
+    };
+    if state
+        .get_key(AuthProvider::Email, &user.email)
+        .await?
+        .is_none()
+    {
+        return Ok(response);
+    }
+
+    let token = state.create_password_reset_token(user.id).await?;
+    let link = state.frontend_link("reset-password", &token);
+    state.send_email_in_background(Email {
+        to: user.email,
+        subject: "Reset your Petall password".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+            Someone asked to reset the password of your Petall account. If it was you, choose a new \
+            password within {} minutes at:\n\n{}\n\n\
+            Otherwise you can ignore this email, your password stays the same.\n",
+            user.name,
+            PASSWORD_RESET_TTL.num_minutes(),
+            link
+        ),
+    });
+
+    Ok(response)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct ConfirmPasswordResetRequest {
+    token: String,
+    #[validate(length(
+        min = 8,
+        max = 50,
+        message = "Password must be between 8 and 50 characters"
+    ))]
+    new_password: String,
+}
+
+/// Sets a new password with a token from a reset email, and signs out every session since
+/// whoever had the old password may have one
+#[debug_handler]
+async fn confirm_password_reset_handler(
+    State(state): State<AppState>,
+    ValidatedJson(request): ValidatedJson<ConfirmPasswordResetRequest>,
+) -> AppResult<impl IntoResponse> {
+    let user_id = state
+        .consume_password_reset_token(&request.token)
+        .await?
+        .ok_or(AppError::InvalidPasswordResetToken)?;
+    let user = state
+        .get_user_by_id(user_id)
+        .await?
+        .ok_or(AppError::InvalidPasswordResetToken)?;
+
+    let new_hashed_password = password::hash_password(&request.new_password)?;
+    state
+        .update_key_password(AuthProvider::Email, &user.email, new_hashed_password)
+        .await?;
+    state.delete_all_sessions(user.id).await?;
+    // Only the owner of the email could have followed the link
+    state.mark_email_verified(user.id).await?;
+
+    Ok(Json(PasswordResetResponse {
+        message: "Password reset successfully".to_string(),
+    }))
+}
+
+/// Emails the user a link to verify their email with
+async fn send_verification_email(state: &AppState, user: &User) -> AppResult<()> {
+    let token = state.create_email_verification_token(user.id).await?;
+    let link = state.frontend_link("verify-email", &token);
+    state.send_email_in_background(Email {
+        to: user.email.clone(),
+        subject: "Verify your Petall email".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+            Confirm this is your email to start using your Petall account, within {} hours at:\n\n\
+            {}\n\n\
+            If you didn't create an account, you can ignore this email.\n",
+            user.name,
+            EMAIL_VERIFICATION_TTL.num_hours(),
+            link
+        ),
+    });
+    Ok(())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyEmailResponse {
+    message: String,
+}
+
+/// Verifies the email with a token from a verification email. No session is needed, since the
+/// link may be opened in another browser.
+#[debug_handler]
+async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyEmailRequest>,
+) -> AppResult<impl IntoResponse> {
+    let user_id = state
+        .consume_email_verification_token(&request.token)
+        .await?
+        .ok_or(AppError::InvalidEmailVerificationToken)?;
+    state.mark_email_verified(user_id).await?;
+
+    Ok(Json(VerifyEmailResponse {
+        message: "Email verified".to_string(),
+    }))
+}
+
+/// Sends another verification email, replacing the link of the earlier ones
+#[debug_handler]
+async fn resend_verification_email_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::InvalidSession)?;
+    if user.is_email_verified() {
+        return Err(AppError::EmailAlreadyVerified);
+    }
+
+    send_verification_email(&state, &user).await?;
+
+    Ok(Json(VerifyEmailResponse {
+        message: "Verification email sent".to_string(),
+    }))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorStatusResponse {
+    enabled: bool,
+    recovery_codes_left: i64,
+    /// The auth policy asks the user to enable it, and they haven't yet
+    setup_required: bool,
+}
+
+#[debug_handler]
+async fn two_factor_status_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::InvalidSession)?;
+    let enabled = state.has_two_factor(user.id).await?;
+
+    Ok(Json(TwoFactorStatusResponse {
+        enabled,
+        recovery_codes_left: state.count_recovery_codes(user.id).await?,
+        setup_required: !enabled && state.requires_two_factor(&user).await?,
+    }))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorSetupResponse {
+    /// Base32 secret, for typing into the authenticator
+    secret: String,
+    otpauth_uri: String,
+    /// `data:` URL of a QR code of the URI
+    qr_code: Option<String>,
+}
+
+/// Starts enrolling an authenticator, which only counts once confirmed at `/two-factor/enable`
+#[debug_handler]
+async fn two_factor_setup_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::InvalidSession)?;
+
+    let secret = totp::generate_secret();
+    if !state.store_pending_totp(user.id, &secret).await? {
+        return Err(AppError::TwoFactorAlreadyEnabled);
+    }
+    let enrollment = totp::enrollment(&secret, &user.email);
+
+    Ok(Json(TwoFactorSetupResponse {
+        secret: enrollment.secret,
+        otpauth_uri: enrollment.otpauth_uri,
+        qr_code: enrollment.qr_code,
+    }))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorCodeRequest {
+    code: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecoveryCodesResponse {
+    /// Only ever shown here, the backend keeps their hashes
+    recovery_codes: Vec<String>,
+}
+
+/// Confirms the enrolled authenticator with one of its codes, turning on two-factor
+/// authentication, and hands out recovery codes
+#[debug_handler]
+async fn two_factor_enable_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Json(request): Json<TwoFactorCodeRequest>,
+) -> AppResult<impl IntoResponse> {
+    let user_totp = state
+        .get_user_totp(session.user_id)
+        .await?
+        .ok_or(AppError::TwoFactorNotEnabled)?;
+    if user_totp.enabled_at.is_some() {
+        return Err(AppError::TwoFactorAlreadyEnabled);
+    }
+
+    let now = chrono::Utc::now()
+        .timestamp()
+        .try_into()
+        .unwrap_or_default();
+    let step = totp::verify_code(&user_totp.secret, request.code.trim(), now)
+        .ok_or(AppError::InvalidTwoFactorCode)?;
+    if !state.use_totp_step(session.user_id, step as i64).await? {
+        return Err(AppError::InvalidTwoFactorCode);
+    }
+
+    let recovery_codes = totp::generate_recovery_codes();
+    state.enable_totp(session.user_id, &recovery_codes).await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns off two-factor authentication, given a code from the authenticator or a recovery code
+#[debug_handler]
+async fn two_factor_disable_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Json(request): Json<TwoFactorCodeRequest>,
+) -> AppResult<impl IntoResponse> {
+    if !state.has_two_factor(session.user_id).await? {
+        return Err(AppError::TwoFactorNotEnabled);
+    }
+    if !state
+        .verify_second_factor(session.user_id, &request.code)
+        .await?
+    {
+        return Err(AppError::InvalidTwoFactorCode);
+    }
+
+    state.disable_totp(session.user_id).await?;
+
+    Ok(Json(RevokeResponse {
+        message: "Two-factor authentication disabled".to_string(),
+    }))
+}
+
+/// Replaces the recovery codes, given a code from the authenticator or one of the old codes
+#[debug_handler]
+async fn regenerate_recovery_codes_handler(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Json(request): Json<TwoFactorCodeRequest>,
+) -> AppResult<impl IntoResponse> {
+    if !state.has_two_factor(session.user_id).await? {
+        return Err(AppError::TwoFactorNotEnabled);
+    }
+    if !state
+        .verify_second_factor(session.user_id, &request.code)
+        .await?
+    {
+        return Err(AppError::InvalidTwoFactorCode);
+    }
+
+    let recovery_codes = totp::generate_recovery_codes();
+    state
+        .set_recovery_codes(session.user_id, &recovery_codes)
+        .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct MeResponse {
@@ -303,6 +1012,10 @@ struct MeResponse {
     email: String,
     name: String,
     is_admin: bool,
+    email_verified: bool,
+    two_factor_enabled: bool,
+    /// The auth policy asks the user to enable two-factor authentication, and they haven't yet
+    two_factor_setup_required: bool,
     can_access_admin_view: bool,
 }
 
@@ -317,45 +1030,70 @@ async fn me_handler(
         .ok_or(AppError::InvalidSession)?;
 
     let can_access_admin_view = state.can_access_admin_view(&user).await?;
+    let email_verified = user.is_email_verified();
+    let two_factor_enabled = state.has_two_factor(user.id).await?;
+    let two_factor_setup_required = !two_factor_enabled && state.requires_two_factor(&user).await?;
 
     Ok(Json(MeResponse {
         id: user.id,
         email: user.email,
         name: user.name,
         is_admin: user.is_admin,
+        email_verified,
+        two_factor_enabled,
+        two_factor_setup_required,
         can_access_admin_view,
     }))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
 
-    use axum::http::StatusCode;
-    use axum_test::TestServer;
+    use axum::{
+        Form, Json, Router,
+        extract::State,
+        http::StatusCode,
+        routing::{get, post},
+    };
+    use axum_test::{TestResponse, TestServer};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, jwk::Jwk};
+    use oauth2::{PkceCodeChallenge, PkceCodeVerifier};
+    use serde_json::{Value, json};
     use sqlx::PgPool;
     use tower_http::trace::TraceLayer;
     use tracing_test::traced_test;
+    use url::Url;
 
     use crate::{
-        auth::router::{
-            ChangePasswordRequest, LoginRequest, MeResponse, RegisterRequest, RegisterResponse,
+        AppState,
+        auth::{
+            SessionPolicy,
+            oauth::GitHubOAuthClient,
+            oauth_state_store::OAuthState,
+            oidc::{OidcClient, OidcProviderConfig},
+            router::{
+                ChangePasswordRequest, ConfirmPasswordResetRequest, LoginRequest, LoginResponse,
+                MeResponse, OAuthCallbackResponse, OAuthUrlResponse, OidcProviderResponse,
+                PasswordResetRequest, PasswordResetResponse, RecoveryCodesResponse,
+                RegisterRequest, RegisterResponse, RevokeOtherSessionsResponse, SessionResponse,
+                TwoFactorChallengeResponse, TwoFactorCodeRequest, TwoFactorLoginRequest,
+                TwoFactorSetupResponse, TwoFactorStatusResponse, VerifyEmailRequest, router,
+            },
+            totp,
+            two_factor_store::MAX_TWO_FACTOR_ATTEMPTS,
         },
+        mail::SmtpMailer,
+        models::AuthProvider,
+        router::test_utils::{received_token, smtp_sink, test_state, test_user},
         sign::ValidationSigner,
     };
-    fn server(pg_pool: PgPool) -> TestServer {
-        let google_oauth = crate::auth::oauth::GoogleOAuthClient::new(
-            "test-client-id".to_string(),
-            "test-client-secret".to_string(),
-            "http://localhost:8080/auth/callback".to_string(),
-        )
-        .unwrap();
 
-        let state = crate::AppState {
-            pg_pool,
-            google_oauth,
-            validation_signer: Arc::new(ValidationSigner::test_new()),
-        };
+    fn server(pg_pool: PgPool) -> TestServer {
+        let state = crate::router::test_utils::test_state(pg_pool);
         let router = crate::auth::router::router()
             .with_state(state)
             .layer(TraceLayer::new_for_http());
@@ -372,9 +1110,20 @@ mod tests {
             name: "Test User".to_string(),
             email: "test@example.com".to_string(),
             password: "test_password".to_string(),
-            is_admin: false,
         };
 
+        // Roles can't be asked for when registering
+        let response = server
+            .post("/register")
+            .json(&json!({
+                "name": "Test User",
+                "email": "test@example.com",
+                "password": "test_password",
+                "isAdmin": true,
+            }))
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
         let response = server.post("/register").json(&request_body).await;
         response.assert_status(StatusCode::OK);
 
@@ -391,7 +1140,6 @@ mod tests {
             name: "Test User".to_string(),
             email: "test@example.com".to_string(),
             password: "test_password".to_string(),
-            is_admin: false,
         };
 
         let response = server.post("/register").json(&register_request).await;
@@ -431,7 +1179,6 @@ mod tests {
             name: "Test User".to_string(),
             email: "test@example.com".to_string(),
             password: "test_password".to_string(),
-            is_admin: false,
         };
 
         let response = server.post("/register").json(&register_request).await;
@@ -465,7 +1212,6 @@ mod tests {
             name: "Test User".to_string(),
             email: "test@example.com".to_string(),
             password: "old_password".to_string(),
-            is_admin: false,
         };
 
         let response = server.post("/register").json(&register_request).await;
@@ -505,6 +1251,361 @@ mod tests {
         response.assert_status(StatusCode::UNAUTHORIZED);
     }
 
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_password_reset(pool: PgPool) {
+        let (smtp_url, mut emails) = smtp_sink().await;
+        let mut state = test_state(pool);
+        state.mailer = Arc::new(
+            SmtpMailer::new(&smtp_url, "Petall <no-reply@petall.test>".parse().unwrap()).unwrap(),
+        );
+        let server = TestServer::new(router().with_state(state.clone())).unwrap();
+
+        let register_response: RegisterResponse = server
+            .post("/register")
+            .json(&RegisterRequest {
+                name: "Test User".to_string(),
+                email: "test@example.com".to_string(),
+                password: "old_password".to_string(),
+            })
+            .await
+            .json();
+        let login = |password: &str| {
+            server.post("/login").json(&LoginRequest {
+                email: "test@example.com".to_string(),
+                password: password.to_string(),
+            })
+        };
+        let request_reset = |email: &str| {
+            server
+                .post("/password-reset/request")
+                .json(&PasswordResetRequest {
+                    email: email.to_string(),
+                })
+        };
+        let confirm_reset = |token: &str, new_password: &str| {
+            server
+                .post("/password-reset/confirm")
+                .json(&ConfirmPasswordResetRequest {
+                    token: token.to_string(),
+                    new_password: new_password.to_string(),
+                })
+        };
+        // Registering sent a verification email
+        received_token(&mut emails, "test@example.com", "verify-email").await;
+
+        // Unknown emails get the same answer, but no email
+        let unknown: PasswordResetResponse = request_reset("nobody@example.com").await.json();
+        let known: PasswordResetResponse = request_reset("test@example.com").await.json();
+        assert_eq!(unknown.message, known.message);
+        let token = received_token(&mut emails, "test@example.com", "reset-password").await;
+        assert!(emails.try_recv().is_err());
+
+        confirm_reset(&token, "short")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        confirm_reset(&token, "new_password")
+            .await
+            .assert_status_ok();
+
+        login("old_password")
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        login("new_password").await.assert_status_ok();
+        assert!(
+            state
+                .get_valid_session(register_response.session_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        // Following the link proved the email is theirs
+        let user = state
+            .get_user_by_id(register_response.uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.is_email_verified());
+
+        // Tokens work once
+        confirm_reset(&token, "other_password")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        // A newer request replaces the earlier token
+        request_reset("test@example.com").await.assert_status_ok();
+        let replaced = received_token(&mut emails, "test@example.com", "reset-password").await;
+        request_reset("test@example.com").await.assert_status_ok();
+        let token = received_token(&mut emails, "test@example.com", "reset-password").await;
+        confirm_reset(&replaced, "other_password")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        sqlx::query!("UPDATE password_reset_token SET expires_at = NOW() - INTERVAL '1 second'")
+            .execute(&state.pg_pool)
+            .await
+            .unwrap();
+        confirm_reset(&token, "other_password")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        login("new_password").await.assert_status_ok();
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_email_verification(pool: PgPool) {
+        let (smtp_url, mut emails) = smtp_sink().await;
+        let mut state = test_state(pool);
+        state.mailer = Arc::new(
+            SmtpMailer::new(&smtp_url, "Petall <no-reply@petall.test>".parse().unwrap()).unwrap(),
+        );
+        let server = TestServer::new(router().with_state(state.clone())).unwrap();
+
+        let registered: RegisterResponse = server
+            .post("/register")
+            .json(&test_user("test@example.com"))
+            .await
+            .json();
+        let session_id = registered.session_id.to_string();
+        let email_verified = async || {
+            server
+                .post("/me")
+                .add_header("Authorization", &session_id)
+                .await
+                .json::<MeResponse>()
+                .email_verified
+        };
+        let verify = |token: &str| {
+            server.post("/verify-email").json(&VerifyEmailRequest {
+                token: token.to_string(),
+            })
+        };
+        let resend = || {
+            server
+                .post("/verify-email/resend")
+                .add_header("Authorization", &session_id)
+        };
+
+        let first = received_token(&mut emails, "test@example.com", "verify-email").await;
+        assert!(!email_verified().await);
+
+        // Resending replaces the earlier link
+        resend().await.assert_status_ok();
+        let token = received_token(&mut emails, "test@example.com", "verify-email").await;
+        verify(&first).await.assert_status(StatusCode::BAD_REQUEST);
+        assert!(!email_verified().await);
+
+        sqlx::query!(
+            "UPDATE email_verification_token SET expires_at = NOW() - INTERVAL '1 second'"
+        )
+        .execute(&state.pg_pool)
+        .await
+        .unwrap();
+        verify(&token).await.assert_status(StatusCode::BAD_REQUEST);
+
+        resend().await.assert_status_ok();
+        let token = received_token(&mut emails, "test@example.com", "verify-email").await;
+        verify(&token).await.assert_status_ok();
+        assert!(email_verified().await);
+
+        verify(&token).await.assert_status(StatusCode::BAD_REQUEST);
+        resend().await.assert_status(StatusCode::CONFLICT);
+        server
+            .post("/verify-email/resend")
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_two_factor(pool: PgPool) {
+        let server = server(pool);
+
+        let registered: RegisterResponse = server
+            .post("/register")
+            .json(&test_user("test@example.com"))
+            .await
+            .json();
+        let session_id = registered.session_id.to_string();
+        let login = || {
+            server.post("/login").json(&LoginRequest {
+                email: "test@example.com".to_string(),
+                password: "test_password".to_string(),
+            })
+        };
+        let challenge = async || {
+            let response = login().await;
+            response.assert_status(StatusCode::ACCEPTED);
+            response.json::<TwoFactorChallengeResponse>().challenge_id
+        };
+        let answer = |challenge_id, code: &str| {
+            server
+                .post("/login/two-factor")
+                .json(&TwoFactorLoginRequest {
+                    challenge_id,
+                    code: code.to_string(),
+                })
+        };
+        let with_code = |path: &str, code: &str| {
+            server
+                .post(path)
+                .add_header("Authorization", &session_id)
+                .json(&TwoFactorCodeRequest {
+                    code: code.to_string(),
+                })
+        };
+        let now = || chrono::Utc::now().timestamp() as u64;
+
+        login().await.assert_status_ok();
+
+        let setup: TwoFactorSetupResponse = server
+            .post("/two-factor/setup")
+            .add_header("Authorization", &session_id)
+            .await
+            .json();
+        assert!(setup.otpauth_uri.starts_with("otpauth://totp/Petall:"));
+        assert!(setup.qr_code.is_some());
+        let secret = totp_rs::Secret::Encoded(setup.secret).to_bytes().unwrap();
+
+        // Not enabled until confirmed with a code
+        login().await.assert_status_ok();
+        with_code("/two-factor/enable", "000000")
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let code = totp::generate_code(&secret, now());
+        let recovery_codes = with_code("/two-factor/enable", &code)
+            .await
+            .json::<RecoveryCodesResponse>()
+            .recovery_codes;
+        assert_eq!(recovery_codes.len(), totp::RECOVERY_CODE_COUNT);
+        server
+            .post("/two-factor/setup")
+            .add_header("Authorization", &session_id)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        // The password alone only gets a challenge, and codes can't be replayed
+        let challenge_id = challenge().await;
+        answer(challenge_id, &code)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let next_code = totp::generate_code(&secret, now() + 30);
+        let signed_in: LoginResponse = answer(challenge_id, &next_code).await.json();
+        assert_eq!(signed_in.uuid, registered.uuid);
+        answer(challenge_id, &recovery_codes[0])
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        // Recovery codes work once, however they are typed
+        let challenge_id = challenge().await;
+        answer(
+            challenge_id,
+            &recovery_codes[0].to_uppercase().replace('-', " "),
+        )
+        .await
+        .assert_status_ok();
+        let challenge_id = challenge().await;
+        answer(challenge_id, &recovery_codes[0])
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        // Wrong codes add up across challenges, counting the one above, until a valid code
+        // clears them
+        let challenge_id = challenge().await;
+        for _ in 2..MAX_TWO_FACTOR_ATTEMPTS {
+            answer(challenge_id, "aaaaa-aaaaa")
+                .await
+                .assert_status(StatusCode::UNAUTHORIZED);
+        }
+        answer(challenge_id, &recovery_codes[1])
+            .await
+            .assert_status_ok();
+
+        let status: TwoFactorStatusResponse = server
+            .get("/two-factor")
+            .add_header("Authorization", &session_id)
+            .await
+            .json();
+        assert!(status.enabled);
+        assert_eq!(status.recovery_codes_left, 8);
+        assert!(!status.setup_required);
+
+        let recovery_codes = with_code("/two-factor/recovery-codes", &recovery_codes[2])
+            .await
+            .json::<RecoveryCodesResponse>()
+            .recovery_codes;
+        with_code("/two-factor/disable", "aaaaa-aaaaa")
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        with_code("/two-factor/disable", &recovery_codes[0])
+            .await
+            .assert_status_ok();
+        login().await.assert_status_ok();
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_two_factor_attempts_are_limited_per_user(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router().with_state(state.clone())).unwrap();
+
+        let registered: RegisterResponse = server
+            .post("/register")
+            .json(&test_user("test@example.com"))
+            .await
+            .json();
+        let secret = totp::generate_secret();
+        state
+            .store_pending_totp(registered.uuid, &secret)
+            .await
+            .unwrap();
+        state.enable_totp(registered.uuid, &[]).await.unwrap();
+        let challenge = async || {
+            server
+                .post("/login")
+                .json(&LoginRequest {
+                    email: "test@example.com".to_string(),
+                    password: "test_password".to_string(),
+                })
+                .await
+                .json::<TwoFactorChallengeResponse>()
+                .challenge_id
+        };
+        let answer = |challenge_id, code: &str| {
+            server
+                .post("/login/two-factor")
+                .json(&TwoFactorLoginRequest {
+                    challenge_id,
+                    code: code.to_string(),
+                })
+        };
+        let code = || totp::generate_code(&secret, chrono::Utc::now().timestamp() as u64);
+
+        // Starting over with the password doesn't give more guesses
+        for _ in 0..MAX_TWO_FACTOR_ATTEMPTS {
+            answer(challenge().await, "000000")
+                .await
+                .assert_status(StatusCode::UNAUTHORIZED);
+        }
+        answer(challenge().await, &code())
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+        server
+            .post("/two-factor/disable")
+            .add_header("Authorization", registered.session_id.to_string())
+            .json(&TwoFactorCodeRequest { code: code() })
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+        // Once the lockout is over codes are accepted again
+        sqlx::query("UPDATE user_totp SET locked_until = NOW() WHERE user_id = $1")
+            .bind(registered.uuid)
+            .execute(&state.pg_pool)
+            .await
+            .unwrap();
+        answer(challenge().await, &code()).await.assert_status_ok();
+    }
+
     #[traced_test]
     #[sqlx::test]
     async fn test_me(pool: PgPool) {
@@ -514,7 +1615,6 @@ mod tests {
             name: "Test User".to_string(),
             email: "test@example.com".to_string(),
             password: "test_password".to_string(),
-            is_admin: false,
         };
 
         let response = server.post("/register").json(&register_request).await;
@@ -538,4 +1638,614 @@ mod tests {
         let response = server.post("/me").await;
         response.assert_status(StatusCode::UNAUTHORIZED);
     }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_session_expiry(pool: PgPool) {
+        let state = AppState {
+            session_policy: SessionPolicy {
+                lifetime: chrono::Duration::hours(1),
+                max_lifetime: chrono::Duration::hours(8),
+            },
+            ..crate::router::test_utils::test_state(pool)
+        };
+        let server =
+            TestServer::new(crate::auth::router::router().with_state(state.clone())).unwrap();
+
+        let registered: RegisterResponse = server
+            .post("/register")
+            .json(&test_user("operator@example.com"))
+            .await
+            .json();
+        let session_id = registered.session_id;
+        let set_session = |created_ago: chrono::Duration, expires_in: chrono::Duration| {
+            let now = chrono::Utc::now();
+            sqlx::query!(
+                "UPDATE session SET created_at = $2, expiration = $3 WHERE id = $1",
+                session_id,
+                now - created_ago,
+                now + expires_in
+            )
+            .execute(&state.pg_pool)
+        };
+        let current_session = async || {
+            let sessions: Vec<SessionResponse> = server
+                .get("/sessions")
+                .add_header("Authorization", session_id.to_string())
+                .await
+                .json();
+            sessions
+                .into_iter()
+                .find(|session| session.current)
+                .unwrap()
+        };
+        let minutes_until = |time: chrono::DateTime<chrono::Utc>| {
+            (time - chrono::Utc::now()).num_seconds() as f64 / 60.0
+        };
+
+        // Using the session slides its expiration by the lifetime
+        set_session(chrono::Duration::hours(2), chrono::Duration::minutes(5))
+            .await
+            .unwrap();
+        server
+            .post("/me")
+            .add_header("Authorization", session_id.to_string())
+            .await
+            .assert_status_ok();
+        let session = current_session().await;
+        assert_eq!(session.id, session_id);
+        assert!((59.0..=60.0).contains(&minutes_until(session.expiration)));
+        assert!((359.0..=360.0).contains(&minutes_until(session.max_expiration)));
+
+        // but not past the maximum lifetime
+        set_session(
+            chrono::Duration::minutes(7 * 60 + 30),
+            chrono::Duration::minutes(5),
+        )
+        .await
+        .unwrap();
+        let session = current_session().await;
+        assert!((29.0..=30.0).contains(&minutes_until(session.expiration)));
+        assert_eq!(session.expiration, session.max_expiration);
+
+        // Idle sessions die, and so do ones past the maximum lifetime after a policy change
+        set_session(chrono::Duration::hours(2), chrono::Duration::seconds(-1))
+            .await
+            .unwrap();
+        server
+            .post("/me")
+            .add_header("Authorization", session_id.to_string())
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        set_session(chrono::Duration::hours(9), chrono::Duration::minutes(5))
+            .await
+            .unwrap();
+        server
+            .post("/me")
+            .add_header("Authorization", session_id.to_string())
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_sessions(pool: PgPool) {
+        let server = server(pool);
+
+        let registered: RegisterResponse = server
+            .post("/register")
+            .add_header("User-Agent", "Laptop")
+            .add_header("X-Forwarded-For", "198.51.100.1")
+            .json(&test_user("test@example.com"))
+            .await
+            .json();
+        let laptop = registered.session_id;
+        let login = |user_agent: &'static str, email: &'static str| {
+            let server = &server;
+            async move {
+                server
+                    .post("/login")
+                    .add_header("User-Agent", user_agent)
+                    .add_header("X-Forwarded-For", "203.0.113.7")
+                    .json(&LoginRequest {
+                        email: email.to_string(),
+                        password: "test_password".to_string(),
+                    })
+                    .await
+                    .json::<LoginResponse>()
+                    .session_id
+            }
+        };
+        let phone = login("Phone", "test@example.com").await;
+        let tablet = login("Tablet", "test@example.com").await;
+
+        // Sessions are listed by when they were last used, the laptop's by this very request
+        server
+            .post("/me")
+            .add_header("Authorization", phone.to_string())
+            .await
+            .assert_status_ok();
+        let sessions: Vec<SessionResponse> = server
+            .get("/sessions")
+            .add_header("Authorization", laptop.to_string())
+            .await
+            .json();
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].id, laptop);
+        assert!(sessions[0].current);
+        assert_eq!(sessions[1].id, phone);
+        assert!(!sessions[1].current);
+        assert_eq!(sessions[1].user_agent.as_deref(), Some("Phone"));
+        assert_eq!(sessions[1].client_ip.as_deref(), Some("203.0.113.7"));
+        let laptop_session = &sessions[0];
+        assert_eq!(laptop_session.user_agent.as_deref(), Some("Laptop"));
+        assert_eq!(laptop_session.client_ip.as_deref(), Some("198.51.100.1"));
+
+        server
+            .delete(&format!("/sessions/{phone}"))
+            .add_header("Authorization", laptop.to_string())
+            .await
+            .assert_status_ok();
+        server
+            .post("/me")
+            .add_header("Authorization", phone.to_string())
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        // Sessions of other users can't be revoked
+        server
+            .post("/register")
+            .json(&test_user("other@example.com"))
+            .await
+            .assert_status_ok();
+        let other = login("Other", "other@example.com").await;
+        server
+            .delete(&format!("/sessions/{other}"))
+            .add_header("Authorization", laptop.to_string())
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        let revoked: RevokeOtherSessionsResponse = server
+            .delete("/sessions")
+            .add_header("Authorization", laptop.to_string())
+            .await
+            .json();
+        assert_eq!(revoked.revoked, 1);
+        for (session_id, status) in [
+            (tablet, StatusCode::UNAUTHORIZED),
+            (other, StatusCode::OK),
+            (laptop, StatusCode::OK),
+        ] {
+            server
+                .post("/me")
+                .add_header("Authorization", session_id.to_string())
+                .await
+                .assert_status(status);
+        }
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_oauth_state(pool: PgPool) {
+        let state = crate::router::test_utils::test_state(pool);
+        let server =
+            TestServer::new(crate::auth::router::router().with_state(state.clone())).unwrap();
+
+        let response = server.get("/oauth/google").await;
+        response.assert_status(StatusCode::OK);
+        let oauth_url: OAuthUrlResponse = response.json();
+
+        let url = url::Url::parse(&oauth_url.authorization_url).unwrap();
+        let param = |name| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        assert_eq!(param("state"), Some(oauth_url.state.clone()));
+        assert_eq!(param("code_challenge_method").as_deref(), Some("S256"));
+        assert!(param("code_challenge").is_some());
+
+        // Callbacks without the state issued to the browser are refused before the code is used
+        for path in ["/callback?code=code", "/callback?code=code&state=forged"] {
+            let response = server.get(path).await;
+            response.assert_status(StatusCode::BAD_REQUEST);
+            response.assert_json(&json!({
+                "error": "Invalid or expired OAuth state"
+            }));
+        }
+
+        // States are single use and bound to the provider they were issued for
+        assert!(
+            state
+                .take_oauth_state(&oauth_url.state, AuthProvider::GitHub, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            state
+                .take_oauth_state(&oauth_url.state, AuthProvider::Google, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let issued = OAuthState::new(
+            AuthProvider::Google,
+            "issued".to_string(),
+            "verifier".to_string(),
+        );
+        state.store_oauth_state(&issued).await.unwrap();
+        let taken = state
+            .take_oauth_state("issued", AuthProvider::Google, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(taken.pkce_verifier, "verifier");
+        assert!(
+            state
+                .take_oauth_state("issued", AuthProvider::Google, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let mut expired = OAuthState::new(
+            AuthProvider::Google,
+            "expired".to_string(),
+            "verifier".to_string(),
+        );
+        expired.expires_at = chrono::Utc::now() - chrono::Duration::seconds(1);
+        state.store_oauth_state(&expired).await.unwrap();
+        assert!(
+            state
+                .take_oauth_state("expired", AuthProvider::Google, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    /// What the mock GitHub answers with, and the PKCE verifier it was sent
+    struct MockGitHub {
+        user_id: u64,
+        emails: Value,
+        code_verifier: Option<String>,
+    }
+
+    /// Serves GitHub's token, user and emails endpoints, returning the base URL of both the OAuth
+    /// endpoints and the API
+    async fn mock_github(mock: Arc<Mutex<MockGitHub>>) -> Url {
+        async fn access_token(
+            State(mock): State<Arc<Mutex<MockGitHub>>>,
+            Form(form): Form<HashMap<String, String>>,
+        ) -> Json<Value> {
+            mock.lock().unwrap().code_verifier = form.get("code_verifier").cloned();
+            Json(json!({
+                "access_token": "mock-token",
+                "token_type": "bearer",
+                "scope": "read:user,user:email"
+            }))
+        }
+
+        async fn user(State(mock): State<Arc<Mutex<MockGitHub>>>) -> Json<Value> {
+            let user_id = mock.lock().unwrap().user_id;
+            Json(json!({ "id": user_id, "login": "octocat", "name": null }))
+        }
+
+        async fn emails(State(mock): State<Arc<Mutex<MockGitHub>>>) -> Json<Value> {
+            Json(mock.lock().unwrap().emails.clone())
+        }
+
+        let app = Router::new()
+            .route("/login/oauth/access_token", post(access_token))
+            .route("/user", get(user))
+            .route("/user/emails", get(emails))
+            .with_state(mock);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url.parse().unwrap()
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_github_oauth(pool: PgPool) {
+        let mock = Arc::new(Mutex::new(MockGitHub {
+            user_id: 4242,
+            emails: json!([
+                { "email": "public@example.com", "primary": true, "verified": false },
+                { "email": "octocat@example.com", "primary": false, "verified": true }
+            ]),
+            code_verifier: None,
+        }));
+        let github_url = mock_github(mock.clone()).await;
+        let state = AppState {
+            github_oauth: GitHubOAuthClient::new(
+                "test-client-id".to_string(),
+                "test-client-secret".to_string(),
+                "http://localhost:8080/auth/oauth/github/callback".to_string(),
+                &github_url,
+                github_url.clone(),
+            )
+            .unwrap(),
+            ..crate::router::test_utils::test_state(pool)
+        };
+        let server =
+            TestServer::new(crate::auth::router::router().with_state(state.clone())).unwrap();
+
+        let registered: RegisterResponse = server
+            .post("/register")
+            .json(&test_user("octocat@example.com"))
+            .await
+            .json();
+
+        let login = || async {
+            let oauth_url: OAuthUrlResponse = server.get("/oauth/github").await.json();
+            let url = Url::parse(&oauth_url.authorization_url).unwrap();
+            assert!(url.as_str().starts_with(github_url.as_str()));
+            let challenge = url
+                .query_pairs()
+                .find(|(key, _)| key == "code_challenge")
+                .map(|(_, value)| value.into_owned())
+                .unwrap();
+
+            let response = server
+                .get(&format!(
+                    "/oauth/github/callback?code=code&state={}",
+                    oauth_url.state
+                ))
+                .await;
+
+            // The code was exchanged with the verifier of the challenge the flow started with
+            let verifier = mock.lock().unwrap().code_verifier.take().unwrap();
+            assert_eq!(
+                PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(verifier))
+                    .as_str(),
+                challenge
+            );
+            response
+        };
+
+        // Whoever registered the email may not own it, so it isn't linked until verified
+        login().await.assert_status(StatusCode::CONFLICT);
+        assert!(
+            state
+                .get_key(AuthProvider::GitHub, "4242")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        state.mark_email_verified(registered.uuid).await.unwrap();
+
+        // The verified email links the GitHub account to the existing one, not the public email
+        let response = login().await;
+        response.assert_status(StatusCode::OK);
+        let signed_in: OAuthCallbackResponse = response.json();
+        assert_eq!(signed_in.user_id, registered.uuid);
+        assert!(!signed_in.is_new_user);
+        let key = state
+            .get_key(AuthProvider::GitHub, "4242")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.user_id, registered.uuid);
+
+        let signed_in: OAuthCallbackResponse = login().await.json();
+        assert_eq!(signed_in.user_id, registered.uuid);
+
+        // Accounts without a verified email can't sign in
+        {
+            let mut mock = mock.lock().unwrap();
+            mock.user_id = 7;
+            mock.emails = json!([
+                { "email": "new@example.com", "primary": true, "verified": false }
+            ]);
+        }
+        login().await.assert_status(StatusCode::FORBIDDEN);
+
+        mock.lock().unwrap().emails = json!([
+            { "email": "new@example.com", "primary": true, "verified": true }
+        ]);
+        let signed_in: OAuthCallbackResponse = login().await.json();
+        assert!(signed_in.is_new_user);
+        assert_eq!(signed_in.email, "new@example.com");
+        assert_eq!(signed_in.name, "octocat");
+    }
+
+    /// What the mock OIDC provider puts in the ID tokens it issues
+    struct MockOidc {
+        issuer: String,
+        audience: String,
+        nonce: Option<String>,
+        email_verified: bool,
+        /// Signs with the client secret instead of the published key
+        symmetric: bool,
+    }
+
+    const MOCK_OIDC_KEY_ID: &str = "mock-key";
+
+    /// Serves a Keycloak like realm's discovery document, keys and token endpoint, returning its
+    /// issuer
+    async fn mock_oidc(mock: Arc<Mutex<MockOidc>>) -> String {
+        async fn discovery(State(mock): State<Arc<Mutex<MockOidc>>>) -> Json<Value> {
+            let issuer = mock.lock().unwrap().issuer.clone();
+            Json(json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{issuer}/protocol/openid-connect/auth"),
+                "token_endpoint": format!("{issuer}/protocol/openid-connect/token"),
+                "jwks_uri": format!("{issuer}/protocol/openid-connect/certs"),
+            }))
+        }
+
+        async fn certs() -> Json<Value> {
+            let key = EncodingKey::from_rsa_pem(ValidationSigner::TEST_PRIVATE_KEY).unwrap();
+            let mut jwk = Jwk::from_encoding_key(&key, Algorithm::RS256).unwrap();
+            jwk.common.key_id = Some(MOCK_OIDC_KEY_ID.to_string());
+            Json(json!({ "keys": [jwk] }))
+        }
+
+        async fn token(State(mock): State<Arc<Mutex<MockOidc>>>) -> Json<Value> {
+            let mock = mock.lock().unwrap();
+            let now = chrono::Utc::now().timestamp();
+            let claims = json!({
+                "iss": mock.issuer,
+                "aud": mock.audience,
+                "sub": "agency-user-1",
+                "iat": now,
+                "exp": now + 300,
+                "nonce": mock.nonce,
+                "email": "member@agency.example",
+                "email_verified": mock.email_verified,
+                "preferred_username": "member",
+            });
+            let (mut header, key) = if mock.symmetric {
+                (
+                    Header::new(Algorithm::HS256),
+                    EncodingKey::from_secret(b"test-client-secret"),
+                )
+            } else {
+                (
+                    Header::new(Algorithm::RS256),
+                    EncodingKey::from_rsa_pem(ValidationSigner::TEST_PRIVATE_KEY).unwrap(),
+                )
+            };
+            header.kid = Some(MOCK_OIDC_KEY_ID.to_string());
+
+            Json(json!({
+                "access_token": "mock-token",
+                "token_type": "Bearer",
+                "id_token": jsonwebtoken::encode(&header, &claims, &key).unwrap(),
+            }))
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}/realms/energy", listener.local_addr().unwrap());
+        mock.lock().unwrap().issuer = issuer.clone();
+
+        let realm = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/protocol/openid-connect/certs", get(certs))
+            .route("/protocol/openid-connect/token", post(token));
+        let app = Router::new().nest("/realms/energy", realm).with_state(mock);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        issuer
+    }
+
+    /// Goes through a login with the `agency` provider, letting `tamper` change the ID token the
+    /// provider returns
+    async fn oidc_login(
+        server: &TestServer,
+        mock: &Mutex<MockOidc>,
+        tamper: impl FnOnce(&mut MockOidc),
+    ) -> TestResponse {
+        let oauth_url: OAuthUrlResponse = server.get("/oidc/agency").await.json();
+        let url = Url::parse(&oauth_url.authorization_url).unwrap();
+        let nonce = url
+            .query_pairs()
+            .find(|(key, _)| key == "nonce")
+            .map(|(_, value)| value.into_owned());
+        assert!(nonce.is_some());
+
+        {
+            let mut mock = mock.lock().unwrap();
+            mock.audience = "petall".to_string();
+            mock.nonce = nonce;
+            mock.email_verified = true;
+            mock.symmetric = false;
+            tamper(&mut mock);
+        }
+
+        server
+            .get(&format!(
+                "/oidc/agency/callback?code=code&state={}",
+                oauth_url.state
+            ))
+            .await
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn test_oidc(pool: PgPool) {
+        let mock = Arc::new(Mutex::new(MockOidc {
+            issuer: String::new(),
+            audience: String::new(),
+            nonce: None,
+            email_verified: true,
+            symmetric: false,
+        }));
+        let issuer = mock_oidc(mock.clone()).await;
+        let state = AppState {
+            oidc_providers: Arc::new(vec![OidcClient::new(OidcProviderConfig {
+                name: "agency".to_string(),
+                display_name: "Energy agency".to_string(),
+                issuer: issuer.clone(),
+                client_id: "petall".to_string(),
+                client_secret: "test-client-secret".to_string(),
+                redirect_url: "http://localhost:4173/oauth/oidc/agency/callback".to_string(),
+                scopes: vec!["email".to_string(), "profile".to_string()],
+            })]),
+            ..crate::router::test_utils::test_state(pool)
+        };
+        let server =
+            TestServer::new(crate::auth::router::router().with_state(state.clone())).unwrap();
+
+        let providers: Vec<OidcProviderResponse> = server.get("/oidc").await.json();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].name, "agency");
+        assert_eq!(providers[0].display_name, "Energy agency");
+        server
+            .get("/oidc/unknown")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        let oauth_url: OAuthUrlResponse = server.get("/oidc/agency").await.json();
+        let url = Url::parse(&oauth_url.authorization_url).unwrap();
+        assert!(url.as_str().starts_with(&issuer));
+        let scope = url
+            .query_pairs()
+            .find(|(key, _)| key == "scope")
+            .map(|(_, value)| value.into_owned());
+        assert_eq!(scope.as_deref(), Some("openid email profile"));
+
+        // States are bound to the provider they were issued for
+        server
+            .get(&format!("/callback?code=code&state={}", oauth_url.state))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        let response = oidc_login(&server, &mock, |_| {}).await;
+        response.assert_status(StatusCode::OK);
+        let signed_in: OAuthCallbackResponse = response.json();
+        assert!(signed_in.is_new_user);
+        assert_eq!(signed_in.email, "member@agency.example");
+        assert_eq!(signed_in.name, "member");
+        let key = state
+            .get_key(AuthProvider::Oidc, &format!("{issuer}|agency-user-1"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.user_id, signed_in.user_id);
+
+        let again: OAuthCallbackResponse = oidc_login(&server, &mock, |_| {}).await.json();
+        assert_eq!(again.user_id, signed_in.user_id);
+        assert!(!again.is_new_user);
+
+        // ID tokens of another login, for another client or not signed with the provider's keys
+        // are refused
+        let tampered: [fn(&mut MockOidc); 3] = [
+            |mock| mock.nonce = Some("replayed".to_string()),
+            |mock| mock.audience = "other-client".to_string(),
+            |mock| mock.symmetric = true,
+        ];
+        for tamper in tampered {
+            oidc_login(&server, &mock, tamper)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+
+        oidc_login(&server, &mock, |mock| mock.email_verified = false)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
 }
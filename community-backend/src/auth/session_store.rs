@@ -1,17 +1,26 @@
 use uuid::Uuid;
 
-use crate::{AppState, auth::Session, error::AppResult};
+use crate::{
+    AppState,
+    auth::{Session, SessionDevice},
+    error::AppResult,
+};
 
 impl AppState {
     pub async fn store_session(&self, session: &Session) -> AppResult<()> {
         sqlx::query!(
             r#"
-            INSERT INTO session (id, user_id, expiration)
-            VALUES ($1, $2, $3)
+            INSERT INTO session
+                (id, user_id, created_at, last_seen_at, expiration, user_agent, client_ip)
+            VALUES ($1, $2, $3, $4, $5, $6, $7::text::inet)
             "#,
             session.id,
             session.user_id,
-            session.expiration
+            session.created_at,
+            session.last_seen_at,
+            session.expiration,
+            session.user_agent,
+            session.client_ip
         )
         .execute(&self.pg_pool)
         .await?;
@@ -19,20 +28,54 @@ impl AppState {
         Ok(())
     }
 
+    /// Starts a new session for the user on `device`
+    pub async fn create_session(&self, user_id: Uuid, device: SessionDevice) -> AppResult<Session> {
+        let session = Session::new_random_from(user_id, &self.session_policy, device);
+        self.store_session(&session).await?;
+        Ok(session)
+    }
+
+    /// Returns the session if it has not expired, marking it as seen and extending it by the
+    /// session lifetime up to its maximum lifetime
     pub async fn get_valid_session(&self, session_id: Uuid) -> AppResult<Option<Session>> {
         sqlx::query_as!(
             Session,
             r#"
-            SELECT id, user_id, expiration FROM session
-            WHERE id = $1 AND expiration > NOW()
+            UPDATE session
+            SET last_seen_at = NOW(),
+                expiration = LEAST(NOW() + $2::interval, created_at + $3::interval)
+            WHERE id = $1 AND expiration > NOW() AND created_at + $3::interval > NOW()
+            RETURNING id, user_id, created_at, last_seen_at, expiration, user_agent,
+                host(client_ip) AS client_ip
             "#,
-            session_id
+            session_id,
+            self.session_policy.lifetime as _,
+            self.session_policy.max_lifetime as _
         )
         .fetch_optional(&self.pg_pool)
         .await
         .map_err(Into::into)
     }
 
+    /// The user's sessions that have not expired, most recently used first
+    pub async fn get_user_sessions(&self, user_id: Uuid) -> AppResult<Vec<Session>> {
+        sqlx::query_as!(
+            Session,
+            r#"
+            SELECT id, user_id, created_at, last_seen_at, expiration, user_agent,
+                host(client_ip) AS client_ip
+            FROM session
+            WHERE user_id = $1 AND expiration > NOW() AND created_at + $2::interval > NOW()
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id,
+            self.session_policy.max_lifetime as _
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+        .map_err(Into::into)
+    }
+
     pub async fn delete_session(&self, session_id: Uuid) -> AppResult<bool> {
         let result = sqlx::query!(
             r#"
@@ -47,6 +90,22 @@ impl AppState {
         Ok(result.rows_affected() != 0)
     }
 
+    /// Deletes the session if it belongs to the user
+    pub async fn delete_user_session(&self, user_id: Uuid, session_id: Uuid) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM session
+            WHERE id = $1 AND user_id = $2
+            "#,
+            session_id,
+            user_id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+
     pub async fn delete_all_sessions(&self, user_id: Uuid) -> AppResult<()> {
         sqlx::query!(
             r#"
@@ -60,4 +119,20 @@ impl AppState {
 
         Ok(())
     }
+
+    /// Deletes every session of the user but `keep`, returning how many there were
+    pub async fn delete_other_sessions(&self, user_id: Uuid, keep: Uuid) -> AppResult<u64> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM session
+            WHERE user_id = $1 AND id <> $2
+            "#,
+            user_id,
+            keep
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
 }
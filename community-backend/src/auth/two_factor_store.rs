@@ -0,0 +1,376 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{hash_token, totp},
+    error::{AppError, AppResult},
+};
+
+/// How long a user has to enter their second factor after their password
+pub const TWO_FACTOR_CHALLENGE_TTL: chrono::Duration = chrono::Duration::minutes(5);
+
+/// How many wrong second factor codes a user can enter in a row, across all their login
+/// challenges and sessions, before codes are refused for [`TWO_FACTOR_LOCKOUT`]
+pub const MAX_TWO_FACTOR_ATTEMPTS: i32 = 5;
+
+pub const TWO_FACTOR_LOCKOUT: chrono::Duration = chrono::Duration::minutes(15);
+
+/// A login of a user with two-factor authentication, waiting for their code
+pub struct LoginChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A user's TOTP secret, which only counts once [`UserTotp::enabled_at`] is set
+pub struct UserTotp {
+    pub secret: Vec<u8>,
+    pub enabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Time step of the last accepted code, so codes can't be replayed
+    pub last_used_step: Option<i64>,
+}
+
+/// Authentication rules admins set for everyone
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthPolicy {
+    /// Managers and admins can't use the admin endpoints until they enable two-factor
+    /// authentication
+    pub require_staff_two_factor: bool,
+}
+
+impl AppState {
+    pub async fn get_user_totp(&self, user_id: Uuid) -> AppResult<Option<UserTotp>> {
+        sqlx::query_as!(
+            UserTotp,
+            r#"
+            SELECT secret, enabled_at, last_used_step FROM user_totp
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn has_two_factor(&self, user_id: Uuid) -> AppResult<bool> {
+        Ok(self
+            .get_user_totp(user_id)
+            .await?
+            .is_some_and(|totp| totp.enabled_at.is_some()))
+    }
+
+    /// Stores a secret waiting to be confirmed with a code, replacing an earlier pending one.
+    /// Returns false, leaving things as they are, if two-factor authentication is already enabled.
+    pub async fn store_pending_totp(&self, user_id: Uuid, secret: &[u8]) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, last_used_step = NULL
+            WHERE user_totp.enabled_at IS NULL
+            "#,
+            user_id,
+            secret
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Records that a code of time step `step` was used, returning false if that step or a later
+    /// one already was
+    pub async fn use_totp_step(&self, user_id: Uuid, step: i64) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+            user_id,
+            step
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Turns on the pending secret, and replaces the recovery codes with `recovery_codes`
+    pub async fn enable_totp(&self, user_id: Uuid, recovery_codes: &[String]) -> AppResult<()> {
+        let mut tx = self.pg_pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET enabled_at = NOW()
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        replace_recovery_codes(&mut tx, user_id, recovery_codes).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Turns off two-factor authentication, dropping the secret and recovery codes
+    pub async fn disable_totp(&self, user_id: Uuid) -> AppResult<()> {
+        let mut tx = self.pg_pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM user_totp
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        replace_recovery_codes(&mut tx, user_id, &[]).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn set_recovery_codes(&self, user_id: Uuid, codes: &[String]) -> AppResult<()> {
+        let mut tx = self.pg_pool.begin().await?;
+        replace_recovery_codes(&mut tx, user_id, codes).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn count_recovery_codes(&self, user_id: Uuid) -> AppResult<i64> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM totp_recovery_code
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pg_pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Removes the recovery code so it can only be used once, returning whether the user had it
+    pub async fn consume_recovery_code(&self, user_id: Uuid, code: &str) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM totp_recovery_code
+            WHERE user_id = $1 AND code_hash = $2
+            "#,
+            user_id,
+            hash_token(&totp::normalize_recovery_code(code))
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Counts an attempt at the user's second factor before it is checked, so concurrent guesses
+    /// can't get past [`MAX_TWO_FACTOR_ATTEMPTS`]. The attempt that reaches it locks codes out for
+    /// [`TWO_FACTOR_LOCKOUT`], and the first one after that starts counting again. Returns false if
+    /// the user is locked out or doesn't have two-factor authentication.
+    async fn reserve_two_factor_attempt(&self, user_id: Uuid) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET failed_attempts = CASE
+                    WHEN locked_until IS NOT NULL THEN 1
+                    ELSE failed_attempts + 1
+                END,
+                locked_until = CASE
+                    WHEN locked_until IS NULL AND failed_attempts + 1 >= $2 THEN $3::TIMESTAMPTZ
+                    ELSE NULL
+                END
+            WHERE user_id = $1
+                AND enabled_at IS NOT NULL
+                AND (locked_until IS NULL OR locked_until <= NOW())
+            "#,
+            user_id,
+            MAX_TWO_FACTOR_ATTEMPTS,
+            chrono::Utc::now() + TWO_FACTOR_LOCKOUT
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn reset_two_factor_attempts(&self, user_id: Uuid) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET failed_attempts = 0, locked_until = NULL
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Checks a code from the user's authenticator, or one of their recovery codes, using it up.
+    /// Every check counts against the user's attempts, whichever login challenge or session it
+    /// comes from, and a valid code clears them.
+    pub async fn verify_second_factor(&self, user_id: Uuid, code: &str) -> AppResult<bool> {
+        if !self.reserve_two_factor_attempt(user_id).await? {
+            return if self.has_two_factor(user_id).await? {
+                Err(AppError::TooManyTwoFactorAttempts)
+            } else {
+                Ok(false)
+            };
+        }
+
+        let verified = self.check_second_factor(user_id, code.trim()).await?;
+        if verified {
+            self.reset_two_factor_attempts(user_id).await?;
+        }
+        Ok(verified)
+    }
+
+    async fn check_second_factor(&self, user_id: Uuid, code: &str) -> AppResult<bool> {
+        if !totp::is_totp_code(code) {
+            return self.consume_recovery_code(user_id, code).await;
+        }
+
+        let Some(user_totp) = self.get_user_totp(user_id).await? else {
+            return Ok(false);
+        };
+        let now = chrono::Utc::now()
+            .timestamp()
+            .try_into()
+            .unwrap_or_default();
+        match totp::verify_code(&user_totp.secret, code, now) {
+            Some(step) => self.use_totp_step(user_id, step as i64).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Starts a login waiting for the user's second factor
+    pub async fn create_login_challenge(&self, user_id: Uuid) -> AppResult<LoginChallenge> {
+        // Also drop abandoned challenges while at it
+        sqlx::query!(
+            r#"
+            DELETE FROM login_challenge
+            WHERE expires_at <= NOW()
+            "#
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        sqlx::query_as!(
+            LoginChallenge,
+            r#"
+            INSERT INTO login_challenge (id, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            RETURNING id, user_id, expires_at
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            chrono::Utc::now() + TWO_FACTOR_CHALLENGE_TTL
+        )
+        .fetch_one(&self.pg_pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn get_login_challenge(&self, id: Uuid) -> AppResult<Option<LoginChallenge>> {
+        sqlx::query_as!(
+            LoginChallenge,
+            r#"
+            SELECT id, user_id, expires_at FROM login_challenge
+            WHERE id = $1 AND expires_at > NOW()
+            "#,
+            id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Removes the challenge once answered, returning false if it was already
+    pub async fn delete_login_challenge(&self, id: Uuid) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM login_challenge
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_auth_policy(&self) -> AppResult<AuthPolicy> {
+        sqlx::query_as!(
+            AuthPolicy,
+            r#"
+            SELECT require_staff_two_factor FROM auth_policy
+            "#
+        )
+        .fetch_one(&self.pg_pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn set_auth_policy(&self, policy: AuthPolicy) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE auth_policy
+            SET require_staff_two_factor = $1
+            "#,
+            policy.require_staff_two_factor
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+async fn replace_recovery_codes(
+    tx: &mut sqlx::PgTransaction<'_>,
+    user_id: Uuid,
+    codes: &[String],
+) -> AppResult<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM totp_recovery_code
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_token(&totp::normalize_recovery_code(code)))
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_code (user_id, code_hash)
+        SELECT $1, UNNEST($2::text[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
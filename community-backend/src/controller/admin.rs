@@ -1,3 +1,4 @@
+use common::allocation::{DistributionRule, ProofScheme};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -20,14 +21,37 @@ impl AppState {
         sqlx::query_as!(
             User,
             r#"
-            SELECT id, name, email, is_admin
+            SELECT id, name, email, is_admin, email_verified_at
             FROM "user"
             WHERE is_admin = TRUE
             "#
         )
         .fetch_all(&self.pg_pool)
         .await
-        .map_err(Into::into)
+    }
+
+    /// Grants or revokes admin rights over every community
+    pub async fn set_admin(&self, user_id: Uuid, is_admin: bool) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET is_admin = $2
+            WHERE id = $1
+            "#,
+            user_id,
+            is_admin
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether the auth policy asks the user to enable two-factor authentication, because they
+    /// can manage communities
+    pub async fn requires_two_factor(&self, user: &User) -> AppResult<bool> {
+        Ok(self.get_auth_policy().await?.require_staff_two_factor
+            && self.can_access_admin_view(user).await?)
     }
 
     /// Determines if a user has access to the admin view in the frontend.
@@ -75,22 +99,18 @@ impl AppState {
         is_admin: bool,
     ) -> AppResult<Vec<AdminListCommunityView>> {
         let communities = if is_admin {
-            sqlx::query_as!(
-                Community,
-                "
-                SELECT id, name, description, image FROM community
-                "
-            )
-            .fetch_all(&self.pg_pool)
-            .await?
+            self.get_communities().await?
         } else {
             sqlx::query_as!(
                 Community,
-                "
-                SELECT c.id, c.name, c.description, c.image FROM community c
+                r#"
+                SELECT c.id, c.name, c.description, c.image,
+                c.distribution_rule AS "distribution_rule: DistributionRule",
+                c.proof_scheme AS "proof_scheme: ProofScheme"
+                FROM community c
                 JOIN community_manager cm ON c.id = cm.community_id
                 WHERE cm.user_id = $1
-                ",
+                "#,
                 user_id
             )
             .fetch_all(&self.pg_pool)
@@ -1,9 +1,15 @@
 use crate::AppState;
 use crate::error::{AppError, AppResult};
 use crate::models::{Community, User, UserCommunity};
-use crate::router::community::{EnergyStats, OrderDirection, StatsFilter, StatsGranularity};
-use chrono::{Duration, NaiveDateTime, Utc};
-use common::EnergyRecord;
+use crate::router::community::{
+    EnergyFilter, EnergyStats, OrderDirection, StatsFilter, StatsGranularity,
+};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use common::{
+    EnergyRecord,
+    allocation::{DistributionRule, ProofScheme},
+};
 use serde::{Deserialize, Serialize};
 use sqlx::{QueryBuilder, Row};
 use uuid::Uuid;
@@ -11,10 +17,29 @@ use uuid::Uuid;
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PaginatedEnergyRecords {
-    pub records: Vec<EnergyRecord>,
+    pub records: Vec<ListedEnergyRecord>,
     pub total_count: i64,
 }
 
+/// An energy record, along with when it became part of a committed snapshot. Validation tokens
+/// are only handed out from then on.
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ListedEnergyRecord {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub record: EnergyRecord,
+    pub verifiable_since: Option<DateTime<Utc>>,
+}
+
+/// Totals of a user's records in a community over a period
+#[derive(Debug)]
+pub struct EnergyTotals {
+    pub record_count: i64,
+    pub generated: BigDecimal,
+    pub consumed: BigDecimal,
+}
+
 impl AppState {
     pub async fn get_communities_with_user_energy_records(
         &self,
@@ -27,6 +52,8 @@ impl AppState {
                 c.name,
                 c.description,
                 c.image,
+                c.distribution_rule AS "distribution_rule: DistributionRule",
+                c.proof_scheme AS "proof_scheme: ProofScheme",
                 EXISTS (
                     SELECT 1 FROM community_user cu
                     WHERE cu.community_id = c.id
@@ -53,6 +80,8 @@ impl AppState {
                         name: row.name,
                         description: row.description,
                         image: row.image,
+                        distribution_rule: row.distribution_rule,
+                        proof_scheme: row.proof_scheme,
                     },
                     row.is_present,
                 )
@@ -65,18 +94,24 @@ impl AppState {
         name: &str,
         description: &str,
         image: Option<&str>,
+        distribution_rule: DistributionRule,
+        proof_scheme: ProofScheme,
     ) -> AppResult<Community> {
         let community = sqlx::query_as!(
             Community,
             r#"
             INSERT INTO community
-            (name, description, image)
-            VALUES ($1, $2, $3)
-            RETURNING *
+            (name, description, image, distribution_rule, proof_scheme)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, description, image,
+            distribution_rule AS "distribution_rule: DistributionRule",
+            proof_scheme AS "proof_scheme: ProofScheme"
             "#,
             name,
             description,
-            image
+            image,
+            distribution_rule as DistributionRule,
+            proof_scheme as ProofScheme
         )
         .fetch_one(&self.pg_pool)
         .await
@@ -90,11 +125,28 @@ impl AppState {
         Ok(community)
     }
 
+    pub async fn get_communities(&self) -> sqlx::Result<Vec<Community>> {
+        sqlx::query_as!(
+            Community,
+            r#"
+            SELECT id, name, description, image,
+            distribution_rule AS "distribution_rule: DistributionRule",
+            proof_scheme AS "proof_scheme: ProofScheme"
+            FROM community
+            "#
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+    }
+
     pub async fn get_community_by_id(&self, id: Uuid) -> sqlx::Result<Option<Community>> {
         sqlx::query_as!(
             Community,
             r#"
-            SELECT id, name, description, image FROM community
+            SELECT id, name, description, image,
+            distribution_rule AS "distribution_rule: DistributionRule",
+            proof_scheme AS "proof_scheme: ProofScheme"
+            FROM community
             WHERE id = $1
             "#,
             id,
@@ -107,7 +159,7 @@ impl AppState {
         sqlx::query_as!(
             User,
             r#"
-            SELECT u.id, u.name, u.email, u.is_admin FROM "user" u
+            SELECT u.id, u.name, u.email, u.is_admin, u.email_verified_at FROM "user" u
             JOIN community_user cu ON u.id = cu.user_id
             WHERE cu.community_id = $1
             "#,
@@ -121,7 +173,7 @@ impl AppState {
         sqlx::query_as!(
             User,
             r#"
-            SELECT u.id, u.name, u.email, u.is_admin FROM "user" u
+            SELECT u.id, u.name, u.email, u.is_admin, u.email_verified_at FROM "user" u
             JOIN community_manager cm ON u.id = cm.user_id
             WHERE cm.community_id = $1
             "#,
@@ -169,7 +221,7 @@ impl AppState {
         Ok(user)
     }
 
-    pub async fn insert_energy_records(&self, records: &Vec<EnergyRecord>) -> sqlx::Result<()> {
+    pub async fn insert_energy_records(&self, records: &[EnergyRecord]) -> sqlx::Result<()> {
         const CHUNK_SIZE: usize = 1000; // estava a chegar a limite de argumentos para a query
 
         for chunk in records.chunks(CHUNK_SIZE) {
@@ -289,11 +341,7 @@ impl AppState {
         &self,
         user_id: Uuid,
         community_id: Uuid,
-        page: u32,
-        size: u32,
-        order_dir: OrderDirection,
-        start: Option<NaiveDateTime>,
-        end: Option<NaiveDateTime>,
+        filter: &EnergyFilter,
     ) -> sqlx::Result<PaginatedEnergyRecords> {
         let mut count_builder = QueryBuilder::new(
             r#"
@@ -306,12 +354,12 @@ impl AppState {
         count_builder.push(" AND community_id = ");
         count_builder.push_bind(community_id);
 
-        if let Some(start_time) = start {
+        if let Some(start_time) = filter.start {
             count_builder.push(" AND start >= ");
             count_builder.push_bind(start_time);
         }
 
-        if let Some(end_time) = end {
+        if let Some(end_time) = filter.end {
             count_builder.push(" AND start <= ");
             count_builder.push_bind(end_time);
         }
@@ -320,7 +368,8 @@ impl AppState {
 
         let mut query_builder = QueryBuilder::new(
             r#"
-            SELECT id, user_id, community_id, generated, consumed, consumer_price, seller_price, start
+            SELECT id, user_id, community_id, generated, consumed, consumer_price, seller_price, start,
+                verifiable_since
             FROM energy_record
             WHERE user_id = "#,
         );
@@ -329,26 +378,30 @@ impl AppState {
         query_builder.push(" AND community_id = ");
         query_builder.push_bind(community_id);
 
-        if let Some(start_time) = start {
+        if let Some(start_time) = filter.start {
             query_builder.push(" AND start >= ");
             query_builder.push_bind(start_time);
         }
 
-        if let Some(end_time) = end {
+        if let Some(end_time) = filter.end {
             query_builder.push(" AND start <= ");
             query_builder.push_bind(end_time);
         }
 
-        let order_dir = match order_dir {
+        let order_dir = match filter.order_dir {
             OrderDirection::Ascending => "ASC",
             OrderDirection::Descending => "DESC",
         };
 
         query_builder.push(format!(" ORDER BY start {}", order_dir));
-        query_builder.push(format!(" LIMIT {} OFFSET {}", size, (page - 1) * size));
+        query_builder.push(format!(
+            " LIMIT {} OFFSET {}",
+            filter.size,
+            (filter.page - 1) * filter.size
+        ));
 
         let records = query_builder
-            .build_query_as::<EnergyRecord>()
+            .build_query_as::<ListedEnergyRecord>()
             .fetch_all(&self.pg_pool)
             .await?;
 
@@ -425,11 +478,100 @@ impl AppState {
     ) -> AppResult<Option<EnergyRecord>> {
         sqlx::query_as!(
             EnergyRecord,
-            "SELECT * FROM energy_record WHERE id = $1",
+            r#"
+            SELECT id, user_id, community_id, generated, consumed, consumer_price, seller_price, start
+            FROM energy_record
+            WHERE id = $1
+            "#,
             energy_record_id
         )
         .fetch_optional(&self.pg_pool)
         .await
         .map_err(Into::into)
     }
+
+    pub async fn get_all_energy_records(&self) -> AppResult<Vec<EnergyRecord>> {
+        sqlx::query_as!(
+            EnergyRecord,
+            r#"
+            SELECT id, user_id, community_id, generated, consumed, consumer_price, seller_price, start
+            FROM energy_record
+            "#
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Totals of the records starting within `[start, end)` that are in a committed snapshot, the
+    /// ones the ZK service can prove the totals of
+    pub async fn get_user_committed_energy_totals(
+        &self,
+        user_id: Uuid,
+        community_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> sqlx::Result<EnergyTotals> {
+        sqlx::query_as!(
+            EnergyTotals,
+            r#"
+            SELECT
+                COUNT(*) AS "record_count!",
+                COALESCE(SUM(generated), 0) AS "generated!",
+                COALESCE(SUM(consumed), 0) AS "consumed!"
+            FROM energy_record
+            WHERE user_id = $1 AND community_id = $2 AND start >= $3 AND start < $4
+                AND verifiable_since IS NOT NULL
+            "#,
+            user_id,
+            community_id,
+            start,
+            end
+        )
+        .fetch_one(&self.pg_pool)
+        .await
+    }
+
+    /// Which of `energy_record_ids` belong to the user
+    pub async fn get_user_energy_record_ids(
+        &self,
+        user_id: Uuid,
+        energy_record_ids: &[Uuid],
+    ) -> sqlx::Result<Vec<Uuid>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT id
+            FROM energy_record
+            WHERE user_id = $1 AND id = ANY($2)
+            "#,
+            user_id,
+            energy_record_ids
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+    }
+
+    /// IDs of the user's records in a community starting within `[start, end)`, in order
+    pub async fn get_user_energy_record_ids_in_period(
+        &self,
+        user_id: Uuid,
+        community_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> sqlx::Result<Vec<Uuid>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT id
+            FROM energy_record
+            WHERE user_id = $1 AND community_id = $2 AND start >= $3 AND start < $4
+            ORDER BY start, id
+            "#,
+            user_id,
+            community_id,
+            start,
+            end
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+    }
 }
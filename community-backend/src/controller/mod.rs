@@ -1,3 +1,6 @@
 pub mod admin;
 pub mod community;
+pub mod snapshot_commit;
 pub mod user;
+pub mod validation_request;
+pub mod validation_token;
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use common::snapshot::CommittedRecords;
+use uuid::Uuid;
+
+use crate::AppState;
+
+impl AppState {
+    /// Marks records as verifiable from the time the ZK service committed them. Records keep the
+    /// time of the first snapshot they were in.
+    pub async fn record_committed_records(&self, committed: &CommittedRecords) -> sqlx::Result<()> {
+        let mut tx = self.pg_pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE energy_record
+            SET verifiable_since = $1
+            WHERE id = ANY($2) AND verifiable_since IS NULL
+            "#,
+            committed.committed_at,
+            &committed.energy_record_ids,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO snapshot_commit (committed_at, next_commit_at)
+            VALUES ($1, $2)
+            ON CONFLICT (committed_at) DO NOTHING
+            "#,
+            committed.committed_at,
+            committed.next_commit_at,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// When the ZK service last said it would commit snapshots next
+    pub async fn get_next_snapshot_commit(&self) -> sqlx::Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT next_commit_at
+            FROM snapshot_commit
+            ORDER BY committed_at DESC
+            LIMIT 1
+            "#
+        )
+        .fetch_optional(&self.pg_pool)
+        .await
+    }
+
+    /// Which of `energy_record_ids` are not in a committed snapshot yet
+    pub async fn get_unverifiable_energy_record_ids(
+        &self,
+        energy_record_ids: &[Uuid],
+    ) -> sqlx::Result<Vec<Uuid>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT id
+            FROM energy_record
+            WHERE id = ANY($1) AND verifiable_since IS NULL
+            "#,
+            energy_record_ids
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+    }
+}
@@ -42,23 +42,16 @@ impl AppState {
         .map_err(Into::into)
     }
 
-    pub async fn register_user(
-        &self,
-        email: &str,
-        name: &str,
-        password: &str,
-        is_admin: bool,
-    ) -> AppResult<User> {
+    pub async fn register_user(&self, email: &str, name: &str, password: &str) -> AppResult<User> {
         let user = sqlx::query_as!(
             User,
             r#"
-            INSERT INTO "user" (email, name, is_admin)
-            VALUES ($1, $2, $3)
+            INSERT INTO "user" (email, name)
+            VALUES ($1, $2)
             RETURNING *
             "#,
             email,
-            name,
-            is_admin
+            name
         )
         .fetch_one(&self.pg_pool)
         .await?;
@@ -82,8 +75,8 @@ impl AppState {
         let user = sqlx::query_as!(
             User,
             r#"
-            INSERT INTO "user" (email, name)
-            VALUES ($1, $2)
+            INSERT INTO "user" (email, name, email_verified_at)
+            VALUES ($1, $2, NOW())
             RETURNING *
             "#,
             email,
@@ -97,6 +90,22 @@ impl AppState {
         Ok(user)
     }
 
+    /// Records that the user owns their email, returning whether it wasn't verified yet
+    pub async fn mark_email_verified(&self, user_id: Uuid) -> AppResult<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET email_verified_at = NOW()
+            WHERE id = $1 AND email_verified_at IS NULL
+            "#,
+            user_id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn get_user_communities(&self, user: &Uuid) -> sqlx::Result<Vec<UserCommunity>> {
         sqlx::query_as!(
             UserCommunity,
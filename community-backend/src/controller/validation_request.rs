@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use common::validation::RegisteredClaims;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::validation_token::record_validation_token;
+use crate::{AppState, models::ValidationRequest};
+
+/// Filters of the validation request audit trail. Unset fields match everything.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationRequestFilter {
+    pub user_id: Option<Uuid>,
+    pub energy_record_id: Option<Uuid>,
+    pub community_id: Option<Uuid>,
+    pub token_id: Option<Uuid>,
+    /// Requests made at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Requests made before this time
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// What a validation token was requested for
+pub enum ValidationSubject<'a> {
+    /// Energy records, each audited on its own
+    Records(&'a [Uuid]),
+    /// The totals of the user's records in a community starting within `[start, end)`
+    Period {
+        community_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    },
+}
+
+impl AppState {
+    /// Records an issued token along with the request it was issued for, at once so no token is
+    /// handed out without its audit trail
+    pub async fn record_validation_request(
+        &self,
+        user_id: Uuid,
+        claims: &RegisteredClaims,
+        subject: ValidationSubject<'_>,
+        client_ip: Option<IpAddr>,
+    ) -> sqlx::Result<()> {
+        let requested_at = Utc::now();
+        let client_ip = client_ip.map(|ip| ip.to_string());
+        let mut tx = self.pg_pool.begin().await?;
+
+        record_validation_token(&mut tx, user_id, claims).await?;
+        match subject {
+            ValidationSubject::Records(energy_record_ids) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO validation_request
+                        (user_id, energy_record_id, token_id, requested_at, client_ip)
+                    SELECT $1, UNNEST($2::uuid[]), $3, $4, $5::text::inet
+                    "#,
+                    user_id,
+                    energy_record_ids,
+                    claims.jti,
+                    requested_at,
+                    client_ip,
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            ValidationSubject::Period {
+                community_id,
+                start,
+                end,
+            } => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO validation_request
+                        (user_id, community_id, period_start, period_end, token_id, requested_at,
+                        client_ip)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7::text::inet)
+                    "#,
+                    user_id,
+                    community_id,
+                    start,
+                    end,
+                    claims.jti,
+                    requested_at,
+                    client_ip,
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    /// Validation requests matching `filter`, most recent first
+    pub async fn get_validation_requests(
+        &self,
+        filter: &ValidationRequestFilter,
+    ) -> sqlx::Result<Vec<ValidationRequest>> {
+        sqlx::query_as!(
+            ValidationRequest,
+            r#"
+            SELECT
+                id,
+                user_id,
+                energy_record_id,
+                community_id,
+                period_start,
+                period_end,
+                token_id,
+                requested_at,
+                host(client_ip) AS client_ip
+            FROM validation_request
+            WHERE ($1::uuid IS NULL OR user_id = $1)
+                AND ($2::uuid IS NULL OR energy_record_id = $2)
+                AND ($3::uuid IS NULL OR community_id = $3)
+                AND ($4::uuid IS NULL OR token_id = $4)
+                AND ($5::timestamptz IS NULL OR requested_at >= $5)
+                AND ($6::timestamptz IS NULL OR requested_at < $6)
+            ORDER BY requested_at DESC
+            "#,
+            filter.user_id,
+            filter.energy_record_id,
+            filter.community_id,
+            filter.token_id,
+            filter.from,
+            filter.to,
+        )
+        .fetch_all(&self.pg_pool)
+        .await
+    }
+}
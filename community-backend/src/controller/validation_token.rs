@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use common::validation::RegisteredClaims;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{AppError, AppResult},
+};
+
+impl AppState {
+    /// Marks a token as consumed. Fails if the token was never issued, was already consumed or has
+    /// expired.
+    pub async fn consume_validation_token(&self, token_id: Uuid) -> AppResult<()> {
+        let now = Utc::now();
+
+        // A single conditional update, so two verifiers racing on a token can't both consume it
+        let consumed = sqlx::query!(
+            r#"
+            UPDATE validation_token
+            SET consumed_at = $2
+            WHERE id = $1 AND consumed_at IS NULL AND expires_at > $2
+            "#,
+            token_id,
+            now,
+        )
+        .execute(&self.pg_pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if consumed {
+            return Ok(());
+        }
+
+        let token = sqlx::query!(
+            r#"
+            SELECT consumed_at, expires_at
+            FROM validation_token
+            WHERE id = $1
+            "#,
+            token_id,
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+
+        match token {
+            None => Err(AppError::ValidationTokenNotFound(token_id)),
+            Some(token) if token.consumed_at.is_some() => {
+                Err(AppError::ValidationTokenConsumed(token_id))
+            }
+            Some(_) => Err(AppError::ValidationTokenExpired(token_id)),
+        }
+    }
+}
+
+/// Records a token when it's issued, so it can be consumed exactly once later
+pub(super) async fn record_validation_token(
+    tx: &mut sqlx::PgTransaction<'_>,
+    user_id: Uuid,
+    claims: &RegisteredClaims,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO validation_token (id, user_id, issued_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        claims.jti,
+        user_id,
+        DateTime::from_timestamp(claims.iat, 0),
+        DateTime::from_timestamp(claims.exp, 0),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use axum::Json;
 use axum::extract::rejection::JsonRejection;
 use axum::extract::{FromRequest, Request};
-use axum::http::StatusCode;
+use axum::http::{HeaderValue, StatusCode, header};
 use axum::response::{IntoResponse, Response};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
@@ -50,6 +50,8 @@ pub enum AppError {
     Unauthorized,
     #[error("invalid session")]
     InvalidSession,
+    #[error("session not found: {0}")]
+    SessionNotFound(Uuid),
     #[error("email already in use: {0}")]
     EmailAlreadyInUse(String),
     #[error("argon2 error: {0}")]
@@ -62,6 +64,34 @@ pub enum AppError {
     OAuthError(String),
     #[error("email not verified by provider")]
     EmailNotVerified,
+    #[error("invalid or expired OAuth state")]
+    InvalidOAuthState,
+    #[error("unknown OIDC provider: {0}")]
+    UnknownOidcProvider(String),
+    #[error("invalid or expired password reset token")]
+    InvalidPasswordResetToken,
+    #[error("invalid or expired email verification token")]
+    InvalidEmailVerificationToken,
+    #[error("email already verified")]
+    EmailAlreadyVerified,
+    #[error("user has not verified their email: {0}")]
+    UserEmailNotVerified(String),
+    #[error("an account with an unverified email already uses it: {0}")]
+    UnverifiedAccountExists(String),
+    #[error("two-factor authentication already enabled")]
+    TwoFactorAlreadyEnabled,
+    #[error("two-factor authentication not enabled")]
+    TwoFactorNotEnabled,
+    #[error("invalid two-factor code")]
+    InvalidTwoFactorCode,
+    #[error("too many wrong two-factor codes")]
+    TooManyTwoFactorAttempts,
+    #[error("invalid or expired login challenge")]
+    InvalidLoginChallenge,
+    #[error("two-factor authentication required")]
+    TwoFactorRequired,
+    #[error("admins can't revoke their own admin rights")]
+    CannotRevokeOwnAdmin,
     #[error("user already added to community: {0}")]
     UserAlreadyAddedToCommunity(Uuid),
     #[error("manager already added to community: {0}")]
@@ -74,6 +104,22 @@ pub enum AppError {
     JsonWebTokenError(#[from] jsonwebtoken::errors::Error),
     #[error("energy record not found")]
     EnergyRecordNotFound(Uuid),
+    #[error("period start must be before its end")]
+    InvalidPeriod,
+    #[error("energy record not in a committed snapshot yet: {id}")]
+    EnergyRecordNotYetVerifiable {
+        id: Uuid,
+        /// Seconds until the next snapshot is expected, if known
+        retry_after: Option<u64>,
+    },
+    #[error("batch must have between 1 and {max} records")]
+    InvalidBatchSize { max: usize },
+    #[error("validation token not found: {0}")]
+    ValidationTokenNotFound(Uuid),
+    #[error("validation token already consumed: {0}")]
+    ValidationTokenConsumed(Uuid),
+    #[error("validation token expired: {0}")]
+    ValidationTokenExpired(Uuid),
 }
 
 impl IntoResponse for AppError {
@@ -142,11 +188,73 @@ impl IntoResponse for AppError {
                 StatusCode::FORBIDDEN,
                 "Email not verified by provider".to_string(),
             ),
+            AppError::InvalidOAuthState => (
+                StatusCode::BAD_REQUEST,
+                "Invalid or expired OAuth state".to_string(),
+            ),
+            AppError::UnknownOidcProvider(name) => (
+                StatusCode::NOT_FOUND,
+                format!("Unknown OIDC provider: {}", name),
+            ),
+            AppError::InvalidPasswordResetToken => (
+                StatusCode::BAD_REQUEST,
+                "Invalid or expired password reset token".to_string(),
+            ),
+            AppError::InvalidEmailVerificationToken => (
+                StatusCode::BAD_REQUEST,
+                "Invalid or expired email verification token".to_string(),
+            ),
+            AppError::EmailAlreadyVerified => {
+                (StatusCode::CONFLICT, "Email already verified".to_string())
+            }
+            AppError::UserEmailNotVerified(email) => (
+                StatusCode::CONFLICT,
+                format!("User has not verified their email yet: {}", email),
+            ),
+            AppError::UnverifiedAccountExists(email) => (
+                StatusCode::CONFLICT,
+                format!(
+                    "An account already uses {} but has not verified it. Verify the email from \
+                    that account, or reset its password, before signing in with a provider.",
+                    email
+                ),
+            ),
+            AppError::TwoFactorAlreadyEnabled => (
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled".to_string(),
+            ),
+            AppError::TwoFactorNotEnabled => (
+                StatusCode::CONFLICT,
+                "Two-factor authentication is not enabled".to_string(),
+            ),
+            AppError::InvalidTwoFactorCode => (
+                StatusCode::UNAUTHORIZED,
+                "Invalid two-factor code".to_string(),
+            ),
+            AppError::TooManyTwoFactorAttempts => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many wrong two-factor codes, try again later".to_string(),
+            ),
+            AppError::InvalidLoginChallenge => (
+                StatusCode::UNAUTHORIZED,
+                "Invalid or expired login, sign in again".to_string(),
+            ),
+            AppError::TwoFactorRequired => (
+                StatusCode::FORBIDDEN,
+                "Managers and admins must enable two-factor authentication first".to_string(),
+            ),
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            AppError::SessionNotFound(id) => {
+                (StatusCode::NOT_FOUND, format!("Session not found: {}", id))
+            }
             AppError::UserAlreadyAddedToCommunity(id) => (
                 StatusCode::CONFLICT,
                 format!("User already added to community: {}", id),
             ),
+            AppError::CannotRevokeOwnAdmin => (
+                StatusCode::CONFLICT,
+                "Admins can't revoke their own admin rights".to_string(),
+            ),
             AppError::ManagerAlreadyAddedToCommunity(id) => (
                 StatusCode::CONFLICT,
                 format!("Manager already added to community: {}", id),
@@ -167,10 +275,50 @@ impl IntoResponse for AppError {
                 StatusCode::NOT_FOUND,
                 format!("Energy record not found: {}", uuid),
             ),
+            AppError::InvalidPeriod => (
+                StatusCode::BAD_REQUEST,
+                "Period start must be before its end".to_string(),
+            ),
+            AppError::EnergyRecordNotYetVerifiable { id, retry_after } => (
+                StatusCode::CONFLICT,
+                match retry_after {
+                    Some(seconds) => format!(
+                        "Energy record {} is not in a committed snapshot yet, retry in {} seconds",
+                        id, seconds
+                    ),
+                    None => format!("Energy record {} is not in a committed snapshot yet", id),
+                },
+            ),
+            AppError::InvalidBatchSize { max } => (
+                StatusCode::BAD_REQUEST,
+                format!("Batch must have between 1 and {} records", max),
+            ),
+            AppError::ValidationTokenNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                format!("Validation token not found: {}", id),
+            ),
+            AppError::ValidationTokenConsumed(id) => (
+                StatusCode::CONFLICT,
+                format!("Validation token already consumed: {}", id),
+            ),
+            AppError::ValidationTokenExpired(id) => (
+                StatusCode::GONE,
+                format!("Validation token expired: {}", id),
+            ),
         };
 
         let body = ErrorBody { error };
 
-        (status, Json(body)).into_response()
+        let mut response = (status, Json(body)).into_response();
+        if let AppError::EnergyRecordNotYetVerifiable {
+            retry_after: Some(seconds),
+            ..
+        } = self
+        {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
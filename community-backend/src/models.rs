@@ -1,3 +1,5 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use common::allocation::{DistributionRule, ProofScheme};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -8,6 +10,8 @@ pub struct Community {
     pub description: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
+    pub distribution_rule: DistributionRule,
+    pub proof_scheme: ProofScheme,
 }
 
 #[derive(Debug, Serialize)]
@@ -16,6 +20,15 @@ pub struct User {
     pub email: String,
     pub name: String,
     pub is_admin: bool,
+    /// Unset until the user proves they own the email, which they can't do for an account they
+    /// registered with someone else's
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl User {
+    pub fn is_email_verified(&self) -> bool {
+        self.email_verified_at.is_some()
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -30,6 +43,9 @@ pub enum AuthProvider {
     Email,
     Google,
     GitHub,
+    /// A provider configured with `--oidc-providers-file`, whose key IDs are qualified with its
+    /// issuer
+    Oidc,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,3 +55,19 @@ pub struct Key {
     pub user_id: Uuid,
     pub hashed_password: Option<String>,
 }
+
+/// A call to sign a validation token for an energy record, or for the totals of a period in a
+/// community, kept so disputes about proofs can be investigated
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationRequest {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub energy_record_id: Option<Uuid>,
+    pub community_id: Option<Uuid>,
+    pub period_start: Option<NaiveDateTime>,
+    pub period_end: Option<NaiveDateTime>,
+    pub token_id: Uuid,
+    pub requested_at: DateTime<Utc>,
+    pub client_ip: Option<String>,
+}
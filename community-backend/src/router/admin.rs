@@ -1,15 +1,67 @@
 use crate::AppState;
 use crate::auth::extractor::ExtractSession;
+use crate::auth::two_factor_store::AuthPolicy;
 use crate::controller::admin::AdminListCommunityView;
+use crate::controller::validation_request::ValidationRequestFilter;
 use crate::error::{AppError, AppResult, ValidatedJson};
-use crate::models::{Community, User};
-use axum::extract::Path;
+use crate::models::{Community, User, ValidationRequest};
+use crate::session_gc::SessionGcStats;
+use axum::extract::{Path, Query, Request};
 use axum::http::StatusCode;
-use axum::{Json, debug_handler, extract::State, response::IntoResponse};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{get, put};
+use axum::{Json, Router, debug_handler, extract::State, response::IntoResponse};
+use common::allocation::{DistributionRule, ProofScheme};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/community",
+            get(get_admin_manageable_communities).post(create_community),
+        )
+        .route("/community/{id}", get(get_admin_community_information))
+        .route(
+            "/community/{id}/manager",
+            put(add_manager_to_community).delete(remove_manager_from_community),
+        )
+        .route(
+            "/community/{id}/user",
+            put(add_user_to_community).delete(remove_user_from_community),
+        )
+        .route("/admins", put(grant_admin).delete(revoke_admin))
+        .route("/validation-request", get(get_validation_requests))
+        .route("/session-gc", get(get_session_gc_stats))
+        .route("/auth-policy", get(get_auth_policy).put(update_auth_policy))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            require_staff_two_factor,
+        ))
+}
+
+/// Keeps managers and admins out of the admin endpoints until they enable two-factor
+/// authentication, when the auth policy asks for it
+async fn require_staff_two_factor(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> AppResult<Response> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::InvalidSession)?;
+
+    if state.requires_two_factor(&user).await? && !state.has_two_factor(user.id).await? {
+        return Err(AppError::TwoFactorRequired);
+    }
+
+    Ok(next.run(request).await)
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AdminCommunityInfoResponseUser {
     pub id: Uuid,
@@ -40,6 +92,11 @@ pub struct ChangeMembersCommunityRequest {
     pub user_email: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeAdminRequest {
+    pub user_email: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CommunityCreateRequest {
     #[validate(length(
@@ -53,6 +110,10 @@ pub struct CommunityCreateRequest {
     #[validate(url(message = "Image URL must be valid"))]
     #[serde(deserialize_with = "empty_string_as_none")]
     pub image: Option<String>,
+    #[serde(default)]
+    pub distribution_rule: DistributionRule,
+    #[serde(default)]
+    pub proof_scheme: ProofScheme,
 }
 
 fn empty_string_as_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
@@ -123,6 +184,8 @@ pub async fn create_community(
             &request.name,
             &request.description,
             request.image.as_deref(),
+            request.distribution_rule,
+            request.proof_scheme,
         )
         .await?;
 
@@ -175,6 +238,9 @@ pub async fn add_user_to_community(
     let target_user =
         require_manage_permission_and_find_user(&state, session.user_id, id, &request.user_email)
             .await?;
+    if !target_user.is_email_verified() {
+        return Err(AppError::UserEmailNotVerified(target_user.email));
+    }
     state.add_user_to_community(id, target_user.id).await?;
     Ok(StatusCode::NO_CONTENT)
 }
@@ -203,6 +269,9 @@ pub async fn add_manager_to_community(
     let target_user =
         require_manage_permission_and_find_user(&state, session.user_id, id, &request.user_email)
             .await?;
+    if !target_user.is_email_verified() {
+        return Err(AppError::UserEmailNotVerified(target_user.email));
+    }
     state.add_manager_to_community(id, target_user.id).await?;
     Ok(StatusCode::NO_CONTENT)
 }
@@ -223,61 +292,203 @@ pub async fn remove_manager_from_community(
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn require_admin_and_find_user(
+    state: &AppState,
+    user_id: Uuid,
+    user_email: &str,
+) -> AppResult<User> {
+    let user = state
+        .get_user_by_id(user_id)
+        .await?
+        .ok_or(AppError::UserNotFoundId(user_id))?;
+
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    state
+        .get_user_by_email(user_email)
+        .await?
+        .ok_or_else(|| AppError::UserNotFoundEmail(user_email.to_string()))
+}
+
+/// Makes another user an admin of every community
+#[debug_handler]
+pub async fn grant_admin(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Json(request): Json<ChangeAdminRequest>,
+) -> AppResult<StatusCode> {
+    let target_user =
+        require_admin_and_find_user(&state, session.user_id, &request.user_email).await?;
+    if !target_user.is_email_verified() {
+        return Err(AppError::UserEmailNotVerified(target_user.email));
+    }
+    state.set_admin(target_user.id, true).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Takes admin rights away from another admin. Admins can't revoke their own, so there is always
+/// one left.
+#[debug_handler]
+pub async fn revoke_admin(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Json(request): Json<ChangeAdminRequest>,
+) -> AppResult<StatusCode> {
+    let target_user =
+        require_admin_and_find_user(&state, session.user_id, &request.user_email).await?;
+    if target_user.id == session.user_id {
+        return Err(AppError::CannotRevokeOwnAdmin);
+    }
+    state.set_admin(target_user.id, false).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Searches the validation request audit trail, to investigate disputes about proofs
+#[debug_handler]
+pub async fn get_validation_requests(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Query(filter): Query<ValidationRequestFilter>,
+) -> AppResult<Json<Vec<ValidationRequest>>> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::UserNotFoundId(session.user_id))?;
+
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(Json(state.get_validation_requests(&filter).await?))
+}
+
+/// How many expired sessions and OAuth states were purged since the backend started
+#[debug_handler]
+pub async fn get_session_gc_stats(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<Json<SessionGcStats>> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::UserNotFoundId(session.user_id))?;
+
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(Json(state.session_gc_metrics.stats()))
+}
+
+#[debug_handler]
+pub async fn get_auth_policy(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<Json<AuthPolicy>> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::UserNotFoundId(session.user_id))?;
+
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(Json(state.get_auth_policy().await?))
+}
+
+/// Changes the auth policy. Admins can only require two-factor authentication of staff once they
+/// use it themselves, so they don't lock themselves out.
+#[debug_handler]
+pub async fn update_auth_policy(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+    Json(policy): Json<AuthPolicy>,
+) -> AppResult<Json<AuthPolicy>> {
+    let user = state
+        .get_user_by_id(session.user_id)
+        .await?
+        .ok_or(AppError::UserNotFoundId(session.user_id))?;
+
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+    if policy.require_staff_two_factor && !state.has_two_factor(user.id).await? {
+        return Err(AppError::TwoFactorRequired);
+    }
+
+    state.set_auth_policy(policy).await?;
+    Ok(Json(policy))
+}
+
 #[cfg(test)]
 mod tests {
     use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use serde_json::Value;
     use sqlx::PgPool;
     use tracing_test::traced_test;
 
     use crate::{
-        auth::router::{RegisterRequest, RegisterResponse},
+        auth::{
+            router::{RegisterRequest, RegisterResponse},
+            totp,
+            two_factor_store::AuthPolicy,
+        },
         controller::admin::AdminListCommunityView,
         models::Community,
-        router::test_utils::test_server,
+        router::{
+            router,
+            test_utils::{create_test_community, register, test_state},
+        },
     };
 
     use super::{
-        AdminCommunityInfoResponse, ChangeMembersCommunityRequest, CommunityCreateRequest,
+        AdminCommunityInfoResponse, ChangeAdminRequest, ChangeMembersCommunityRequest,
+        CommunityCreateRequest, DistributionRule, ProofScheme,
     };
 
     #[traced_test]
     #[sqlx::test]
     fn integration_test_admin_community_crud_and_members(pool: PgPool) {
-        let server = test_server(pool);
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
 
         // Register an admin user
         let admin_request = RegisterRequest {
             name: "admin_user".to_string(),
             email: "admin@example.com".to_string(),
             password: "password".to_string(),
-            is_admin: true,
         };
 
         let admin_response = server.post("/auth/register").json(&admin_request).await;
         admin_response.assert_status(StatusCode::OK);
         let admin_response = admin_response.json::<RegisterResponse>();
+        state.set_admin(admin_response.uuid, true).await.unwrap();
 
         // Register a regular user
         let user_request = RegisterRequest {
             name: "regular_user".to_string(),
             email: "user@example.com".to_string(),
             password: "password".to_string(),
-            is_admin: false,
         };
 
         let user_response = server.post("/auth/register").json(&user_request).await;
         user_response.assert_status(StatusCode::OK);
+        let user_response = user_response.json::<RegisterResponse>();
 
         // Register a manager user
         let manager_request = RegisterRequest {
             name: "manager_user".to_string(),
             email: "manager@example.com".to_string(),
             password: "password".to_string(),
-            is_admin: false,
         };
 
         let manager_response = server.post("/auth/register").json(&manager_request).await;
         manager_response.assert_status(StatusCode::OK);
+        let manager_response = manager_response.json::<RegisterResponse>();
 
         // List manageable communities (should be empty initially)
         let list_communities_response = server
@@ -297,6 +508,8 @@ mod tests {
             name: name.to_string(),
             description: description.to_string(),
             image: None,
+            distribution_rule: DistributionRule::Equal,
+            proof_scheme: ProofScheme::Attestation,
         };
 
         let create_community_response = server
@@ -310,6 +523,8 @@ mod tests {
         assert_eq!(community.name, name);
         assert_eq!(community.description, description);
         assert_eq!(community.image, None);
+        assert_eq!(community.distribution_rule, DistributionRule::Equal);
+        assert_eq!(community.proof_scheme, ProofScheme::Attestation);
 
         // List manageable communities (should have 1 community)
         let list_communities_response = server
@@ -339,11 +554,21 @@ mod tests {
         assert_eq!(info.managers.len(), 0);
         assert_eq!(info.admins.len(), 1); // The admin we created
 
-        // Add user to community
+        // Add user to community, which they can only join once they verified their email
         let add_user_request = ChangeMembersCommunityRequest {
             user_email: "user@example.com".to_string(),
         };
 
+        server
+            .put(&format!("/admin/community/{}/user", community.id))
+            .json(&add_user_request)
+            .add_header("Authorization", admin_response.session_id.to_string())
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        for registered in [&user_response, &manager_response] {
+            state.mark_email_verified(registered.uuid).await.unwrap();
+        }
+
         let add_user_response = server
             .put(&format!("/admin/community/{}/user", community.id))
             .json(&add_user_request)
@@ -429,4 +654,118 @@ mod tests {
 
         assert_eq!(info.managers.len(), 0);
     }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn staff_need_two_factor_when_required(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+        let admin = register(&server, "admin@example.com").await;
+        let manager = register(&server, "manager@example.com").await;
+        let user = register(&server, "user@example.com").await;
+        state.set_admin(admin.uuid, true).await.unwrap();
+        let community = create_test_community(&state, "Two factor", &[]).await;
+        state
+            .add_manager_to_community(community.id, manager.uuid)
+            .await
+            .unwrap();
+        let list_communities = |session: &RegisterResponse| {
+            server
+                .get("/admin/community")
+                .add_header("Authorization", session.session_id.to_string())
+        };
+        let require = |required| {
+            server
+                .put("/admin/auth-policy")
+                .add_header("Authorization", admin.session_id.to_string())
+                .json(&AuthPolicy {
+                    require_staff_two_factor: required,
+                })
+        };
+
+        // Admins can't require what they don't use themselves
+        require(true).await.assert_status(StatusCode::FORBIDDEN);
+        state
+            .store_pending_totp(admin.uuid, &totp::generate_secret())
+            .await
+            .unwrap();
+        state.enable_totp(admin.uuid, &[]).await.unwrap();
+        require(true).await.assert_status_ok();
+
+        list_communities(&admin).await.assert_status_ok();
+        list_communities(&manager)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        let me: Value = server
+            .post("/auth/me")
+            .add_header("Authorization", manager.session_id.to_string())
+            .await
+            .json();
+        assert_eq!(me["twoFactorSetupRequired"], true);
+        // Other users aren't asked for it, they just have nothing to manage
+        let listed = list_communities(&user)
+            .await
+            .json::<Vec<AdminListCommunityView>>();
+        assert!(listed.is_empty());
+        server
+            .get("/admin/auth-policy")
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        require(false).await.assert_status_ok();
+        list_communities(&manager).await.assert_status_ok();
+    }
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn admins_grant_and_revoke_admin_rights(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+        let admin = register(&server, "admin@example.com").await;
+        let user = register(&server, "user@example.com").await;
+        state.set_admin(admin.uuid, true).await.unwrap();
+        let change = |session: &RegisterResponse, grant: bool, email: &str| {
+            let path = "/admin/admins";
+            let request = if grant {
+                server.put(path)
+            } else {
+                server.delete(path)
+            };
+            request
+                .add_header("Authorization", session.session_id.to_string())
+                .json(&ChangeAdminRequest {
+                    user_email: email.to_string(),
+                })
+        };
+        let is_admin = |session: &RegisterResponse| {
+            let request = server
+                .post("/auth/me")
+                .add_header("Authorization", session.session_id.to_string());
+            async move { request.await.json::<Value>()["isAdmin"] == true }
+        };
+
+        // Only admins make admins
+        change(&user, true, "user@example.com")
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        change(&admin, true, "user@example.com")
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        state.mark_email_verified(user.uuid).await.unwrap();
+        change(&admin, true, "user@example.com")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert!(is_admin(&user).await);
+
+        // Admins can't lock everyone out by revoking themselves
+        change(&user, false, "user@example.com")
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        change(&user, false, "admin@example.com")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert!(!is_admin(&admin).await);
+        assert!(is_admin(&user).await);
+    }
 }
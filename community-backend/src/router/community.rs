@@ -116,15 +116,7 @@ pub async fn list_user_energy_records(
     ValidatedJson(query): ValidatedJson<EnergyFilter>,
 ) -> AppResult<Json<PaginatedEnergyRecords>> {
     let energy = state
-        .get_user_energy_records(
-            session.user_id,
-            id,
-            query.page,
-            query.size,
-            query.order_dir,
-            query.start,
-            query.end,
-        )
+        .get_user_energy_records(session.user_id, id, &query)
         .await?;
     Ok(Json(energy))
 }
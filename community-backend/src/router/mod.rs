@@ -2,32 +2,16 @@ use crate::AppState;
 use crate::auth;
 use crate::sign;
 use axum::Router;
-use axum::routing::put;
 use axum::routing::{get, post};
 use tower_http::trace::TraceLayer;
 
 pub mod admin;
 pub mod community;
-pub mod temp;
+pub mod service;
+pub mod trusted_entity;
 
 pub fn router(state: AppState) -> Router {
     Router::new()
-        .route(
-            "/admin/community",
-            get(admin::get_admin_manageable_communities).post(admin::create_community),
-        )
-        .route(
-            "/admin/community/{id}",
-            get(admin::get_admin_community_information),
-        )
-        .route(
-            "/admin/community/{id}/manager",
-            put(admin::add_manager_to_community).delete(admin::remove_manager_from_community),
-        )
-        .route(
-            "/admin/community/{id}/user",
-            put(admin::add_user_to_community).delete(admin::remove_user_from_community),
-        )
         .route(
             "/community",
             get(community::get_communities_with_user_energy_records),
@@ -42,11 +26,20 @@ pub fn router(state: AppState) -> Router {
             "/sign-energy-record-validation/{id}",
             get(sign::sign_energy_record_validation_request),
         )
+        .route("/.well-known/jwks.json", get(sign::get_jwks))
+        .route("/validation-request", get(sign::get_validation_history))
+        .route(
+            "/sign-energy-batch-validation",
+            post(sign::sign_energy_batch_validation_request),
+        )
         .route(
-            "/energy-record-unauthenticated/{id}",
-            get(temp::get_energy_record_unauthenticated),
+            "/sign-energy-range-validation/{community_id}",
+            post(sign::sign_energy_range_validation_request),
         )
+        .nest("/admin", admin::router(state.clone()))
         .nest("/auth", auth::router::router().with_state(state.clone()))
+        .nest("/service", service::router(state.clone()))
+        .nest("/trusted-entity", trusted_entity::router(state.clone()))
         .with_state(state.clone())
         .layer(TraceLayer::new_for_http())
 }
@@ -55,26 +48,275 @@ pub fn router(state: AppState) -> Router {
 pub(crate) mod test_utils {
     use std::sync::Arc;
 
-    use axum_test::TestServer;
+    use axum::http::Method;
+    use axum_test::{TestRequest, TestServer};
+    use chrono::{Duration, Utc};
+    use common::{
+        allocation::{DistributionRule, ProofScheme},
+        service_auth::{ServiceKey, sign_request},
+        snapshot::CommittedRecords,
+    };
+    use serde::Serialize;
     use sqlx::PgPool;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+    };
+    use uuid::Uuid;
 
-    use crate::{AppState, router::router, sign::ValidationSigner};
+    use crate::{
+        AppState,
+        auth::{
+            SessionPolicy,
+            router::{RegisterRequest, RegisterResponse},
+        },
+        mail::LogMailer,
+        models::Community,
+        router::router,
+        sign::ValidationSigner,
+    };
+
+    /// Key the tests sign ZK service requests with
+    pub(crate) const TEST_ZK_SERVICE_KEY: &str =
+        "7a6b2d7365727669636520746573742d6b6579207a6b2d736572766963652021";
+
+    /// Key the tests sign trusted entity requests with
+    pub(crate) const TEST_TRUSTED_ENTITY_KEY: &str =
+        "7472757374656420656e746974792074657374206b657920666f722070657461";
 
     pub(crate) fn test_server(pg_pool: PgPool) -> TestServer {
-        let google_oauth = crate::auth::oauth::GoogleOAuthClient::new(
+        TestServer::new(router(test_state(pg_pool))).unwrap()
+    }
+
+    pub(crate) fn test_state(pg_pool: PgPool) -> AppState {
+        let google_oauth = crate::auth::oidc::OidcClient::google(
             "test-client-id".to_string(),
             "test-client-secret".to_string(),
             "http://localhost:8080/auth/callback".to_string(),
         )
         .unwrap();
+        let github_oauth = crate::auth::oauth::GitHubOAuthClient::new(
+            "test-client-id".to_string(),
+            "test-client-secret".to_string(),
+            "http://localhost:8080/auth/oauth/github/callback".to_string(),
+            &"https://github.com/".parse().unwrap(),
+            "https://api.github.com/".parse().unwrap(),
+        )
+        .unwrap();
 
-        let state = AppState {
+        AppState {
             pg_pool,
             google_oauth,
+            github_oauth,
+            oidc_providers: Arc::new(Vec::new()),
+            session_policy: SessionPolicy {
+                lifetime: Duration::hours(24),
+                max_lifetime: Duration::days(7),
+            },
+            session_gc_metrics: Arc::default(),
+            mailer: Arc::new(LogMailer),
+            frontend_url: "http://localhost:4173/".parse().unwrap(),
             validation_signer: Arc::new(ValidationSigner::test_new()),
-        };
-        let router = router(state);
+            zk_service_key: Arc::new(ServiceKey::from_hex(TEST_ZK_SERVICE_KEY).unwrap()),
+            trusted_entity_key: Arc::new(ServiceKey::from_hex(TEST_TRUSTED_ENTITY_KEY).unwrap()),
+            trust_forwarded_for: true,
+        }
+    }
+
+    /// A request without a body signed with `key`, along with its signature to check the response
+    /// against
+    pub(crate) fn signed_request(
+        server: &TestServer,
+        key: &ServiceKey,
+        method: Method,
+        path: &str,
+    ) -> (TestRequest, String) {
+        let (headers, signature) = sign_request(key, &method, path, b"");
+        let request = headers
+            .into_iter()
+            .fold(server.method(method, path), |request, (name, value)| {
+                request.add_header(name.unwrap(), value)
+            });
+        (request, signature)
+    }
+
+    /// A request with a JSON body signed with `key`
+    pub(crate) fn signed_json_request(
+        server: &TestServer,
+        key: &ServiceKey,
+        method: Method,
+        path: &str,
+        body: &impl Serialize,
+    ) -> (TestRequest, String) {
+        let body = serde_json::to_vec(body).unwrap();
+        let (headers, signature) = sign_request(key, &method, path, &body);
+        let request = headers.into_iter().fold(
+            server
+                .method(method, path)
+                .bytes(body.into())
+                .content_type("application/json"),
+            |request, (name, value)| request.add_header(name.unwrap(), value),
+        );
+        (request, signature)
+    }
+
+    /// A password sign up as "Test User"
+    pub(crate) fn test_user(email: &str) -> RegisterRequest {
+        RegisterRequest {
+            name: "Test User".to_string(),
+            email: email.to_string(),
+            password: "test_password".to_string(),
+        }
+    }
+
+    /// Signs up [`test_user`] through `/auth/register`
+    pub(crate) async fn register(server: &TestServer, email: &str) -> RegisterResponse {
+        server
+            .post("/auth/register")
+            .json(&test_user(email))
+            .await
+            .json()
+    }
+
+    /// Creates a community with the default distribution rule and proof scheme, adding `members`
+    /// to it, each of which gets 90 days of records
+    pub(crate) async fn create_test_community(
+        state: &AppState,
+        name: &str,
+        members: &[Uuid],
+    ) -> Community {
+        let community = state
+            .create_community(
+                name,
+                "Community",
+                None,
+                DistributionRule::default(),
+                ProofScheme::default(),
+            )
+            .await
+            .unwrap();
+        for &member in members {
+            state
+                .add_user_to_community(community.id, member)
+                .await
+                .unwrap();
+        }
+        community
+    }
+
+    /// Marks every record as committed by the ZK service, so validation tokens can be signed
+    pub(crate) async fn commit_all_records(state: &AppState) {
+        let energy_record_ids = state
+            .get_all_energy_records()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.id)
+            .collect();
+        state
+            .record_committed_records(&CommittedRecords {
+                committed_at: Utc::now(),
+                next_commit_at: Utc::now() + Duration::minutes(15),
+                energy_record_ids,
+            })
+            .await
+            .unwrap();
+    }
+
+    /// A message delivered to [`smtp_sink`]
+    pub(crate) struct ReceivedEmail {
+        pub recipients: Vec<String>,
+        /// Headers and body, with quoted-printable text decoded
+        pub data: String,
+    }
+
+    /// Starts a bare SMTP server accepting every message, returning its URL and the messages it
+    /// receives
+    pub(crate) async fn smtp_sink() -> (String, mpsc::UnboundedReceiver<ReceivedEmail>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("smtp://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_smtp(stream, sender.clone()));
+            }
+        });
+        (url, receiver)
+    }
+
+    /// Waits for the next email, which must be to `to`, and returns the token of the frontend link
+    /// to `path` in it
+    pub(crate) async fn received_token(
+        emails: &mut mpsc::UnboundedReceiver<ReceivedEmail>,
+        to: &str,
+        path: &str,
+    ) -> String {
+        let email = emails.recv().await.unwrap();
+        assert_eq!(email.recipients, [to]);
+        let (_, token) = email
+            .data
+            .split_once(&format!("http://localhost:4173/{path}?token="))
+            .unwrap();
+        token[..64].to_string()
+    }
+
+    async fn serve_smtp(
+        stream: TcpStream,
+        sender: mpsc::UnboundedSender<ReceivedEmail>,
+    ) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut recipients = Vec::new();
+
+        writer.write_all(b"220 sink ESMTP\r\n").await?;
+        while let Some(line) = lines.next_line().await? {
+            let command = line.to_ascii_uppercase();
+            if command.starts_with("RCPT TO:") {
+                recipients.push(line[8..].trim().trim_matches(['<', '>']).to_string());
+            } else if command.starts_with("DATA") {
+                writer.write_all(b"354 Go ahead\r\n").await?;
+                let mut data = String::new();
+                while let Some(line) = lines.next_line().await? {
+                    if line == "." {
+                        break;
+                    }
+                    data.push_str(line.strip_prefix('.').unwrap_or(&line));
+                    data.push('\n');
+                }
+                let _ = sender.send(ReceivedEmail {
+                    recipients: std::mem::take(&mut recipients),
+                    data: decode_quoted_printable(&data),
+                });
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 Bye\r\n").await?;
+                break;
+            }
+            writer.write_all(b"250 OK\r\n").await?;
+        }
+        Ok(())
+    }
 
-        TestServer::new(router).unwrap()
+    fn decode_quoted_printable(text: &str) -> String {
+        let text = text.replace("=\n", "");
+        let mut decoded = Vec::with_capacity(text.len());
+        let mut rest = text.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            let escaped = (byte == b'=')
+                .then(|| tail.get(..2))
+                .flatten()
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+            match escaped {
+                Some(escaped) => {
+                    decoded.push(escaped);
+                    rest = &tail[2..];
+                }
+                None => {
+                    decoded.push(byte);
+                    rest = tail;
+                }
+            }
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
     }
 }
@@ -0,0 +1,188 @@
+//! Endpoints for the zero-knowledge service.
+//!
+//! These hand out raw records, so they are not behind user sessions but only accept requests
+//! signed with the key shared with the ZK service, see [`common::service_auth`].
+
+use axum::{
+    Json, Router, debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    routing::{get, post},
+};
+use common::{EnergyRecord, service_auth::authenticate_service, snapshot::CommittedRecords};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{AppError, AppResult},
+    models::Community,
+};
+
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/energy-record/", get(get_energy_records))
+        .route("/energy-record/{id}", get(get_energy_record))
+        .route("/community/", get(get_communities))
+        .route("/snapshot-commit", post(record_snapshot_commit))
+        .route_layer(middleware::from_fn_with_state(
+            state.zk_service_key,
+            authenticate_service,
+        ))
+}
+
+/// Returns a single energy record.
+///
+/// The ZK service still grabs the original record to answer validation requests, rather than
+/// proving them from its snapshots.
+#[debug_handler]
+pub async fn get_energy_record(
+    State(state): State<AppState>,
+    Path(energy_record_id): Path<Uuid>,
+) -> AppResult<Json<EnergyRecord>> {
+    let Some(energy_record) = state.get_energy_record(energy_record_id).await? else {
+        return Err(AppError::EnergyRecordNotFound(energy_record_id));
+    };
+    Ok(Json(energy_record))
+}
+
+/// Returns every energy record so the ZK service can commit to them in its snapshots.
+#[debug_handler]
+pub async fn get_energy_records(
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<EnergyRecord>>> {
+    Ok(Json(state.get_all_energy_records().await?))
+}
+
+/// Returns every community so the ZK service can commit to their distribution rules in its
+/// snapshots.
+#[debug_handler]
+pub async fn get_communities(State(state): State<AppState>) -> AppResult<Json<Vec<Community>>> {
+    Ok(Json(state.get_communities().await?))
+}
+
+/// Marks the records the ZK service just committed as verifiable
+#[debug_handler]
+pub async fn record_snapshot_commit(
+    State(state): State<AppState>,
+    Json(committed): Json<CommittedRecords>,
+) -> AppResult<StatusCode> {
+    state.record_committed_records(&committed).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode, header};
+    use axum_test::TestServer;
+    use chrono::{Duration, Utc};
+    use common::{
+        service_auth::{ServiceKey, verify_response},
+        snapshot::CommittedRecords,
+    };
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::{
+        controller::community::PaginatedEnergyRecords,
+        router::{
+            router,
+            test_utils::{
+                TEST_ZK_SERVICE_KEY, create_test_community, register, signed_json_request,
+                signed_request, test_server, test_state,
+            },
+        },
+    };
+
+    #[sqlx::test]
+    async fn only_signed_requests_are_served(pool: PgPool) {
+        let server = test_server(pool);
+        let path = "/service/community/";
+
+        server
+            .get(path)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        let other_key = ServiceKey::from_hex(&"11".repeat(32)).unwrap();
+        let (request, _) = signed_request(&server, &other_key, Method::GET, path);
+        request.await.assert_status(StatusCode::UNAUTHORIZED);
+
+        let key = ServiceKey::from_hex(TEST_ZK_SERVICE_KEY).unwrap();
+        let (request, signature) = signed_request(&server, &key, Method::GET, path);
+        let response = request.await;
+        response.assert_status_ok();
+
+        verify_response(
+            &key,
+            &signature,
+            response.status_code(),
+            response.headers(),
+            response.as_bytes(),
+        )
+        .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn only_committed_records_are_validated(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+        let key = ServiceKey::from_hex(TEST_ZK_SERVICE_KEY).unwrap();
+
+        let user = register(&server, "member@example.com").await;
+        let community = create_test_community(&state, "Snapshots", &[user.uuid]).await;
+        let records = state.get_all_energy_records().await.unwrap();
+        let sign = |id| {
+            server
+                .get(&format!("/sign-energy-record-validation/{id}"))
+                .add_header("Authorization", user.session_id.to_string())
+        };
+
+        // Before the first commit there is no telling when to retry
+        let response = sign(records[0].id).await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+
+        // The ZK service commits all but the first record
+        let committed_at = Utc::now();
+        let (request, _) = signed_json_request(
+            &server,
+            &key,
+            Method::POST,
+            "/service/snapshot-commit",
+            &CommittedRecords {
+                committed_at,
+                next_commit_at: committed_at + Duration::minutes(15),
+                energy_record_ids: records[1..].iter().map(|record| record.id).collect(),
+            },
+        );
+        request.await.assert_status(StatusCode::NO_CONTENT);
+
+        sign(records[1].id).await.assert_status_ok();
+
+        let response = sign(records[0].id).await;
+        response.assert_status(StatusCode::CONFLICT);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=15 * 60).contains(&retry_after));
+
+        let listed = server
+            .post(&format!("/community/{}/energy", community.id))
+            .json(&json!({ "page": 1, "size": 100, "orderDir": "desc" }))
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .json::<PaginatedEnergyRecords>();
+        for listed in &listed.records {
+            let expected = (listed.record.id != records[0].id).then_some(committed_at);
+            assert_eq!(
+                listed
+                    .verifiable_since
+                    .map(|since| since.timestamp_micros()),
+                expected.map(|since| since.timestamp_micros())
+            );
+        }
+    }
+}
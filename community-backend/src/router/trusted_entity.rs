@@ -0,0 +1,227 @@
+//! Endpoints for the trusted entity, which verifies validation tokens.
+//!
+//! Only requests signed with the key shared with the trusted entity are accepted, see
+//! [`common::service_auth`].
+
+use axum::{
+    Router, debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    routing::post,
+};
+use common::service_auth::authenticate_service;
+use uuid::Uuid;
+
+use crate::{AppState, error::AppResult};
+
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/validation-token/{id}/consume",
+            post(consume_validation_token),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.trusted_entity_key,
+            authenticate_service,
+        ))
+}
+
+/// Redeems a validation token by its `jti`. Only the first call for a token succeeds, so a token
+/// can't be replayed.
+#[debug_handler]
+pub async fn consume_validation_token(
+    State(state): State<AppState>,
+    Path(token_id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+    state.consume_validation_token(token_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use axum::{
+        Router,
+        http::{HeaderValue, Method, StatusCode},
+    };
+    use axum_test::TestServer;
+    use common::{bundle::ProofBundle, service_auth::ServiceKey, validation::ValidationClaims};
+    use ed25519_dalek::SigningKey;
+    use jsonwebtoken::{DecodingKey, Validation};
+    use serde_json::{Value, json};
+    use sqlx::PgPool;
+    use tokio::net::TcpListener;
+    use url::Url;
+
+    use crate::{
+        router::{
+            router,
+            test_utils::{
+                TEST_TRUSTED_ENTITY_KEY, TEST_ZK_SERVICE_KEY, commit_all_records,
+                create_test_community, register, signed_request, test_state,
+            },
+        },
+        sign::{SignEnergyRecordValidationResponse, ValidationSigner},
+    };
+
+    #[sqlx::test]
+    async fn validation_tokens_are_consumed_once(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+        let key = ServiceKey::from_hex(TEST_TRUSTED_ENTITY_KEY).unwrap();
+
+        let user = register(&server, "member@example.com").await;
+        create_test_community(&state, "Tokens", &[user.uuid]).await;
+        commit_all_records(&state).await;
+        let record = state.get_all_energy_records().await.unwrap()[0].id;
+
+        let token = server
+            .get(&format!("/sign-energy-record-validation/{record}"))
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .json::<SignEnergyRecordValidationResponse>()
+            .signed_request;
+        let mut validation = Validation::new(jsonwebtoken::Algorithm::RS256);
+        validation.set_issuer(&[ValidationSigner::TEST_ISSUER]);
+        validation.set_audience(&[ValidationSigner::TEST_AUDIENCE]);
+        let claims = jsonwebtoken::decode::<ValidationClaims>(
+            &token,
+            &DecodingKey::from_jwk(&state.validation_signer.jwks().keys[0]).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        let path = format!(
+            "/trusted-entity/validation-token/{}/consume",
+            claims.registered.jti
+        );
+
+        // Only the trusted entity may consume tokens
+        server
+            .post(&path)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        let (request, _) = signed_request(&server, &key, Method::POST, &path);
+        request.await.assert_status(StatusCode::NO_CONTENT);
+
+        // Replaying the token fails
+        let (request, _) = signed_request(&server, &key, Method::POST, &path);
+        request.await.assert_status(StatusCode::CONFLICT);
+
+        let path = format!(
+            "/trusted-entity/validation-token/{}/consume",
+            uuid::Uuid::new_v4()
+        );
+        let (request, _) = signed_request(&server, &key, Method::POST, &path);
+        request.await.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    async fn spawn(app: Router) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap()
+        });
+        url.parse().unwrap()
+    }
+
+    /// A token signed by the backend is proven by the zk-poc-service, checked and redeemed by the
+    /// trusted entity, all of them running their real routes
+    #[sqlx::test]
+    async fn validation_tokens_are_redeemed_end_to_end(pool: PgPool) {
+        let state = test_state(pool);
+        let backend_url = spawn(router(state.clone())).await;
+        let backend = TestServer::new(router(state.clone())).unwrap();
+
+        let user = register(&backend, "member@example.com").await;
+        create_test_community(&state, "Tokens", &[user.uuid]).await;
+        // A few hours of records keep committing and proving them quick
+        sqlx::query(r#"DELETE FROM "energy_record" WHERE "start" < NOW() - INTERVAL '3 hours'"#)
+            .execute(&state.pg_pool)
+            .await
+            .unwrap();
+        let record = state.get_all_energy_records().await.unwrap()[0].clone();
+
+        // The zk-poc-service commits the backend's records and reports them back
+        let snapshot_key = SigningKey::from_bytes(&[7; 32]);
+        let zk_key = ServiceKey::from_hex(&"24".repeat(32)).unwrap();
+        let log_file = std::env::temp_dir().join(format!("{}.jsonl", uuid::Uuid::new_v4()));
+        let zk_state = zk_poc_service::AppState::new(
+            backend_url.join("/service/energy-record/").unwrap(),
+            backend_url.join("/service/community/").unwrap(),
+            backend_url.join("/service/snapshot-commit").unwrap(),
+            ServiceKey::from_hex(TEST_ZK_SERVICE_KEY).unwrap(),
+            snapshot_key.clone(),
+            &log_file,
+        )
+        .unwrap();
+        zk_poc_service::commit_snapshots(&zk_state, Duration::from_secs(900))
+            .await
+            .unwrap();
+        let zk_url = spawn(zk_poc_service::router(zk_state, zk_key.clone())).await;
+
+        let http = reqwest::Client::new();
+        let trusted_entity = TestServer::new(trusted_entity::router(
+            trusted_entity::AppState::new(
+                trusted_entity::TokenVerifier::new(
+                    backend_url.join("/.well-known/jwks.json").unwrap(),
+                    ValidationSigner::TEST_ISSUER,
+                    ValidationSigner::TEST_AUDIENCE,
+                    http.clone(),
+                ),
+                trusted_entity::ServiceClient::new(
+                    backend_url,
+                    ServiceKey::from_hex(TEST_TRUSTED_ENTITY_KEY).unwrap(),
+                    http.clone(),
+                ),
+                trusted_entity::ServiceClient::new(zk_url, zk_key, http),
+                snapshot_key.verifying_key(),
+            ),
+            HeaderValue::from_static("http://localhost:5173"),
+        ))
+        .unwrap();
+
+        let token = backend
+            .get(&format!("/sign-energy-record-validation/{}", record.id))
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .json::<SignEnergyRecordValidationResponse>()
+            .signed_request;
+        let response = trusted_entity
+            .post("/validate")
+            .json(&json!({ "token": token }))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<Value>()["query"], json!(record.id));
+
+        // The proof the user gets checks out offline against the zk-poc-service's key alone
+        let bundle = trusted_entity
+            .get(&format!("/validate/bundle?query={}", record.id))
+            .add_cookie(response.cookie("trustedEntitySessionId"))
+            .await
+            .json::<ProofBundle>();
+        assert!(
+            bundle
+                .verify(Some(&snapshot_key.verifying_key()))
+                .unwrap()
+                .valid
+        );
+
+        // The backend redeemed the token, so it can't be replayed
+        trusted_entity
+            .post("/validate")
+            .json(&json!({ "token": token }))
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        std::fs::remove_file(log_file).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::{AppState, error::AppResult};
+
+/// Rows removed by one collection
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgedRows {
+    pub sessions: u64,
+    pub oauth_states: u64,
+}
+
+/// Running totals of the collections since the backend started
+#[derive(Default)]
+pub struct SessionGcMetrics {
+    runs: AtomicU64,
+    failures: AtomicU64,
+    sessions_purged: AtomicU64,
+    oauth_states_purged: AtomicU64,
+    last_run: Mutex<Option<(DateTime<Utc>, PurgedRows)>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionGcStats {
+    pub runs: u64,
+    pub failures: u64,
+    pub sessions_purged: u64,
+    pub oauth_states_purged: u64,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_run_purged: Option<PurgedRows>,
+}
+
+impl SessionGcMetrics {
+    fn record(&self, purged: PurgedRows) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.sessions_purged
+            .fetch_add(purged.sessions, Ordering::Relaxed);
+        self.oauth_states_purged
+            .fetch_add(purged.oauth_states, Ordering::Relaxed);
+        *self.last_run.lock().unwrap() = Some((Utc::now(), purged));
+    }
+
+    fn record_failure(&self) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> SessionGcStats {
+        let last_run = *self.last_run.lock().unwrap();
+        SessionGcStats {
+            runs: self.runs.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            sessions_purged: self.sessions_purged.load(Ordering::Relaxed),
+            oauth_states_purged: self.oauth_states_purged.load(Ordering::Relaxed),
+            last_run_at: last_run.map(|(at, _)| at),
+            last_run_purged: last_run.map(|(_, purged)| purged),
+        }
+    }
+}
+
+/// Deletes expired sessions, and the OAuth states of logins that were never completed. Sessions
+/// expire when left idle, or `session_max_lifetime` after their login.
+pub async fn purge_expired(
+    pg_pool: &PgPool,
+    session_max_lifetime: chrono::Duration,
+) -> AppResult<PurgedRows> {
+    let sessions = sqlx::query!(
+        r#"
+        DELETE FROM session
+        WHERE expiration <= NOW() OR created_at + $1::interval <= NOW()
+        "#,
+        session_max_lifetime as _
+    )
+    .execute(pg_pool)
+    .await?;
+
+    let oauth_states = sqlx::query!(
+        r#"
+        DELETE FROM oauth_state
+        WHERE expires_at <= NOW()
+        "#
+    )
+    .execute(pg_pool)
+    .await?;
+
+    Ok(PurgedRows {
+        sessions: sessions.rows_affected(),
+        oauth_states: oauth_states.rows_affected(),
+    })
+}
+
+/// Purges expired rows like [`purge_expired`], recording the run in the state's metrics
+pub async fn collect_expired_sessions(state: &AppState) -> AppResult<PurgedRows> {
+    let result = purge_expired(&state.pg_pool, state.session_policy.max_lifetime).await;
+
+    match &result {
+        Ok(purged) => state.session_gc_metrics.record(*purged),
+        Err(_) => state.session_gc_metrics.record_failure(),
+    }
+    result
+}
+
+/// Collects expired sessions every `period`, starting right away
+pub async fn run_periodic_session_gc(state: AppState, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        match collect_expired_sessions(&state).await {
+            Ok(purged) => info!(
+                "Purged {} expired sessions and {} expired OAuth states",
+                purged.sessions, purged.oauth_states
+            ),
+            Err(e) => error!("Error purging expired sessions: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use chrono::{Duration, Utc};
+    use sqlx::PgPool;
+    use tracing_test::traced_test;
+
+    use crate::{
+        auth::{SessionDevice, oauth_state_store::OAuthState},
+        models::AuthProvider,
+        router::{
+            router,
+            test_utils::{register, test_state},
+        },
+        session_gc::{SessionGcStats, collect_expired_sessions},
+    };
+
+    #[traced_test]
+    #[sqlx::test]
+    async fn expired_sessions_are_purged(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+        let admin = register(&server, "admin@example.com").await;
+        let user = register(&server, "user@example.com").await;
+        state.set_admin(admin.uuid, true).await.unwrap();
+
+        let idle = state
+            .create_session(user.uuid, SessionDevice::default())
+            .await
+            .unwrap();
+        let too_old = state
+            .create_session(user.uuid, SessionDevice::default())
+            .await
+            .unwrap();
+        for (id, created_ago, expires_in) in [
+            (idle.id, Duration::hours(2), Duration::seconds(-1)),
+            (too_old.id, Duration::days(8), Duration::hours(1)),
+        ] {
+            sqlx::query!(
+                "UPDATE session SET created_at = $2, expiration = $3 WHERE id = $1",
+                id,
+                Utc::now() - created_ago,
+                Utc::now() + expires_in
+            )
+            .execute(&state.pg_pool)
+            .await
+            .unwrap();
+        }
+
+        let mut abandoned = OAuthState::new(
+            AuthProvider::Google,
+            "abandoned".to_string(),
+            "verifier".to_string(),
+        );
+        abandoned.expires_at = Utc::now() - Duration::seconds(1);
+        state.store_oauth_state(&abandoned).await.unwrap();
+        state
+            .store_oauth_state(&OAuthState::new(
+                AuthProvider::Google,
+                "pending".to_string(),
+                "verifier".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let purged = collect_expired_sessions(&state).await.unwrap();
+        assert_eq!((purged.sessions, purged.oauth_states), (2, 1));
+        let purged = collect_expired_sessions(&state).await.unwrap();
+        assert_eq!((purged.sessions, purged.oauth_states), (0, 0));
+
+        // Sessions and states still in use are left alone
+        assert!(
+            state
+                .get_valid_session(user.session_id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            state
+                .take_oauth_state("pending", AuthProvider::Google, None)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        server
+            .get("/admin/session-gc")
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let stats: SessionGcStats = server
+            .get("/admin/session-gc")
+            .add_header("Authorization", admin.session_id.to_string())
+            .await
+            .json();
+        assert_eq!(stats.runs, 2);
+        assert_eq!(stats.failures, 0);
+        assert_eq!(stats.sessions_purged, 2);
+        assert_eq!(stats.oauth_states_purged, 1);
+        assert!(stats.last_run_at.is_some());
+        assert_eq!(stats.last_run_purged.map(|purged| purged.sessions), Some(0));
+    }
+}
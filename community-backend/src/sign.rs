@@ -1,79 +1,308 @@
 use std::time::Duration;
 
+use anyhow::Context;
 use axum::{
     Json, debug_handler,
     extract::{Path, State},
 };
-use chrono::Utc;
-use jsonwebtoken::{Algorithm, EncodingKey, Header};
-use serde::Serialize;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use common::validation::{
+    BatchValidationClaims, RangeValidationClaims, RegisteredClaims, ValidationClaims,
+};
+use jsonwebtoken::{
+    Algorithm, EncodingKey, Header,
+    jwk::{
+        AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType, ThumbprintHash,
+    },
+};
+use rsa::{RsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
     AppState,
-    auth::extractor::ExtractSession,
+    auth::extractor::{ExtractClientIp, ExtractSession},
+    controller::{
+        community::EnergyTotals,
+        validation_request::{ValidationRequestFilter, ValidationSubject},
+    },
     error::{AppError, AppResult},
+    models::ValidationRequest,
 };
 
 pub struct ValidationSigner {
     private_key: EncodingKey,
+    key_id: String,
+    algorithm: Algorithm,
+    issuer: String,
+    audience: String,
     max_age: Duration,
+    /// Public keys tokens may be verified with: the current key, and retired keys until the last
+    /// tokens they signed have expired
+    public_keys: Vec<PublishedKey>,
+}
+
+struct PublishedKey {
+    jwk: Jwk,
+    /// Time after which no token signed with the key is valid anymore. `None` for the current key.
+    valid_until: Option<DateTime<Utc>>,
+}
+
+/// Public key of a validation key pair that no longer signs tokens
+pub struct RetiredValidationKey {
+    pub public_key: Jwk,
+    pub retired_at: DateTime<Utc>,
+}
+
+impl RetiredValidationKey {
+    /// Format of the retirement time in the names of retired key files
+    const RETIRED_AT_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+    /// Loads a PEM encoded RSA public key from a file named `validation-<retired at>.key.pub`, as
+    /// the rotation script names it, with the retirement time in UTC like `20251031T120000Z`
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let retired_at = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("validation-")?.strip_suffix(".key.pub"))
+            .context("Retired key file is not named validation-<retired at>.key.pub")?;
+        let retired_at = NaiveDateTime::parse_from_str(retired_at, Self::RETIRED_AT_FORMAT)
+            .context("Failed to parse the retirement time of the key")?
+            .and_utc();
+        let public_key = RsaPublicKey::from_public_key_pem(&std::fs::read_to_string(path)?)?;
+
+        Ok(Self {
+            public_key: rsa_jwk(&public_key),
+            retired_at,
+        })
+    }
+
+    /// Loads every `.pub` file in `dir`. A missing directory means no key was retired yet.
+    pub fn load_dir(dir: &std::path::Path) -> anyhow::Result<Vec<Self>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut keys = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "pub") {
+                keys.push(Self::load(&path).with_context(|| format!("{}", path.display()))?);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+fn rsa_jwk(public_key: &RsaPublicKey) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            key_algorithm: Some(KeyAlgorithm::RS256),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+        }),
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct ValidationClaims {
-    /// User ID
-    uid: Uuid,
-    /// Energy Record ID
-    eri: Uuid,
-    /// Expiration Timestamp
-    exp: i64,
+/// Sets the key ID of a public key to its RFC 7638 thumbprint, and marks it for signatures
+fn identify(mut jwk: Jwk) -> Jwk {
+    jwk.common.key_id = Some(jwk.thumbprint(ThumbprintHash::SHA256));
+    jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+    jwk
+}
+
+/// A signed validation token, along with the claims it was issued with
+pub struct SignedToken {
+    pub token: String,
+    pub claims: RegisteredClaims,
 }
 
 impl ValidationSigner {
-    pub fn new(private_key: EncodingKey, max_age: Duration) -> Self {
-        Self {
+    /// Signs tokens with `private_key`, an RSA key, while `retired_keys` stay published for as long
+    /// as the tokens they signed can be valid
+    pub fn new(
+        private_key: EncodingKey,
+        retired_keys: Vec<RetiredValidationKey>,
+        issuer: String,
+        audience: String,
+        max_age: Duration,
+    ) -> anyhow::Result<Self> {
+        let algorithm = Algorithm::RS256;
+        let current = identify(Jwk::from_encoding_key(&private_key, algorithm)?);
+        let key_id = current.common.key_id.clone().unwrap_or_default();
+
+        let public_keys = std::iter::once(PublishedKey {
+            jwk: current,
+            valid_until: None,
+        })
+        .chain(retired_keys.into_iter().map(|retired| PublishedKey {
+            jwk: identify(retired.public_key),
+            valid_until: Some(retired.retired_at + max_age),
+        }))
+        .collect();
+
+        Ok(Self {
             private_key,
+            key_id,
+            algorithm,
+            issuer,
+            audience,
             max_age,
-        }
+            public_keys,
+        })
     }
 
+    #[cfg(test)]
+    pub const TEST_ISSUER: &str = "petall-test";
+
+    #[cfg(test)]
+    pub const TEST_AUDIENCE: &str = "petall-test-verifier";
+
+    #[cfg(test)]
+    pub const TEST_PRIVATE_KEY: &[u8] = include_bytes!("../test-keys/validation.key");
+
     #[cfg(test)]
     pub fn test_new() -> Self {
-        Self {
-            private_key: EncodingKey::from_secret(b"secret"),
-            max_age: Duration::from_secs(60),
+        Self::new(
+            EncodingKey::from_rsa_pem(Self::TEST_PRIVATE_KEY).unwrap(),
+            Vec::new(),
+            Self::TEST_ISSUER.to_string(),
+            Self::TEST_AUDIENCE.to_string(),
+            Duration::from_secs(60),
+        )
+        .unwrap()
+    }
+
+    /// Public keys tokens may currently be verified with
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+        JwkSet {
+            keys: self
+                .public_keys
+                .iter()
+                .filter(|key| key.valid_until.is_none_or(|valid_until| now < valid_until))
+                .map(|key| key.jwk.clone())
+                .collect(),
         }
     }
 
+    /// Claims of a new token, with a fresh ID
+    fn registered_claims(&self) -> RegisteredClaims {
+        let now = Utc::now();
+        RegisteredClaims {
+            jti: Uuid::new_v4(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            iat: now.timestamp(),
+            exp: (now + self.max_age).timestamp(),
+        }
+    }
+
+    fn sign<T: Serialize>(
+        &self,
+        claims: &T,
+        registered: &RegisteredClaims,
+    ) -> AppResult<SignedToken> {
+        let header = Header {
+            kid: Some(self.key_id.clone()),
+            ..Header::new(self.algorithm)
+        };
+        let token = jsonwebtoken::encode(&header, claims, &self.private_key)?;
+        Ok(SignedToken {
+            token,
+            claims: registered.clone(),
+        })
+    }
+
     pub fn create_validation_token(
         &self,
         user_id: Uuid,
         energy_record_id: Uuid,
-    ) -> AppResult<String> {
-        let expiration = (Utc::now() + self.max_age).timestamp();
-
+    ) -> AppResult<SignedToken> {
         let claims = ValidationClaims {
             uid: user_id,
             eri: energy_record_id,
-            exp: expiration,
+            registered: self.registered_claims(),
+        };
+
+        self.sign(&claims, &claims.registered)
+    }
+
+    pub fn create_batch_validation_token(
+        &self,
+        user_id: Uuid,
+        energy_record_ids: Vec<Uuid>,
+    ) -> AppResult<SignedToken> {
+        let claims = BatchValidationClaims {
+            uid: user_id,
+            eris: energy_record_ids,
+            registered: self.registered_claims(),
         };
 
-        let key = &self.private_key;
-        let token = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, key)?;
-        Ok(token)
+        self.sign(&claims, &claims.registered)
     }
+
+    pub fn create_range_validation_token(
+        &self,
+        user_id: Uuid,
+        community_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        totals: EnergyTotals,
+    ) -> AppResult<SignedToken> {
+        let claims = RangeValidationClaims {
+            uid: user_id,
+            cid: community_id,
+            start,
+            end,
+            cnt: totals.record_count as u64,
+            generated: totals.generated,
+            consumed: totals.consumed,
+            registered: self.registered_claims(),
+        };
+
+        self.sign(&claims, &claims.registered)
+    }
+}
+
+/// Public keys the trusted entity verifies validation tokens with
+#[debug_handler]
+pub async fn get_jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.validation_signer.jwks())
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SignEnergyRecordValidationResponse {
     pub signed_request: String,
 }
 
+/// Refuses records that are not in a committed snapshot yet, as the ZK service can't prove them
+async fn require_verifiable(state: &AppState, energy_record_ids: &[Uuid]) -> AppResult<()> {
+    let unverifiable = state
+        .get_unverifiable_energy_record_ids(energy_record_ids)
+        .await?;
+    let Some(&id) = unverifiable.first() else {
+        return Ok(());
+    };
+
+    let retry_after = state
+        .get_next_snapshot_commit()
+        .await?
+        .map(|next_commit| (next_commit - Utc::now()).num_seconds().max(1) as u64);
+    Err(AppError::EnergyRecordNotYetVerifiable { id, retry_after })
+}
+
 #[debug_handler]
 pub async fn sign_energy_record_validation_request(
     ExtractSession(session): ExtractSession,
+    ExtractClientIp(client_ip): ExtractClientIp,
     Path(energy_record_id): Path<Uuid>,
     State(state): State<AppState>,
 ) -> AppResult<Json<SignEnergyRecordValidationResponse>> {
@@ -85,12 +314,480 @@ pub async fn sign_energy_record_validation_request(
     if energy_record.user_id != user_id {
         return Err(AppError::EnergyRecordNotFound(energy_record_id));
     }
+    require_verifiable(&state, &[energy_record_id]).await?;
 
-    let token = state
+    let signed = state
         .validation_signer
         .create_validation_token(user_id, energy_record_id)?;
+    state
+        .record_validation_request(
+            user_id,
+            &signed.claims,
+            ValidationSubject::Records(&[energy_record_id]),
+            client_ip,
+        )
+        .await?;
+
+    Ok(Json(SignEnergyRecordValidationResponse {
+        signed_request: signed.token,
+    }))
+}
+
+/// Most records a batch validation token can cover
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Records to validate at once, either listed or as all of the user's records in a community over
+/// a period
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged, rename_all_fields = "camelCase")]
+pub enum SignEnergyBatchValidationRequest {
+    Records {
+        energy_record_ids: Vec<Uuid>,
+    },
+    Period {
+        community_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    },
+}
+
+#[debug_handler]
+pub async fn sign_energy_batch_validation_request(
+    ExtractSession(session): ExtractSession,
+    ExtractClientIp(client_ip): ExtractClientIp,
+    State(state): State<AppState>,
+    Json(request): Json<SignEnergyBatchValidationRequest>,
+) -> AppResult<Json<SignEnergyRecordValidationResponse>> {
+    let user_id = session.user_id;
+
+    let energy_record_ids = match request {
+        SignEnergyBatchValidationRequest::Records {
+            mut energy_record_ids,
+        } => {
+            energy_record_ids.sort();
+            energy_record_ids.dedup();
+
+            // Records of other users are reported as missing, like in the single record endpoint
+            let owned = state
+                .get_user_energy_record_ids(user_id, &energy_record_ids)
+                .await?;
+            if let Some(missing) = energy_record_ids.iter().find(|id| !owned.contains(id)) {
+                return Err(AppError::EnergyRecordNotFound(*missing));
+            }
+            energy_record_ids
+        }
+        SignEnergyBatchValidationRequest::Period {
+            community_id,
+            start,
+            end,
+        } => {
+            if start >= end {
+                return Err(AppError::InvalidPeriod);
+            }
+            state
+                .get_user_energy_record_ids_in_period(user_id, community_id, start, end)
+                .await?
+        }
+    };
+
+    if energy_record_ids.is_empty() || energy_record_ids.len() > MAX_BATCH_SIZE {
+        return Err(AppError::InvalidBatchSize {
+            max: MAX_BATCH_SIZE,
+        });
+    }
+    require_verifiable(&state, &energy_record_ids).await?;
+
+    let signed = state
+        .validation_signer
+        .create_batch_validation_token(user_id, energy_record_ids.clone())?;
+    state
+        .record_validation_request(
+            user_id,
+            &signed.claims,
+            ValidationSubject::Records(&energy_record_ids),
+            client_ip,
+        )
+        .await?;
+
+    Ok(Json(SignEnergyRecordValidationResponse {
+        signed_request: signed.token,
+    }))
+}
+
+/// The validation requests of the current user, most recent first
+#[debug_handler]
+pub async fn get_validation_history(
+    ExtractSession(session): ExtractSession,
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<ValidationRequest>>> {
+    let filter = ValidationRequestFilter {
+        user_id: Some(session.user_id),
+        ..Default::default()
+    };
+    Ok(Json(state.get_validation_requests(&filter).await?))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignEnergyRangeValidationRequest {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+#[debug_handler]
+pub async fn sign_energy_range_validation_request(
+    ExtractSession(session): ExtractSession,
+    ExtractClientIp(client_ip): ExtractClientIp,
+    Path(community_id): Path<Uuid>,
+    State(state): State<AppState>,
+    Json(request): Json<SignEnergyRangeValidationRequest>,
+) -> AppResult<Json<SignEnergyRecordValidationResponse>> {
+    let user_id = session.user_id;
+    if request.start >= request.end {
+        return Err(AppError::InvalidPeriod);
+    }
+
+    let is_member = state
+        .get_user_communities(&user_id)
+        .await?
+        .iter()
+        .any(|membership| membership.community_id == community_id);
+    if !is_member {
+        return Err(AppError::UserNotInCommunity(community_id));
+    }
+
+    // The ZK service can only prove the totals once every record of the period is committed
+    let energy_record_ids = state
+        .get_user_energy_record_ids_in_period(user_id, community_id, request.start, request.end)
+        .await?;
+    require_verifiable(&state, &energy_record_ids).await?;
+    let totals = state
+        .get_user_committed_energy_totals(user_id, community_id, request.start, request.end)
+        .await?;
+
+    let signed = state.validation_signer.create_range_validation_token(
+        user_id,
+        community_id,
+        request.start,
+        request.end,
+        totals,
+    )?;
+    state
+        .record_validation_request(
+            user_id,
+            &signed.claims,
+            ValidationSubject::Period {
+                community_id,
+                start: request.start,
+                end: request.end,
+            },
+            client_ip,
+        )
+        .await?;
 
     Ok(Json(SignEnergyRecordValidationResponse {
-        signed_request: token,
+        signed_request: signed.token,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{StatusCode, header};
+    use axum_test::TestServer;
+    use bigdecimal::BigDecimal;
+    use chrono::{Duration, SubsecRound};
+    use jsonwebtoken::{DecodingKey, Validation};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::router::{
+        router,
+        test_utils::{commit_all_records, create_test_community, register, test_state},
+    };
+
+    #[sqlx::test]
+    async fn range_validation_token_covers_period_totals(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+
+        let user = register(&server, "member@example.com").await;
+        let community = create_test_community(&state, "Range Community", &[user.uuid]).await;
+
+        // At the precision periods are stored with
+        let end = Utc::now().naive_utc().trunc_subsecs(6) - Duration::days(30);
+        let start = end - Duration::days(30);
+        let path = format!("/sign-energy-range-validation/{}", community.id);
+
+        // Totals are only signed once the records of the period are committed, and before the
+        // first commit there is no telling when to retry
+        let response = server
+            .post(&path)
+            .json(&SignEnergyRangeValidationRequest { start, end })
+            .add_header("Authorization", user.session_id.to_string())
+            .await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+
+        commit_all_records(&state).await;
+        let response = server
+            .post(&path)
+            .json(&SignEnergyRangeValidationRequest { start, end })
+            .add_header("Authorization", user.session_id.to_string())
+            .add_header("X-Forwarded-For", "203.0.113.7")
+            .await;
+        response.assert_status_ok();
+        let token = response
+            .json::<SignEnergyRecordValidationResponse>()
+            .signed_request;
+
+        // Tokens are verified with the published key their header names
+        let jwks = server.get("/.well-known/jwks.json").await.json::<JwkSet>();
+        let kid = jsonwebtoken::decode_header(&token).unwrap().kid.unwrap();
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[ValidationSigner::TEST_ISSUER]);
+        validation.set_audience(&[ValidationSigner::TEST_AUDIENCE]);
+        let claims = jsonwebtoken::decode::<RangeValidationClaims>(
+            &token,
+            &DecodingKey::from_jwk(jwks.find(&kid).unwrap()).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+
+        let records: Vec<_> = state
+            .get_all_energy_records()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.start >= start && record.start < end)
+            .collect();
+
+        assert_eq!((claims.uid, claims.cid), (user.uuid, community.id));
+        assert_eq!(claims.cnt, records.len() as u64);
+        assert_eq!(
+            claims.generated,
+            records
+                .iter()
+                .map(|record| &record.generated)
+                .sum::<BigDecimal>()
+        );
+        assert_eq!(
+            claims.consumed,
+            records
+                .iter()
+                .map(|record| &record.consumed)
+                .sum::<BigDecimal>()
+        );
+
+        // The request is audited with the community and period instead of records
+        let audited = state
+            .get_validation_requests(&ValidationRequestFilter {
+                token_id: Some(claims.registered.jti),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(audited.len(), 1);
+        assert_eq!(audited[0].energy_record_id, None);
+        assert_eq!(audited[0].community_id, Some(community.id));
+        assert_eq!(
+            (audited[0].period_start, audited[0].period_end),
+            (Some(start), Some(end))
+        );
+        assert_eq!(audited[0].client_ip.as_deref(), Some("203.0.113.7"));
+
+        // Periods must not be empty
+        server
+            .post(&path)
+            .json(&SignEnergyRangeValidationRequest { start: end, end })
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        // Only members can ask for a range token
+        let other = create_test_community(&state, "Other Community", &[]).await;
+        server
+            .post(&format!("/sign-energy-range-validation/{}", other.id))
+            .json(&SignEnergyRangeValidationRequest { start, end })
+            .add_header("Authorization", user.session_id.to_string())
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn validation_requests_are_audited(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+
+        let member = register(&server, "member@example.com").await;
+        let admin = register(&server, "admin@example.com").await;
+        state.set_admin(admin.uuid, true).await.unwrap();
+
+        create_test_community(&state, "Audit", &[member.uuid]).await;
+        commit_all_records(&state).await;
+        let records = state.get_all_energy_records().await.unwrap();
+
+        for (record, ip) in [(&records[0], "203.0.113.7"), (&records[1], "198.51.100.1")] {
+            server
+                .get(&format!("/sign-energy-record-validation/{}", record.id))
+                .add_header("Authorization", member.session_id.to_string())
+                .add_header("X-Forwarded-For", format!("10.0.0.1, {ip}"))
+                .await
+                .assert_status_ok();
+        }
+
+        let history = server
+            .get("/validation-request")
+            .add_header("Authorization", member.session_id.to_string())
+            .await
+            .json::<Vec<ValidationRequest>>();
+        assert_eq!(history.len(), 2);
+        // Most recent first, with the address the proxy saw
+        assert_eq!(history[0].energy_record_id, Some(records[1].id));
+        assert_eq!(history[0].client_ip.as_deref(), Some("198.51.100.1"));
+        assert!(history.iter().all(|request| request.user_id == member.uuid));
+
+        let path = format!("/admin/validation-request?energyRecordId={}", records[0].id);
+        let requests = server
+            .get(&path)
+            .add_header("Authorization", admin.session_id.to_string())
+            .await
+            .json::<Vec<ValidationRequest>>();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].token_id, history[1].token_id);
+
+        server
+            .get(&path)
+            .add_header("Authorization", member.session_id.to_string())
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn batch_validation_token_covers_records(pool: PgPool) {
+        let state = test_state(pool);
+        let server = TestServer::new(router(state.clone())).unwrap();
+
+        let member = register(&server, "member@example.com").await;
+        let other = register(&server, "other@example.com").await;
+        let community = create_test_community(&state, "Batch", &[member.uuid, other.uuid]).await;
+        commit_all_records(&state).await;
+        let records = state.get_all_energy_records().await.unwrap();
+        let records_of = |user_id| {
+            records
+                .iter()
+                .filter(move |record| record.user_id == user_id)
+                .collect::<Vec<_>>()
+        };
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[ValidationSigner::TEST_ISSUER]);
+        validation.set_audience(&[ValidationSigner::TEST_AUDIENCE]);
+        let decoding_key = DecodingKey::from_jwk(&state.validation_signer.jwks().keys[0]).unwrap();
+        let sign = |request| {
+            server
+                .post("/sign-energy-batch-validation")
+                .json(&request)
+                .add_header("Authorization", member.session_id.to_string())
+        };
+
+        let mut listed: Vec<_> = records_of(member.uuid)[..3]
+            .iter()
+            .map(|record| record.id)
+            .collect();
+        let token = sign(SignEnergyBatchValidationRequest::Records {
+            energy_record_ids: listed.clone(),
+        })
+        .await
+        .json::<SignEnergyRecordValidationResponse>()
+        .signed_request;
+        let claims =
+            jsonwebtoken::decode::<BatchValidationClaims>(&token, &decoding_key, &validation)
+                .unwrap()
+                .claims;
+        listed.sort();
+        assert_eq!(claims.eris, listed);
+
+        // Every record of the batch is in the audit trail under the same token
+        let audited = state
+            .get_validation_requests(&ValidationRequestFilter {
+                token_id: Some(claims.registered.jti),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(audited.len(), 3);
+
+        let end = Utc::now().naive_utc() - Duration::days(30);
+        let start = end - Duration::days(2);
+        let token = sign(SignEnergyBatchValidationRequest::Period {
+            community_id: community.id,
+            start,
+            end,
+        })
+        .await
+        .json::<SignEnergyRecordValidationResponse>()
+        .signed_request;
+        let claims =
+            jsonwebtoken::decode::<BatchValidationClaims>(&token, &decoding_key, &validation)
+                .unwrap()
+                .claims;
+        let in_period = records_of(member.uuid)
+            .into_iter()
+            .filter(|record| record.start >= start && record.start < end)
+            .count();
+        assert_eq!(claims.eris.len(), in_period);
+
+        // Records of other users can't be batched in
+        let foreign = records_of(other.uuid)[0].id;
+        sign(SignEnergyBatchValidationRequest::Records {
+            energy_record_ids: vec![listed[0], foreign],
+        })
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+        sign(SignEnergyBatchValidationRequest::Records {
+            energy_record_ids: Vec::new(),
+        })
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn retired_keys_are_published_until_their_tokens_expire() {
+        let path = std::path::Path::new("test-keys/validation-20251001T120000Z.key.pub");
+        // The retirement time is taken from the file name, whatever the file's own times are
+        assert_eq!(
+            RetiredValidationKey::load(path).unwrap().retired_at,
+            "2025-10-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert!(
+            RetiredValidationKey::load(std::path::Path::new("test-keys/validation.key")).is_err()
+        );
+
+        let retired_key = |retired_at| RetiredValidationKey {
+            retired_at,
+            ..RetiredValidationKey::load(path).unwrap()
+        };
+        let max_age = std::time::Duration::from_secs(600);
+
+        for (retired_at, published) in [
+            (Utc::now() - Duration::seconds(60), true),
+            (Utc::now() - Duration::seconds(601), false),
+        ] {
+            let signer = ValidationSigner::new(
+                EncodingKey::from_rsa_pem(ValidationSigner::TEST_PRIVATE_KEY).unwrap(),
+                vec![retired_key(retired_at)],
+                ValidationSigner::TEST_ISSUER.to_string(),
+                ValidationSigner::TEST_AUDIENCE.to_string(),
+                max_age,
+            )
+            .unwrap();
+
+            let jwks = signer.jwks();
+            assert_eq!(jwks.keys.len(), if published { 2 } else { 1 });
+            // The current key always signs
+            assert_eq!(jwks.keys[0].common.key_id.as_ref(), Some(&signer.key_id));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-export type RegisterRequest = { name: string; email: string; password: string; isAdmin: boolean };
+export type RegisterRequest = { name: string; email: string; password: string };
 export type RegisterResponse = {
 	uuid: string;
 	name: string;
@@ -14,15 +14,58 @@ export type LoginResponse = {
 	sessionId: string;
 };
 
+/** Answer to a login with 202 Accepted, when the user has two-factor authentication */
+export type TwoFactorChallengeResponse = { challengeId: string; expiresAt: string };
+export type TwoFactorLoginRequest = { challengeId: string; code: string };
+
 export type ChangePasswordRequest = { oldPassword: string; newPassword: string };
 export type ChangePasswordResponse = { message: string; sessionId: string };
 
+export type PasswordResetRequest = { email: string };
+export type ConfirmPasswordResetRequest = { token: string; newPassword: string };
+export type PasswordResetResponse = { message: string };
+
+export type VerifyEmailRequest = { token: string };
+export type VerifyEmailResponse = { message: string };
+
 export type RevokeResponse = { message: string };
-export type MeResponse = { id: string; email: string; name: string };
+export type SessionResponse = {
+	id: string;
+	createdAt: string;
+	lastSeenAt: string;
+	expiration: string;
+	maxExpiration: string;
+	userAgent: string | null;
+	clientIp: string | null;
+	current: boolean;
+};
+export type RevokeOtherSessionsResponse = { revoked: number };
+export type MeResponse = {
+	id: string;
+	email: string;
+	name: string;
+	emailVerified: boolean;
+	twoFactorEnabled: boolean;
+	twoFactorSetupRequired: boolean;
+};
+
+export type TwoFactorStatusResponse = {
+	enabled: boolean;
+	recoveryCodesLeft: number;
+	setupRequired: boolean;
+};
+export type TwoFactorSetupResponse = {
+	secret: string;
+	otpauthUri: string;
+	qrCode: string | null;
+};
+export type TwoFactorCodeRequest = { code: string };
+export type RecoveryCodesResponse = { recoveryCodes: string[] };
 
 // OAuth types
 export type OAuthUrlResponse = {
 	authorizationUrl: string;
+	state: string;
 };
 
 export type OAuthCallbackResponse = {
@@ -32,3 +75,8 @@ export type OAuthCallbackResponse = {
 	email: string;
 	name: string;
 };
+
+export type OidcProviderResponse = {
+	name: string;
+	displayName: string;
+};
@@ -1,9 +1,11 @@
-import type { Community, EnergyRecord } from '$lib';
+import type { Community, DistributionRule, EnergyRecord, ProofScheme } from '$lib';
 
 export type CommunityCreateRequest = {
 	name: string;
 	description: string;
 	image?: string;
+	distribution_rule: DistributionRule;
+	proof_scheme: ProofScheme;
 };
 
 export type CommunityCreateResponse = Community;
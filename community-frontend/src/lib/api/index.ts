@@ -8,5 +8,8 @@ export type User = {
 	email: string;
 	name: string;
 	isAdmin: boolean;
+	emailVerified: boolean;
+	twoFactorEnabled: boolean;
+	twoFactorSetupRequired: boolean;
 	canAccessAdminView: boolean;
 };
@@ -13,6 +13,24 @@ export async function handleValidate(record: EnergyRecord, session_id: string) {
 		}
 	});
 
+	await redeemToken(response);
+}
+
+/** Validates many records with a single token */
+export async function handleValidateBatch(records: EnergyRecord[], session_id: string) {
+	const response = await fetch('/api/sign-energy-batch-validation', {
+		method: 'POST',
+		headers: {
+			Authorization: session_id,
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify({ energyRecordIds: records.map((record) => record.id) })
+	});
+
+	await redeemToken(response);
+}
+
+async function redeemToken(response: Response) {
 	if (!response.ok) {
 		console.error('Validation failed');
 		return;
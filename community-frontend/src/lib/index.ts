@@ -19,7 +19,23 @@ export type Community = {
 	name: string;
 	image?: string;
 	description: string;
-	// rule: string;
+	distribution_rule: DistributionRule;
+	proof_scheme: ProofScheme;
+};
+
+export type DistributionRule = 'equal' | 'proportionalToConsumption';
+
+export const distributionRuleLabels: Record<DistributionRule, string> = {
+	equal: 'Equal shares',
+	proportionalToConsumption: 'Proportional to consumption'
+};
+
+export type ProofScheme = 'aggregateCommitment' | 'commitmentOpening' | 'attestation';
+
+export const proofSchemeLabels: Record<ProofScheme, string> = {
+	aggregateCommitment: 'Daily aggregates',
+	commitmentOpening: 'Open commitments',
+	attestation: 'Signed attestation'
 };
 
 export type EnergyRecord = {
@@ -31,6 +47,8 @@ export type EnergyRecord = {
 	consumerPrice: number;
 	sellerPrice: number;
 	start: string;
+	/** When the record became part of a committed snapshot, only listings include it */
+	verifiableSince?: string | null;
 };
 
 export type EnergyStats = {
@@ -0,0 +1,125 @@
+import { redirect, type Cookies } from '@sveltejs/kit';
+import type {
+	OAuthCallbackResponse,
+	OAuthUrlResponse,
+	OidcProviderResponse
+} from '$lib/api/auth';
+import type { ErrorResponse } from '$lib/api';
+import { continueWithSecondFactor, deviceHeaders } from '$lib/server/session';
+
+type Fetch = typeof fetch;
+
+/** How an OAuth provider's login is started and finished through the backend */
+export type OAuthFlow = {
+	/** Backend endpoint returning the authorization URL */
+	start: string;
+	/** Backend endpoint the code and state are handed to */
+	callback: string;
+	/** Frontend path the provider redirects back to */
+	callbackPath: string;
+};
+
+export const googleFlow: OAuthFlow = {
+	start: '/api/auth/oauth/google',
+	callback: '/api/auth/callback',
+	callbackPath: '/callback'
+};
+
+export const githubFlow: OAuthFlow = {
+	start: '/api/auth/oauth/github',
+	callback: '/api/auth/oauth/github/callback',
+	callbackPath: '/oauth/github/callback'
+};
+
+/** A configured OpenID Connect provider, such as a regional agency's Keycloak */
+export function oidcFlow(provider: string): OAuthFlow {
+	const name = encodeURIComponent(provider);
+	return {
+		start: `/api/auth/oidc/${name}`,
+		callback: `/api/auth/oidc/${name}/callback`,
+		callbackPath: `/oauth/oidc/${name}/callback`
+	};
+}
+
+/** The OpenID Connect providers to offer next to Google and GitHub */
+export async function listOidcProviders(fetch: Fetch): Promise<OidcProviderResponse[]> {
+	const response = await fetch('/api/auth/oidc');
+	if (!response.ok) {
+		console.error('Failed to list OIDC providers:', response.status);
+		return [];
+	}
+	return response.json();
+}
+
+/** Redirects to the provider, keeping the state in a cookie to check the callback against */
+export async function startOAuth(flow: OAuthFlow, fetch: Fetch, cookies: Cookies): Promise<never> {
+	const response = await fetch(flow.start);
+
+	if (!response.ok) {
+		const errorText = await response.text();
+		console.error('OAuth init failed:', response.status, errorText);
+		throw redirect(303, '/login?error=oauth_init_failed');
+	}
+
+	const data: OAuthUrlResponse = await response.json();
+
+	// Ties the login to this browser, so a callback started elsewhere is refused
+	cookies.set('oauthState', data.state, {
+		path: flow.callbackPath,
+		httpOnly: true,
+		sameSite: 'lax',
+		maxAge: 10 * 60
+	});
+
+	throw redirect(303, data.authorizationUrl);
+}
+
+/** Hands the code to the backend if the state matches the cookie, and signs the user in */
+export async function finishOAuth(
+	flow: OAuthFlow,
+	url: URL,
+	request: Request,
+	fetch: Fetch,
+	cookies: Cookies
+): Promise<never> {
+	const code = url.searchParams.get('code');
+	const state = url.searchParams.get('state');
+	const error = url.searchParams.get('error');
+	const expectedState = cookies.get('oauthState');
+	cookies.delete('oauthState', { path: flow.callbackPath });
+
+	if (error) {
+		throw redirect(303, `/login?error=${encodeURIComponent(error)}`);
+	}
+
+	if (!code) {
+		throw redirect(303, '/login?error=missing_code');
+	}
+
+	if (!state || state !== expectedState) {
+		throw redirect(303, '/login?error=invalid_state');
+	}
+
+	const response = await fetch(
+		`${flow.callback}?code=${encodeURIComponent(code)}&state=${encodeURIComponent(state)}`,
+		{ headers: deviceHeaders(request) }
+	);
+
+	if (!response.ok) {
+		const errorData: ErrorResponse = await response.json();
+		throw redirect(303, `/login?error=${encodeURIComponent(errorData.error)}`);
+	}
+
+	if (response.status === 202) {
+		await continueWithSecondFactor(response, cookies);
+	}
+
+	const data: OAuthCallbackResponse = await response.json();
+
+	cookies.set('sessionId', data.sessionId, {
+		path: '/',
+		httpOnly: true
+	});
+
+	throw redirect(303, '/');
+}
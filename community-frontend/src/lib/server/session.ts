@@ -0,0 +1,28 @@
+import { redirect, type Cookies } from '@sveltejs/kit';
+import type { TwoFactorChallengeResponse } from '$lib/api/auth';
+
+/** Passes the browser's user agent on, so the backend can tell the user's sessions apart */
+export function deviceHeaders(request: Request): Record<string, string> {
+	const userAgent = request.headers.get('user-agent');
+	return userAgent ? { 'User-Agent': userAgent } : {};
+}
+
+/**
+ * Keeps a pending two-factor challenge in a cookie and sends the user to enter their code,
+ * when the backend answers a login with 202 Accepted
+ */
+export async function continueWithSecondFactor(
+	response: Response,
+	cookies: Cookies
+): Promise<never> {
+	const { challengeId, expiresAt }: TwoFactorChallengeResponse = await response.json();
+
+	cookies.set('loginChallenge', challengeId, {
+		path: '/login/two-factor',
+		httpOnly: true,
+		sameSite: 'lax',
+		expires: new Date(expiresAt)
+	});
+
+	throw redirect(303, '/login/two-factor');
+}
@@ -20,6 +20,11 @@ export const load: PageServerLoad = async ({ fetch, cookies, locals }) => {
 		}
 	});
 
+	if (response.status == 403) {
+		// The auth policy wants two-factor authentication first
+		throw redirect(302, '/two-factor');
+	}
+
 	const communities: (Community & { userCount: string })[] = await response.json();
 
 	return {
@@ -21,6 +21,10 @@ export const load: PageServerLoad = async ({ fetch, params, cookies }) => {
 		throw redirect(303, '/');
 	}
 
+	if (response.status === 403) {
+		throw redirect(303, '/two-factor');
+	}
+
 	if (!response.ok) {
 		throw new Error('Failed to fetch community');
 	}
@@ -2,6 +2,12 @@ import { fail, redirect } from '@sveltejs/kit';
 import type { PageServerLoad, Actions } from './$types';
 import type { ErrorResponse } from '$lib/api';
 import type { CommunityCreateRequest, CommunityCreateResponse } from '$lib/api/community';
+import {
+	distributionRuleLabels,
+	proofSchemeLabels,
+	type DistributionRule,
+	type ProofScheme
+} from '$lib';
 
 export const load: PageServerLoad = async ({ cookies, locals }) => {
 	const sessionId = cookies.get('sessionId');
@@ -21,11 +27,21 @@ export const actions = {
 		const name = data.get('name')?.toString().trim();
 		const description = data.get('description')?.toString().trim();
 		const image = data.get('image')?.toString();
+		const distributionRule = data.get('distributionRule')?.toString() ?? '';
+		const proofScheme = data.get('proofScheme')?.toString() ?? '';
 
 		if (!name || !description) {
 			return fail(400, { name, description, image, error: 'Missing name or description' });
 		}
 
+		if (!(distributionRule in distributionRuleLabels)) {
+			return fail(400, { name, description, image, error: 'Invalid distribution rule' });
+		}
+
+		if (!(proofScheme in proofSchemeLabels)) {
+			return fail(400, { name, description, image, error: 'Invalid proof scheme' });
+		}
+
 		const sessionId = cookies.get('sessionId');
 
 		if (!sessionId) {
@@ -35,7 +51,9 @@ export const actions = {
 		const req: CommunityCreateRequest = {
 			name,
 			description,
-			image
+			image,
+			distribution_rule: distributionRule as DistributionRule,
+			proof_scheme: proofScheme as ProofScheme
 		};
 
 		const response = await fetch('/api/admin/community', {
@@ -0,0 +1,128 @@
+import { fail, redirect, type Cookies } from '@sveltejs/kit';
+import type { Actions, PageServerLoad } from './$types';
+import type { ErrorResponse } from '$lib/api';
+import type {
+	RecoveryCodesResponse,
+	TwoFactorCodeRequest,
+	TwoFactorSetupResponse,
+	TwoFactorStatusResponse
+} from '$lib/api/auth';
+
+export const load: PageServerLoad = async ({ cookies, fetch }) => {
+	const sessionId = cookies.get('sessionId');
+
+	if (!sessionId) {
+		throw redirect(302, '/login');
+	}
+
+	const response = await fetch('/api/auth/two-factor', {
+		headers: { Authorization: sessionId }
+	});
+
+	if (response.status == 401) {
+		throw redirect(302, '/login');
+	}
+
+	const status: TwoFactorStatusResponse = await response.json();
+	return { status };
+};
+
+/** Posts a code from the form to one of the two-factor endpoints */
+async function postCode(
+	path: string,
+	data: FormData,
+	cookies: Cookies,
+	fetch: typeof globalThis.fetch
+) {
+	const sessionId = cookies.get('sessionId');
+	if (!sessionId) {
+		redirect(303, '/login');
+	}
+
+	const code = data.get('code')?.toString().trim();
+	if (!code) {
+		return { error: 'Missing code', status: 400 };
+	}
+
+	const codeRequest: TwoFactorCodeRequest = { code };
+	const response = await fetch(path, {
+		method: 'POST',
+		headers: {
+			Authorization: sessionId,
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(codeRequest)
+	});
+
+	if (!response.ok) {
+		const error: ErrorResponse = await response.json();
+		return { error: error.error, status: response.status };
+	}
+
+	return { response };
+}
+
+export const actions = {
+	setup: async ({ cookies, fetch }) => {
+		const sessionId = cookies.get('sessionId');
+		if (!sessionId) {
+			redirect(303, '/login');
+		}
+
+		const response = await fetch('/api/auth/two-factor/setup', {
+			method: 'POST',
+			headers: { Authorization: sessionId }
+		});
+
+		if (!response.ok) {
+			const error: ErrorResponse = await response.json();
+			return fail(response.status, { error: error.error });
+		}
+
+		const setup: TwoFactorSetupResponse = await response.json();
+		return { setup };
+	},
+	enable: async ({ cookies, request, fetch }) => {
+		const data = await request.formData();
+		const result = await postCode('/api/auth/two-factor/enable', data, cookies, fetch);
+		if (!result.response) {
+			// Keep showing the same secret, so a mistyped code can be entered again
+			const setup: TwoFactorSetupResponse = {
+				secret: data.get('secret')?.toString() ?? '',
+				otpauthUri: '',
+				qrCode: data.get('qrCode')?.toString() || null
+			};
+			return fail(result.status, { error: result.error, setup });
+		}
+
+		const { recoveryCodes }: RecoveryCodesResponse = await result.response.json();
+		return { recoveryCodes };
+	},
+	recoveryCodes: async ({ cookies, request, fetch }) => {
+		const result = await postCode(
+			'/api/auth/two-factor/recovery-codes',
+			await request.formData(),
+			cookies,
+			fetch
+		);
+		if (!result.response) {
+			return fail(result.status, { error: result.error });
+		}
+
+		const { recoveryCodes }: RecoveryCodesResponse = await result.response.json();
+		return { recoveryCodes };
+	},
+	disable: async ({ cookies, request, fetch }) => {
+		const result = await postCode(
+			'/api/auth/two-factor/disable',
+			await request.formData(),
+			cookies,
+			fetch
+		);
+		if (!result.response) {
+			return fail(result.status, { error: result.error });
+		}
+
+		return { disabled: true };
+	}
+} satisfies Actions;
@@ -0,0 +1,37 @@
+import { fail, redirect } from '@sveltejs/kit';
+import type { Actions, PageServerLoad } from './$types';
+import type { PasswordResetRequest, PasswordResetResponse } from '$lib/api/auth';
+import type { ErrorResponse } from '$lib/api';
+
+export const load: PageServerLoad = async ({ locals }) => {
+	if (locals.user) {
+		throw redirect(302, '/');
+	}
+};
+
+export const actions = {
+	default: async ({ request, fetch }) => {
+		const data = await request.formData();
+		const email = data.get('email')?.toString().trim();
+
+		if (!email) {
+			return fail(400, { email, error: 'Missing email' });
+		}
+
+		const resetRequest: PasswordResetRequest = { email };
+
+		const response = await fetch('/api/auth/password-reset/request', {
+			method: 'POST',
+			headers: { 'Content-Type': 'application/json' },
+			body: JSON.stringify(resetRequest)
+		});
+
+		if (!response.ok) {
+			const error: ErrorResponse = await response.json();
+			return fail(response.status, { email, error: error.error });
+		}
+
+		const { message }: PasswordResetResponse = await response.json();
+		return { email, message };
+	}
+} satisfies Actions;
@@ -2,8 +2,10 @@ import { fail, redirect } from '@sveltejs/kit';
 import type { Actions, PageServerLoad } from './$types';
 import type { LoginRequest, LoginResponse } from '$lib/api/auth';
 import type { ErrorResponse } from '$lib/api';
+import { listOidcProviders } from '$lib/server/oauth';
+import { continueWithSecondFactor, deviceHeaders } from '$lib/server/session';
 
-export const load: PageServerLoad = async ({ locals, url }) => {
+export const load: PageServerLoad = async ({ locals, url, fetch }) => {
 	const { user } = locals;
 
 	if (user) {
@@ -14,7 +16,8 @@ export const load: PageServerLoad = async ({ locals, url }) => {
 	const error = url.searchParams.get('error');
 
 	return {
-		error
+		error,
+		oidcProviders: await listOidcProviders(fetch)
 	};
 };
 
@@ -36,6 +39,7 @@ export const actions = {
 		const response = await fetch('/api/auth/login', {
 			method: 'POST',
 			headers: {
+				...deviceHeaders(request),
 				'Content-Type': 'application/json'
 			},
 			body: JSON.stringify(loginRequest)
@@ -46,6 +50,10 @@ export const actions = {
 			return fail(response.status, { email, error: error.error });
 		}
 
+		if (response.status === 202) {
+			await continueWithSecondFactor(response, cookies);
+		}
+
 		const { sessionId }: LoginResponse = await response.json();
 
 		cookies.set('sessionId', sessionId, {
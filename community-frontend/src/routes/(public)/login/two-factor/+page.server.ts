@@ -0,0 +1,53 @@
+import { fail, redirect } from '@sveltejs/kit';
+import type { Actions, PageServerLoad } from './$types';
+import type { LoginResponse, TwoFactorLoginRequest } from '$lib/api/auth';
+import type { ErrorResponse } from '$lib/api';
+import { deviceHeaders } from '$lib/server/session';
+
+export const load: PageServerLoad = async ({ cookies }) => {
+	if (!cookies.get('loginChallenge')) {
+		// Nothing to complete, the password comes first
+		throw redirect(302, '/login');
+	}
+};
+
+export const actions = {
+	default: async ({ cookies, request, fetch }) => {
+		const data = await request.formData();
+		const code = data.get('code')?.toString().trim();
+		const challengeId = cookies.get('loginChallenge');
+
+		if (!challengeId) {
+			redirect(303, '/login');
+		}
+		if (!code) {
+			return fail(400, { error: 'Missing code' });
+		}
+
+		const loginRequest: TwoFactorLoginRequest = { challengeId, code };
+
+		const response = await fetch('/api/auth/login/two-factor', {
+			method: 'POST',
+			headers: {
+				...deviceHeaders(request),
+				'Content-Type': 'application/json'
+			},
+			body: JSON.stringify(loginRequest)
+		});
+
+		if (!response.ok) {
+			const error: ErrorResponse = await response.json();
+			return fail(response.status, { error: error.error });
+		}
+
+		const { sessionId }: LoginResponse = await response.json();
+
+		cookies.delete('loginChallenge', { path: '/login/two-factor' });
+		cookies.set('sessionId', sessionId, {
+			path: '/',
+			httpOnly: true
+		});
+
+		redirect(303, '/');
+	}
+} satisfies Actions;
@@ -0,0 +1,6 @@
+import type { RequestHandler } from './$types';
+import { githubFlow, startOAuth } from '$lib/server/oauth';
+
+export const GET: RequestHandler = async ({ fetch, cookies }) => {
+	return startOAuth(githubFlow, fetch, cookies);
+};
@@ -0,0 +1,6 @@
+import type { RequestHandler } from './$types';
+import { finishOAuth, githubFlow } from '$lib/server/oauth';
+
+export const GET: RequestHandler = async ({ url, request, fetch, cookies }) => {
+	return finishOAuth(githubFlow, url, request, fetch, cookies);
+};
@@ -0,0 +1,6 @@
+import type { RequestHandler } from './$types';
+import { oidcFlow, startOAuth } from '$lib/server/oauth';
+
+export const GET: RequestHandler = async ({ params, fetch, cookies }) => {
+	return startOAuth(oidcFlow(params.provider), fetch, cookies);
+};
@@ -0,0 +1,6 @@
+import type { RequestHandler } from './$types';
+import { finishOAuth, oidcFlow } from '$lib/server/oauth';
+
+export const GET: RequestHandler = async ({ params, url, request, fetch, cookies }) => {
+	return finishOAuth(oidcFlow(params.provider), url, request, fetch, cookies);
+};
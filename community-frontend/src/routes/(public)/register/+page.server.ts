@@ -1,9 +1,11 @@
 import { fail, redirect } from '@sveltejs/kit';
 import type { RegisterRequest, RegisterResponse } from '$lib/api/auth';
 import type { ErrorResponse } from '$lib/api';
+import { listOidcProviders } from '$lib/server/oauth';
+import { deviceHeaders } from '$lib/server/session';
 import type { Actions, PageServerLoad } from './$types';
 
-export const load: PageServerLoad = async ({ locals, url }) => {
+export const load: PageServerLoad = async ({ locals, url, fetch }) => {
 	const { user } = locals;
 
 	if (user) {
@@ -14,7 +16,8 @@ export const load: PageServerLoad = async ({ locals, url }) => {
 	const error = url.searchParams.get('error');
 
 	return {
-		error
+		error,
+		oidcProviders: await listOidcProviders(fetch)
 	};
 };
 
@@ -24,7 +27,6 @@ export const actions = {
 		const name = data.get('name')?.toString().trim();
 		const email = data.get('email')?.toString().trim();
 		const password = data.get('password')?.toString();
-		const isAdmin = data.get('is_admin')?.toString() === 'on';
 
 		if (!name || !email || !password) {
 			return fail(400, { name, email, error: 'Missing name, email or password' });
@@ -33,13 +35,13 @@ export const actions = {
 		const registerRequest: RegisterRequest = {
 			name,
 			email,
-			password,
-			isAdmin
+			password
 		};
 
 		const response = await fetch('/api/auth/register', {
 			method: 'POST',
 			headers: {
+				...deviceHeaders(request),
 				'Content-Type': 'application/json'
 			},
 			body: JSON.stringify(registerRequest)
@@ -0,0 +1,39 @@
+import { fail, redirect } from '@sveltejs/kit';
+import type { Actions, PageServerLoad } from './$types';
+import type { ConfirmPasswordResetRequest } from '$lib/api/auth';
+import type { ErrorResponse } from '$lib/api';
+
+export const load: PageServerLoad = async ({ url }) => {
+	return { token: url.searchParams.get('token') ?? '' };
+};
+
+export const actions = {
+	default: async ({ request, fetch }) => {
+		const data = await request.formData();
+		const token = data.get('token')?.toString();
+		const newPassword = data.get('newPassword')?.toString();
+		const confirmPassword = data.get('confirmPassword')?.toString();
+
+		if (!token) {
+			return fail(400, { error: 'Missing reset token, open the link from the email again' });
+		}
+		if (!newPassword || newPassword !== confirmPassword) {
+			return fail(400, { error: 'Passwords do not match' });
+		}
+
+		const confirmRequest: ConfirmPasswordResetRequest = { token, newPassword };
+
+		const response = await fetch('/api/auth/password-reset/confirm', {
+			method: 'POST',
+			headers: { 'Content-Type': 'application/json' },
+			body: JSON.stringify(confirmRequest)
+		});
+
+		if (!response.ok) {
+			const error: ErrorResponse = await response.json();
+			return fail(response.status, { error: error.error });
+		}
+
+		redirect(303, '/login');
+	}
+} satisfies Actions;
@@ -0,0 +1,48 @@
+import { fail } from '@sveltejs/kit';
+import type { Actions, PageServerLoad } from './$types';
+import type { VerifyEmailRequest, VerifyEmailResponse } from '$lib/api/auth';
+import type { ErrorResponse } from '$lib/api';
+
+export const load: PageServerLoad = async ({ url, fetch }) => {
+	const token = url.searchParams.get('token');
+	if (!token) {
+		return { verified: false, error: null };
+	}
+
+	const verifyRequest: VerifyEmailRequest = { token };
+
+	const response = await fetch('/api/auth/verify-email', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify(verifyRequest)
+	});
+
+	if (!response.ok) {
+		const error: ErrorResponse = await response.json();
+		return { verified: false, error: error.error };
+	}
+
+	return { verified: true, error: null };
+};
+
+export const actions = {
+	resend: async ({ cookies, fetch }) => {
+		const sessionId = cookies.get('sessionId');
+		if (!sessionId) {
+			return fail(401, { error: 'Log in to send the verification email again' });
+		}
+
+		const response = await fetch('/api/auth/verify-email/resend', {
+			method: 'POST',
+			headers: { Authorization: sessionId }
+		});
+
+		if (!response.ok) {
+			const error: ErrorResponse = await response.json();
+			return fail(response.status, { error: error.error });
+		}
+
+		const { message }: VerifyEmailResponse = await response.json();
+		return { message };
+	}
+} satisfies Actions;
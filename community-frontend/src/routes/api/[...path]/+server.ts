@@ -3,13 +3,17 @@ import type { RequestHandler } from './$types';
 
 const BACKEND_URL = env.BACKEND_URL || 'http://localhost:8080';
 
-async function proxyRequest(method: string, path: string, request: Request) {
+async function proxyRequest(method: string, path: string, request: Request, clientAddress: string) {
 	const originalUrl = new URL(request.url);
 	const url = `${BACKEND_URL}/${path}${originalUrl.search}`;
 
+	// The backend records client addresses for its audit trail, and only trusts the last one
+	const headers = new Headers(request.headers);
+	headers.set('X-Forwarded-For', clientAddress);
+
 	const options: RequestInit = {
 		method,
-		headers: request.headers
+		headers
 	};
 
 	if (method !== 'GET' && method !== 'HEAD') {
@@ -19,18 +23,18 @@ async function proxyRequest(method: string, path: string, request: Request) {
 	return await fetch(url, options);
 }
 
-export const GET: RequestHandler = async ({ params, request }) => {
-	return await proxyRequest('GET', params.path, request);
+export const GET: RequestHandler = async ({ params, request, getClientAddress }) => {
+	return await proxyRequest('GET', params.path, request, getClientAddress());
 };
 
-export const POST: RequestHandler = async ({ params, request }) => {
-	return await proxyRequest('POST', params.path, request);
+export const POST: RequestHandler = async ({ params, request, getClientAddress }) => {
+	return await proxyRequest('POST', params.path, request, getClientAddress());
 };
 
-export const PUT: RequestHandler = async ({ params, request }) => {
-	return await proxyRequest('PUT', params.path, request);
+export const PUT: RequestHandler = async ({ params, request, getClientAddress }) => {
+	return await proxyRequest('PUT', params.path, request, getClientAddress());
 };
 
-export const DELETE: RequestHandler = async ({ params, request }) => {
-	return await proxyRequest('DELETE', params.path, request);
+export const DELETE: RequestHandler = async ({ params, request, getClientAddress }) => {
+	return await proxyRequest('DELETE', params.path, request, getClientAddress());
 };
@@ -0,0 +1,19 @@
+# Replaces the validation key pair, keeping the old public key in retired/ so tokens it signed
+# stay valid until they expire.
+#
+# The backend only reads its keys when it starts, so the script restarts it rather than have it
+# watch the key files: a restart swaps the signing key and the published keys at once, and loses
+# no sessions or issued tokens, which are kept in the database. Without a running Docker Compose
+# backend, restart it yourself, or it keeps signing with the retired key.
+mkdir -p retired
+# The backend reads the time the key was retired from the file name, in UTC
+mv validation.key.pub "retired/validation-$(date -u +%Y%m%dT%H%M%SZ).key.pub"
+rm validation.key
+ssh-keygen -t rsa -b 4096 -m PEM -f validation.key -N ""
+openssl rsa -in validation.key -pubout -outform PEM -out validation.key.pub
+
+if docker compose -f ../docker-compose.yml ps --services --status running 2>/dev/null | grep -qx community-backend; then
+  docker compose -f ../docker-compose.yml restart community-backend
+else
+  echo "The backend isn't running in Docker Compose, restart it to sign with the new key"
+fi
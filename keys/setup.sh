@@ -1,2 +1,7 @@
 ssh-keygen -t rsa -b 4096 -m PEM -f validation.key
 openssl rsa -in validation.key -pubout -outform PEM -out validation.key.pub
+openssl genpkey -algorithm ed25519 -out snapshot.key
+openssl pkey -in snapshot.key -pubout -out snapshot.key.pub
+openssl rand -hex 32 > backend-zk.key
+openssl rand -hex 32 > trusted-entity-zk.key
+openssl rand -hex 32 > backend-trusted-entity.key
@@ -0,0 +1,125 @@
+//! Checks proof bundles fully offline, so an auditor needs nothing but the bundle and, ideally,
+//! the public key the ZK service publishes.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use anyhow::Context;
+use clap::Parser;
+use common::bundle::{BundledProof, ProofBundle};
+use ed25519_dalek::{VerifyingKey, pkcs8::DecodePublicKey};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Proof bundle to check
+    bundle: PathBuf,
+
+    /// Ed25519 public key (PEM) the ZK service signs snapshots with. Without it the bundled key
+    /// is used, and its ID has to be compared with the published one by hand.
+    #[arg(long, env)]
+    snapshot_public_key: Option<PathBuf>,
+
+    /// Print the verdict as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+
+    let bundle: ProofBundle = serde_json::from_str(
+        &std::fs::read_to_string(&args.bundle)
+            .with_context(|| format!("Failed to read {}", args.bundle.display()))?,
+    )
+    .context("Not a proof bundle")?;
+    let trusted_key = args
+        .snapshot_public_key
+        .map(|path| {
+            VerifyingKey::from_public_key_pem(&std::fs::read_to_string(&path)?)
+                .with_context(|| format!("Invalid public key in {}", path.display()))
+        })
+        .transpose()?;
+
+    let verdict = bundle.verify(trusted_key.as_ref())?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&verdict)?);
+    } else {
+        let header = &bundle.proof.snapshot().header;
+        println!("Proof:     {}", describe(&bundle.proof));
+        println!(
+            "Snapshot:  #{} of community {}, taken at {}",
+            header.sequence, header.community_id, header.taken_at
+        );
+        println!("Root:      {}", bundle.snapshot_root);
+        println!(
+            "Signed by: {}{}",
+            bundle.snapshot_key.key_id,
+            if trusted_key.is_some() {
+                " (trusted)"
+            } else {
+                " (compare with the key ID the ZK service publishes)"
+            }
+        );
+        println!(
+            "Result:    {}",
+            if verdict.valid { "VALID" } else { "INVALID" }
+        );
+        for failure in &verdict.failures {
+            println!("  - {}", serde_json::to_string(failure)?);
+        }
+    }
+
+    Ok(if verdict.valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// What the proof claims, in a line
+fn describe(proof: &BundledProof) -> String {
+    match proof {
+        BundledProof::Record { energy_record, .. } => format!(
+            "energy record {} of user {} starting at {}: {} kWh generated, {} kWh consumed",
+            energy_record.id,
+            energy_record.user_id,
+            energy_record.start,
+            energy_record.generated,
+            energy_record.consumed
+        ),
+        BundledProof::PeriodTotals { proof } => {
+            let statement = &proof.statement;
+            let period = &statement.period;
+            format!(
+                "user {} generated {} kWh and consumed {} kWh over {} records from {} to {}",
+                period.user_id,
+                statement.generated,
+                statement.consumed,
+                statement.record_count,
+                period.start,
+                period.end
+            )
+        }
+        BundledProof::GenerationThreshold { proof } => {
+            let statement = &proof.statement;
+            format!(
+                "user {} generated at least {} kWh from {} to {}",
+                statement.user_id, statement.threshold, statement.start, statement.end
+            )
+        }
+        BundledProof::Allocation { proof } => {
+            let statement = proof.statement();
+            let period = &statement.member.period;
+            format!(
+                "user {} was allocated {} kWh by the {:?} rule from {} to {} ({:?} proof)",
+                period.user_id,
+                statement.allocated,
+                statement.rule,
+                period.start,
+                period.end,
+                proof.scheme()
+            )
+        }
+    }
+}
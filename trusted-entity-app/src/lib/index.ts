@@ -10,3 +10,54 @@ export type EnergyRecord = {
 	sellerPrice: number;
 	start: string;
 };
+
+export type MerkleProof = {
+	index: number;
+	leafCount: number;
+	siblings: string[];
+};
+
+export type SignedSnapshot = {
+	header: {
+		communityId: string;
+		sequence: number;
+		takenAt: string;
+		recordCount: number;
+		root: string;
+	};
+	keyId: string;
+	signature: string;
+};
+
+export type RecordInclusionProof = {
+	snapshot: SignedSnapshot;
+	salt: string;
+	recordPath: MerkleProof;
+	memberPath: MerkleProof;
+};
+
+export type MemberPeriod = {
+	userId: string;
+	communityId: string;
+	start: string;
+	end: string;
+};
+
+export type PeriodTotals = {
+	period: MemberPeriod;
+	recordCount: number;
+	generated: number;
+	consumed: number;
+};
+
+export type PeriodTotalsProof = {
+	statement: PeriodTotals;
+	records: {
+		snapshot: SignedSnapshot;
+		records: unknown[];
+		recordPath: unknown;
+		memberPath: MerkleProof;
+	};
+	generatedBlinding: string;
+	consumedBlinding: string;
+};
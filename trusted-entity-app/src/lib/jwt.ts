@@ -1,30 +1,55 @@
-import { jwtVerify, importSPKI } from 'jose';
-import { readFileSync } from 'fs';
-import { join } from 'path';
+import { jwtVerify, createRemoteJWKSet } from 'jose';
+import type { PeriodTotals } from '$lib';
 
 export interface ValidationClaims {
 	uid: string; // User ID
-	eri: string; // Energy Record ID
+	eris: string[]; // Energy Record IDs, a single one unless the token covers a batch
+	periodTotals?: PeriodTotals; // Totals of a period signed instead of records, for range tokens
+	jti: string; // Token ID, consumed on the backend when the token is redeemed
 	exp: number; // Expiration timestamp
 }
 
-function loadPublicKeyPEM(): string {
-	const publicKeyPath =
-		process.env.VALIDATION_PUBLIC_KEY_PATH ||
-		join(process.cwd(), '..', 'keys', 'validation.key.pub');
-	return readFileSync(publicKeyPath, 'utf-8');
-}
+// Keys are fetched from the backend by the `kid` of each token, and refetched when an unknown
+// key shows up, so rotating the validation key needs no change here
+const jwks = createRemoteJWKSet(
+	new URL(process.env.VALIDATION_JWKS_URL || 'http://localhost:8080/.well-known/jwks.json')
+);
 
-const publicKey = await importSPKI(loadPublicKeyPEM(), 'RS256');
+const issuer = process.env.VALIDATION_TOKEN_ISSUER || 'petall-community-backend';
+const audience = process.env.VALIDATION_TOKEN_AUDIENCE || 'petall-trusted-entity';
 
 export async function validateJWT(token: string): Promise<ValidationClaims> {
-	const { payload } = await jwtVerify(token, publicKey, {
-		algorithms: ['RS256']
+	const { payload } = await jwtVerify(token, jwks, {
+		algorithms: ['RS256'],
+		issuer,
+		audience,
+		requiredClaims: ['jti', 'iat']
 	});
 
+	if (payload.cid) {
+		return {
+			uid: payload.uid as string,
+			eris: [],
+			periodTotals: {
+				period: {
+					userId: payload.uid as string,
+					communityId: payload.cid as string,
+					start: payload.start as string,
+					end: payload.end as string
+				},
+				recordCount: payload.cnt as number,
+				generated: payload.generated as number,
+				consumed: payload.consumed as number
+			},
+			jti: payload.jti as string,
+			exp: payload.exp as number
+		};
+	}
+
 	return {
 		uid: payload.uid as string,
-		eri: payload.eri as string,
+		eris: (payload.eris as string[] | undefined) ?? [payload.eri as string],
+		jti: payload.jti as string,
 		exp: payload.exp as number
 	};
 }
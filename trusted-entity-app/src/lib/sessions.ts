@@ -1,8 +1,8 @@
-import type { EnergyRecord } from '$lib';
+import type { EnergyRecord, PeriodTotalsProof, RecordInclusionProof } from '$lib';
 import { v4 as randomUUID } from 'uuid';
 
 export interface ValidationResult {
-	proof: string;
+	proof: RecordInclusionProof;
 	energyRecord: EnergyRecord;
 }
 
@@ -11,6 +11,7 @@ export interface Session {
 	userId: string;
 	expiresAt: Date;
 	queries: Map<string, ValidationResult>; // Map of energy record ID -> validation result
+	periodTotals: Map<string, PeriodTotalsProof>; // Map of token ID -> proven period totals
 }
 
 const sessions = new Map<string, Session>();
@@ -29,7 +30,8 @@ export function getOrCreateSession(userId: string): Session {
 		id: randomUUID(),
 		userId,
 		expiresAt: new Date(now.getTime() + SESSION_DURATION_MS),
-		queries: new Map()
+		queries: new Map(),
+		periodTotals: new Map()
 	};
 
 	sessions.set(session.id, session);
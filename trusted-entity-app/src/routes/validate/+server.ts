@@ -1,11 +1,14 @@
 import { json } from '@sveltejs/kit';
 import type { RequestHandler } from './$types';
 import { validateJWT } from '$lib/jwt';
+import { backendKey, signedFetch, zkServiceKey } from '$lib/service-auth';
 import { getOrCreateSession, SESSION_DURATION_MS } from '$lib/sessions';
-import type { EnergyRecord } from '$lib';
+import type { EnergyRecord, PeriodTotals, PeriodTotalsProof, RecordInclusionProof } from '$lib';
 
 const ZK_POC_SERVICE_URL = process.env.ZK_POC_SERVICE_URL || 'http://localhost:3002';
 
+const BACKEND_URL = process.env.BACKEND_URL || 'http://localhost:8080';
+
 const ALLOWED_ORIGIN = process.env.ALLOWED_ORIGIN || 'http://localhost:5173';
 
 interface ValidateRequest {
@@ -13,10 +16,97 @@ interface ValidateRequest {
 }
 
 interface ZKValidateResponse {
-	proof: string;
+	proof: RecordInclusionProof;
 	energyRecord: EnergyRecord;
 }
 
+interface ZKValidateBatchResponse {
+	records: ZKValidateResponse[];
+	unavailable: string[];
+}
+
+function sameTotals(proven: PeriodTotals, signed: PeriodTotals): boolean {
+	return (
+		proven.period.userId === signed.period.userId &&
+		proven.period.communityId === signed.period.communityId &&
+		proven.period.start === signed.period.start &&
+		proven.period.end === signed.period.end &&
+		proven.recordCount === signed.recordCount &&
+		proven.generated === signed.generated &&
+		proven.consumed === signed.consumed
+	);
+}
+
+/**
+ * Gets a proof of the totals of the period a range token was signed for, has the zk-poc-service
+ * check it against the snapshot it was made from, and checks it proves the very totals the
+ * backend signed
+ */
+async function provePeriodTotals(signed: PeriodTotals): Promise<PeriodTotalsProof | Response> {
+	let proof: PeriodTotalsProof;
+	try {
+		const { response: proveResponse, body: proveBody } = await signedFetch(
+			zkServiceKey,
+			`${ZK_POC_SERVICE_URL}/prove/period-totals`,
+			{
+				method: 'POST',
+				body: JSON.stringify(signed.period),
+				headers: { 'Content-Type': 'application/json' }
+			}
+		);
+		if (!proveResponse.ok) {
+			throw new Error(`zk-poc-service responded with ${proveResponse.status}`);
+		}
+		proof = JSON.parse(proveBody.toString('utf-8'));
+
+		const verifyResponse = await fetch(`${ZK_POC_SERVICE_URL}/verify/period-totals`, {
+			method: 'POST',
+			body: JSON.stringify({ proof, snapshotRoot: proof.records.snapshot.header.root }),
+			headers: { 'Content-Type': 'application/json' }
+		});
+		if (!verifyResponse.ok) {
+			throw new Error(`zk-poc-service responded with ${verifyResponse.status}`);
+		}
+		const verdict: { valid: boolean } = await verifyResponse.json();
+		if (!verdict.valid) {
+			return json({ error: 'Could not verify the proof of the period totals' }, { status: 502 });
+		}
+	} catch (e) {
+		console.error(e);
+		return json({ error: 'Failed to query zk-poc-service' }, { status: 500 });
+	}
+
+	if (!sameTotals(proof.statement, signed)) {
+		return json(
+			{ error: "The totals signed in the token don't match the committed records" },
+			{ status: 409 }
+		);
+	}
+
+	return proof;
+}
+
+/**
+ * Consumes the token with the backend, so it can't be redeemed again. Returns the response to
+ * answer with if it can't be.
+ */
+async function redeemToken(tokenId: string): Promise<Response | null> {
+	try {
+		const { response } = await signedFetch(
+			backendKey,
+			`${BACKEND_URL}/trusted-entity/validation-token/${tokenId}/consume`,
+			{ method: 'POST' }
+		);
+		if (!response.ok) {
+			return json({ error: 'Validation token was already used or has expired' }, { status: 409 });
+		}
+	} catch (e) {
+		console.error(e);
+		return json({ error: 'Failed to redeem validation token' }, { status: 500 });
+	}
+	return null;
+}
+
 function setCorsHeaders(response: Response, origin: string | null): void {
 	if (!origin || origin !== ALLOWED_ORIGIN) {
 		return;
@@ -45,30 +135,70 @@ export const POST: RequestHandler = async ({ request, cookies }) => {
 	}
 
 	const claims = await validateJWT(body.token);
-	const energyRecordId = claims.eri;
+	const energyRecordIds = claims.eris;
 	const userId = claims.uid;
 
-	const session = getOrCreateSession(userId);
+	if (claims.periodTotals) {
+		const proof = await provePeriodTotals(claims.periodTotals);
+		if (proof instanceof Response) {
+			setCorsHeaders(proof, origin);
+			return proof;
+		}
 
-	const zkResponse = await fetch(`${ZK_POC_SERVICE_URL}/validate/${energyRecordId}`, {
-		method: 'GET',
-		headers: {
-			'Content-Type': 'application/json'
+		// Tokens are single-use: consume it once its proof checked out, so failures don't burn it
+		const redeemError = await redeemToken(claims.jti);
+		if (redeemError) {
+			setCorsHeaders(redeemError, origin);
+			return redeemError;
 		}
-	});
 
-	if (!zkResponse.ok) {
+		const session = getOrCreateSession(userId);
+		session.periodTotals.set(claims.jti, proof);
+		cookies.set('trustedEntitySessionId', session.id, {
+			path: '/',
+			maxAge: SESSION_DURATION_MS / 1000
+		});
+
+		const successResponse = json({ periodTotals: claims.jti }, { status: 200 });
+		setCorsHeaders(successResponse, origin);
+		return successResponse;
+	}
+
+	let zkResult: ZKValidateBatchResponse;
+	try {
+		const { response: zkResponse, body: zkBody } = await signedFetch(
+			zkServiceKey,
+			`${ZK_POC_SERVICE_URL}/validate-batch`,
+			{
+				method: 'POST',
+				body: JSON.stringify({ energyRecordIds }),
+				headers: { 'Content-Type': 'application/json' }
+			}
+		);
+		if (!zkResponse.ok) {
+			throw new Error(`zk-poc-service responded with ${zkResponse.status}`);
+		}
+		zkResult = JSON.parse(zkBody.toString('utf-8'));
+	} catch (e) {
+		console.error(e);
 		const errorResponse = json({ error: 'Failed to query zk-poc-service' }, { status: 500 });
 		setCorsHeaders(errorResponse, origin);
 		return errorResponse;
 	}
 
-	const zkResult: ZKValidateResponse = await zkResponse.json();
+	const redeemError = await redeemToken(claims.jti);
+	if (redeemError) {
+		setCorsHeaders(redeemError, origin);
+		return redeemError;
+	}
 
-	session.queries.set(energyRecordId, {
-		proof: zkResult.proof,
-		energyRecord: zkResult.energyRecord
-	});
+	const session = getOrCreateSession(userId);
+	for (const result of zkResult.records) {
+		session.queries.set(result.energyRecord.id, {
+			proof: result.proof,
+			energyRecord: result.energyRecord
+		});
+	}
 
 	// Not sure if this works with different domains but whatever...
 	cookies.set('trustedEntitySessionId', session.id, {
@@ -76,7 +206,14 @@ export const POST: RequestHandler = async ({ request, cookies }) => {
 		maxAge: SESSION_DURATION_MS / 1000 // Convert milliseconds to seconds
 	});
 
-	const successResponse = json({ query: energyRecordId }, { status: 200 });
+	const successResponse = json(
+		{
+			query: energyRecordIds[0],
+			queries: zkResult.records.map((result) => result.energyRecord.id),
+			unavailable: zkResult.unavailable
+		},
+		{ status: 200 }
+	);
 	setCorsHeaders(successResponse, origin);
 	return successResponse;
 };
@@ -0,0 +1,51 @@
+import { error, json } from '@sveltejs/kit';
+import type { RequestHandler } from './$types';
+import { getSession } from '$lib/sessions';
+
+const ZK_POC_SERVICE_URL = process.env.ZK_POC_SERVICE_URL || 'http://localhost:3002';
+
+// Mirrors `common::bundle` on the Rust side, checked offline with `petall-verify`
+const BUNDLE_VERSION = 1;
+
+interface PublicKeyResponse {
+	keyId: string;
+	publicKey: string;
+}
+
+export const GET: RequestHandler = async ({ cookies, url }) => {
+	const sessionId = cookies.get('trustedEntitySessionId');
+	const query = url.searchParams.get('query');
+	if (!sessionId) {
+		error(401, 'Missing session ID in cookie');
+	}
+	if (!query) {
+		error(400, 'Missing query parameter');
+	}
+
+	const session = getSession(sessionId);
+	if (!session) {
+		error(401, 'Invalid or expired session');
+	}
+	const result = session.queries.get(query);
+	if (!result) {
+		error(404, 'Query not found in session');
+	}
+
+	const keyResponse = await fetch(`${ZK_POC_SERVICE_URL}/public-key`);
+	if (!keyResponse.ok) {
+		error(502, 'Failed to fetch the snapshot signing key');
+	}
+	const snapshotKey: PublicKeyResponse = await keyResponse.json();
+
+	const bundle = {
+		version: BUNDLE_VERSION,
+		exportedAt: new Date().toISOString(),
+		snapshotRoot: result.proof.snapshot.header.root,
+		snapshotKey,
+		proof: { kind: 'record', energyRecord: result.energyRecord, proof: result.proof }
+	};
+
+	return json(bundle, {
+		headers: { 'Content-Disposition': `attachment; filename="petall-proof-${query}.json"` }
+	});
+};
@@ -0,0 +1,104 @@
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+
+pub type AppResult<T> = Result<T, AppError>;
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("invalid validation token: {0}")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+    #[error("validation token signed with unknown key: {0}")]
+    UnknownSigningKey(String),
+    #[error("validation token already used or expired: {0}")]
+    TokenRedeemed(Uuid),
+    #[error("missing session")]
+    MissingSession,
+    #[error("invalid session")]
+    InvalidSession,
+    #[error("energy record not validated in this session: {0}")]
+    ResultNotFound(Uuid),
+    #[error("period totals not validated in this session: {0}")]
+    PeriodTotalsNotFound(Uuid),
+    #[error("invalid proof for energy record: {0}")]
+    InvalidProof(Uuid),
+    #[error("invalid proof of the period totals of token: {0}")]
+    InvalidPeriodProof(Uuid),
+    #[error("proven totals don't match the signed ones of token: {0}")]
+    TotalsMismatch(Uuid),
+    #[error(transparent)]
+    ServiceError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct ErrorBody {
+            error: String,
+        }
+
+        let (status, error) = match &self {
+            AppError::InvalidToken(_) | AppError::UnknownSigningKey(_) => (
+                StatusCode::UNAUTHORIZED,
+                "Invalid validation token".to_string(),
+            ),
+            AppError::TokenRedeemed(_) => (
+                StatusCode::CONFLICT,
+                "Validation token was already used or has expired".to_string(),
+            ),
+            AppError::MissingSession => (StatusCode::UNAUTHORIZED, "Missing session".to_string()),
+            AppError::InvalidSession => (
+                StatusCode::UNAUTHORIZED,
+                "Invalid or expired session".to_string(),
+            ),
+            AppError::ResultNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                format!("Energy record {} was not validated in this session", id),
+            ),
+            AppError::PeriodTotalsNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Period totals of token {} were not validated in this session",
+                    id
+                ),
+            ),
+            AppError::InvalidProof(id) => {
+                error!("zk-poc-service returned an invalid proof for energy record {id}");
+                (
+                    StatusCode::BAD_GATEWAY,
+                    format!("Could not verify the proof of energy record {}", id),
+                )
+            }
+            AppError::InvalidPeriodProof(id) => {
+                error!("zk-poc-service returned an invalid proof for the period of token {id}");
+                (
+                    StatusCode::BAD_GATEWAY,
+                    format!(
+                        "Could not verify the proof of the period totals of token {}",
+                        id
+                    ),
+                )
+            }
+            AppError::TotalsMismatch(id) => (
+                StatusCode::CONFLICT,
+                format!(
+                    "The totals signed in token {} don't match the committed records",
+                    id
+                ),
+            ),
+            AppError::ServiceError(err) => {
+                error!("Service error occurred: {err:#}");
+                (
+                    StatusCode::BAD_GATEWAY,
+                    "Failed to reach the other services".to_string(),
+                )
+            }
+        };
+
+        (status, Json(ErrorBody { error })).into_response()
+    }
+}
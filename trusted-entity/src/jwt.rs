@@ -0,0 +1,157 @@
+//! Verification of the validation tokens the community backend signs.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use common::{
+    aggregate::PeriodTotals,
+    validation::{BatchValidationClaims, RangeValidationClaims, ValidationClaims},
+};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation,
+    errors::ErrorKind,
+    jwk::{Jwk, JwkSet},
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+
+/// How long to wait before fetching the key set again for a key it didn't have, so tokens with
+/// made up key IDs can't make us hammer the backend
+const JWKS_REFRESH_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// A verified token, along with what it lets the trusted entity validate
+#[derive(Debug)]
+pub struct VerifiedToken {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub statement: TokenStatement,
+}
+
+#[derive(Debug)]
+pub enum TokenStatement {
+    /// Records to prove one by one
+    Records(Vec<Uuid>),
+    /// Totals of the user's records over a period, as signed by the backend
+    PeriodTotals(PeriodTotals),
+}
+
+/// Tokens cover either one record, a batch of them or the totals of a period
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenClaims {
+    Range(RangeValidationClaims),
+    Batch(BatchValidationClaims),
+    Single(ValidationClaims),
+}
+
+struct CachedKeys {
+    keys: JwkSet,
+    fetched_at: Option<Instant>,
+}
+
+pub struct TokenVerifier {
+    jwks_url: Url,
+    validation: Validation,
+    http: reqwest::Client,
+    cache: RwLock<CachedKeys>,
+}
+
+impl TokenVerifier {
+    pub fn new(jwks_url: Url, issuer: &str, audience: &str, http: reqwest::Client) -> Self {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+        Self {
+            jwks_url,
+            validation,
+            http,
+            cache: RwLock::new(CachedKeys {
+                keys: JwkSet { keys: Vec::new() },
+                fetched_at: None,
+            }),
+        }
+    }
+
+    /// Checks the token was signed by the backend for us. Keys are fetched from the backend by
+    /// the `kid` of each token, so rotating the validation key needs no change here.
+    pub async fn verify(&self, token: &str) -> AppResult<VerifiedToken> {
+        let kid = jsonwebtoken::decode_header(token)?
+            .kid
+            .ok_or(AppError::InvalidToken(ErrorKind::InvalidToken.into()))?;
+        let key = match self.find_key(&kid).await {
+            Some(key) => key,
+            None => {
+                // The key may have been published since the key set was last fetched
+                self.refresh_keys().await?;
+                self.find_key(&kid)
+                    .await
+                    .ok_or(AppError::UnknownSigningKey(kid))?
+            }
+        };
+
+        let claims = jsonwebtoken::decode::<TokenClaims>(
+            token,
+            &DecodingKey::from_jwk(&key)?,
+            &self.validation,
+        )?
+        .claims;
+
+        let token = match claims {
+            TokenClaims::Range(claims) => VerifiedToken {
+                token_id: claims.registered.jti,
+                user_id: claims.uid,
+                statement: TokenStatement::PeriodTotals(claims.statement()),
+            },
+            TokenClaims::Batch(claims) => VerifiedToken {
+                token_id: claims.registered.jti,
+                user_id: claims.uid,
+                statement: TokenStatement::Records(claims.eris),
+            },
+            TokenClaims::Single(claims) => VerifiedToken {
+                token_id: claims.registered.jti,
+                user_id: claims.uid,
+                statement: TokenStatement::Records(vec![claims.eri]),
+            },
+        };
+        if matches!(&token.statement, TokenStatement::Records(ids) if ids.is_empty()) {
+            return Err(AppError::InvalidToken(ErrorKind::InvalidToken.into()));
+        }
+
+        Ok(token)
+    }
+
+    async fn find_key(&self, kid: &str) -> Option<Jwk> {
+        self.cache.read().await.keys.find(kid).cloned()
+    }
+
+    /// Fetches the key set again, unless it was fetched too recently
+    async fn refresh_keys(&self) -> anyhow::Result<()> {
+        let mut cache = self.cache.write().await;
+        if cache
+            .fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() < JWKS_REFRESH_COOLDOWN)
+        {
+            return Ok(());
+        }
+
+        cache.keys = self
+            .http
+            .get(self.jwks_url.as_str())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to fetch validation keys")?
+            .json()
+            .await
+            .context("Failed to parse validation keys")?;
+        cache.fetched_at = Some(Instant::now());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,886 @@
+//! Trusted entity: redeems the validation tokens users bring from the community backend, gets
+//! proofs of their records from the zk-poc-service, checks them and keeps the results for the
+//! user to look at.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Json, Router, debug_handler,
+    extract::{Query, State},
+    http::{
+        HeaderValue, Method, StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::IntoResponse,
+    routing::{get, post},
+};
+use axum_extra::extract::{CookieJar, cookie::Cookie};
+use common::{
+    aggregate::{PeriodTotals, PeriodTotalsProof},
+    bundle::{BundledProof, ProofBundle},
+    verify::{verify_period_totals, verify_record_inclusion},
+};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    jwt::TokenStatement,
+    sessions::{SESSION_DURATION, SessionStore, ValidationResult},
+};
+
+mod error;
+mod jwt;
+mod services;
+mod sessions;
+
+pub use jwt::TokenVerifier;
+pub use services::ServiceClient;
+
+const SESSION_COOKIE: &str = "trustedEntitySessionId";
+
+/// Handles shared by the routes
+#[derive(Clone)]
+pub struct AppState {
+    tokens: Arc<TokenVerifier>,
+    backend: Arc<ServiceClient>,
+    zk: Arc<ServiceClient>,
+    snapshot_key: VerifyingKey,
+    sessions: Arc<SessionStore>,
+}
+
+impl AppState {
+    /// Trusts proofs against snapshots signed with `snapshot_key` only
+    pub fn new(
+        tokens: TokenVerifier,
+        backend: ServiceClient,
+        zk: ServiceClient,
+        snapshot_key: VerifyingKey,
+    ) -> Self {
+        Self {
+            tokens: Arc::new(tokens),
+            backend: Arc::new(backend),
+            zk: Arc::new(zk),
+            snapshot_key,
+            sessions: Arc::new(SessionStore::default()),
+        }
+    }
+
+    /// Consumes the token with the backend, so it can't be redeemed again
+    async fn redeem_token(&self, token_id: Uuid) -> AppResult<()> {
+        let path = format!("/trusted-entity/validation-token/{token_id}/consume");
+        let (status, _) = self
+            .backend
+            .request(Method::POST, &path, Vec::new())
+            .await?;
+
+        match status {
+            StatusCode::NO_CONTENT => Ok(()),
+            StatusCode::NOT_FOUND | StatusCode::CONFLICT | StatusCode::GONE => {
+                Err(AppError::TokenRedeemed(token_id))
+            }
+            status => Err(anyhow::anyhow!("Backend responded with {status} to {path}").into()),
+        }
+    }
+
+    /// Gets proofs of the records of a token, and checks each of them before trusting it.
+    /// Returns the proven records, and those that are not in a committed snapshot yet.
+    async fn prove_records(
+        &self,
+        user_id: Uuid,
+        energy_record_ids: &[Uuid],
+    ) -> AppResult<(Vec<ValidationResult>, Vec<Uuid>)> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ValidateBatchRequest<'a> {
+            energy_record_ids: &'a [Uuid],
+        }
+
+        #[derive(Deserialize)]
+        struct ValidateBatchResponse {
+            records: Vec<ValidationResult>,
+            unavailable: Vec<Uuid>,
+        }
+
+        let body = serde_json::to_vec(&ValidateBatchRequest { energy_record_ids })
+            .context("Failed to serialize batch")?;
+        let (status, body) = self
+            .zk
+            .request(Method::POST, "/validate-batch", body)
+            .await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("zk-poc-service responded with {status}").into());
+        }
+        let response: ValidateBatchResponse =
+            serde_json::from_slice(&body).context("Failed to parse zk-poc-service response")?;
+
+        for result in &response.records {
+            let record = &result.energy_record;
+            let verdict = verify_record_inclusion(
+                &result.proof,
+                record,
+                &result.proof.snapshot.header.root,
+                &self.snapshot_key,
+            );
+            if !verdict.valid
+                || record.user_id != user_id
+                || !energy_record_ids.contains(&record.id)
+            {
+                return Err(AppError::InvalidProof(record.id));
+            }
+        }
+
+        Ok((response.records, response.unavailable))
+    }
+
+    /// Gets a proof of the totals of the period a token was signed for, checks it, and checks
+    /// that it proves the very totals the backend signed
+    async fn prove_period_totals(
+        &self,
+        token_id: Uuid,
+        signed: &PeriodTotals,
+    ) -> AppResult<PeriodTotalsProof> {
+        let body = serde_json::to_vec(&signed.period).context("Failed to serialize period")?;
+        let (status, body) = self
+            .zk
+            .request(Method::POST, "/prove/period-totals", body)
+            .await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("zk-poc-service responded with {status}").into());
+        }
+        let proof: PeriodTotalsProof =
+            serde_json::from_slice(&body).context("Failed to parse zk-poc-service response")?;
+
+        let verdict = verify_period_totals(
+            &proof,
+            &proof.records.snapshot.header.root,
+            &self.snapshot_key,
+        );
+        if !verdict.valid || proof.statement.period != signed.period {
+            return Err(AppError::InvalidPeriodProof(token_id));
+        }
+        if proof.statement != *signed {
+            return Err(AppError::TotalsMismatch(token_id));
+        }
+
+        Ok(proof)
+    }
+}
+
+/// Routes users redeem their validation tokens on and look at the results of
+pub fn router(state: AppState, allowed_origin: HeaderValue) -> Router {
+    let cors = CorsLayer::new()
+        .allow_origin(allowed_origin)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([CONTENT_TYPE])
+        .allow_credentials(true);
+
+    Router::new()
+        .route("/validate", post(validate).get(get_validation_result))
+        .route("/validate/bundle", get(export_proof_bundle))
+        .route("/validate/period-totals", get(get_period_totals))
+        .route(
+            "/validate/period-totals/bundle",
+            get(export_period_totals_bundle),
+        )
+        .layer(cors)
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct ValidateRequest {
+    token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged, rename_all_fields = "camelCase")]
+enum ValidateResponse {
+    Records {
+        /// First record of the token, to show the user
+        query: Uuid,
+        /// Records that were proven and can be looked up in the session
+        queries: Vec<Uuid>,
+        /// Records that are not part of any committed snapshot yet
+        unavailable: Vec<Uuid>,
+    },
+    PeriodTotals {
+        /// ID of the token, which the proven totals can be looked up by in the session
+        period_totals: Uuid,
+    },
+}
+
+/// Proves the statement of a validation token, redeems it, and keeps the proofs of its records or
+/// of its period totals in the user's session
+#[debug_handler]
+async fn validate(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<ValidateRequest>,
+) -> AppResult<(CookieJar, Json<ValidateResponse>)> {
+    let token = state.tokens.verify(&request.token).await?;
+
+    let (session_id, response) = match token.statement {
+        TokenStatement::Records(energy_record_ids) => {
+            let (results, unavailable) = state
+                .prove_records(token.user_id, &energy_record_ids)
+                .await?;
+            // Tokens are single-use: redeem it only once its proofs checked out, so a failing
+            // service doesn't burn it
+            state.redeem_token(token.token_id).await?;
+            let queries = results
+                .iter()
+                .map(|result| result.energy_record.id)
+                .collect();
+            let session_id = state.sessions.store_results(token.user_id, results).await;
+            let response = ValidateResponse::Records {
+                query: energy_record_ids[0],
+                queries,
+                unavailable,
+            };
+            (session_id, response)
+        }
+        TokenStatement::PeriodTotals(signed) => {
+            let proof = state.prove_period_totals(token.token_id, &signed).await?;
+            state.redeem_token(token.token_id).await?;
+            let session_id = state
+                .sessions
+                .store_period_totals(token.user_id, token.token_id, proof)
+                .await;
+            let response = ValidateResponse::PeriodTotals {
+                period_totals: token.token_id,
+            };
+            (session_id, response)
+        }
+    };
+
+    let cookie = Cookie::build((SESSION_COOKIE, session_id.to_string()))
+        .path("/")
+        .http_only(true)
+        .max_age(time::Duration::seconds(SESSION_DURATION.num_seconds()));
+
+    Ok((jar.add(cookie), Json(response)))
+}
+
+#[derive(Deserialize)]
+struct ValidationResultQuery {
+    query: Uuid,
+}
+
+/// Returns a record validated in the caller's session, along with its proof
+#[debug_handler]
+async fn get_validation_result(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(ValidationResultQuery { query }): Query<ValidationResultQuery>,
+) -> AppResult<Json<ValidationResult>> {
+    let session_id = session_id(&jar)?;
+    Ok(Json(state.sessions.get_result(session_id, query).await?))
+}
+
+/// Exports a record validated in the caller's session as a proof bundle, which anyone can check
+/// offline with `petall-verify`
+#[debug_handler]
+async fn export_proof_bundle(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(ValidationResultQuery { query }): Query<ValidationResultQuery>,
+) -> AppResult<impl IntoResponse> {
+    let session_id = session_id(&jar)?;
+    let ValidationResult {
+        proof,
+        energy_record,
+    } = state.sessions.get_result(session_id, query).await?;
+
+    let bundle = ProofBundle::new(
+        BundledProof::Record {
+            energy_record,
+            proof,
+        },
+        state.snapshot_key,
+    );
+    let disposition = format!("attachment; filename=\"petall-proof-{query}.json\"");
+
+    Ok(([(CONTENT_DISPOSITION, disposition)], Json(bundle)))
+}
+
+/// Returns period totals proven in the caller's session, looked up by the ID of their token
+#[debug_handler]
+async fn get_period_totals(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(ValidationResultQuery { query }): Query<ValidationResultQuery>,
+) -> AppResult<Json<PeriodTotalsProof>> {
+    let session_id = session_id(&jar)?;
+    Ok(Json(
+        state.sessions.get_period_totals(session_id, query).await?,
+    ))
+}
+
+/// Exports period totals proven in the caller's session as a proof bundle
+#[debug_handler]
+async fn export_period_totals_bundle(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(ValidationResultQuery { query }): Query<ValidationResultQuery>,
+) -> AppResult<impl IntoResponse> {
+    let session_id = session_id(&jar)?;
+    let proof = state.sessions.get_period_totals(session_id, query).await?;
+
+    let bundle = ProofBundle::new(BundledProof::PeriodTotals { proof }, state.snapshot_key);
+    let disposition = format!("attachment; filename=\"petall-period-totals-{query}.json\"");
+
+    Ok(([(CONTENT_DISPOSITION, disposition)], Json(bundle)))
+}
+
+fn session_id(jar: &CookieJar) -> AppResult<Uuid> {
+    jar.get(SESSION_COOKIE)
+        .ok_or(AppError::MissingSession)?
+        .value()
+        .parse()
+        .map_err(|_| AppError::InvalidSession)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::Path, middleware, routing::get};
+    use axum_test::TestServer;
+    use bigdecimal::BigDecimal;
+    use chrono::{Duration, NaiveDateTime, Utc};
+    use common::{
+        EnergyRecord,
+        aggregate::MemberPeriod,
+        allocation::{DistributionRule, ProofScheme},
+        merkle::{Hash, MerkleTree},
+        service_auth::{ServiceKey, authenticate_service},
+        snapshot::{
+            QuantityBlindings, RecordCommitment, RecordInclusionProof, RecordRangeProof,
+            SignedSnapshot, SnapshotHeader, member_leaf, record_leaf,
+        },
+        validation::{
+            BatchValidationClaims, RangeValidationClaims, RegisteredClaims, ValidationClaims,
+        },
+    };
+    use curve25519_dalek::Scalar;
+    use ed25519_dalek::SigningKey;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, jwk::Jwk, jwk::JwkSet};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use url::Url;
+
+    use super::*;
+
+    const ISSUER: &str = "petall-test";
+    const AUDIENCE: &str = "petall-test-verifier";
+    const KEY_ID: &str = "test-key";
+    const TEST_VALIDATION_KEY: &[u8] =
+        include_bytes!("../../community-backend/test-keys/validation.key");
+
+    fn backend_key() -> ServiceKey {
+        ServiceKey::from_hex(&"42".repeat(32)).unwrap()
+    }
+
+    fn zk_key() -> ServiceKey {
+        ServiceKey::from_hex(&"24".repeat(32)).unwrap()
+    }
+
+    /// An hour of records of one member, with quantities at the precision they are stored with
+    fn records() -> Vec<EnergyRecord> {
+        let start = NaiveDateTime::default();
+        let mut records = EnergyRecord::random_vec(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            start,
+            start + Duration::hours(1),
+        );
+        for record in &mut records {
+            record.generated = record.generated.with_scale(4);
+            record.consumed = record.consumed.with_scale(4);
+            record.consumer_price = record.consumer_price.with_scale(4);
+            record.seller_price = record.seller_price.with_scale(4);
+        }
+        records
+    }
+
+    /// Commits `records` of a single member to a signed snapshot, with one salt for all of them
+    fn commit(
+        records: &[EnergyRecord],
+        signing_key: &SigningKey,
+    ) -> (Hash, MerkleTree, MerkleTree, SignedSnapshot) {
+        let salt = Hash::digest(b"salt");
+        let record_tree = MerkleTree::new(
+            records
+                .iter()
+                .map(|record| record_leaf(&salt, record).unwrap())
+                .collect(),
+        );
+        let member_tree = MerkleTree::new(vec![member_leaf(
+            records[0].user_id,
+            &record_tree.root(),
+            record_tree.len(),
+        )]);
+        let snapshot = SignedSnapshot::sign(
+            SnapshotHeader {
+                community_id: records[0].community_id,
+                sequence: 1,
+                taken_at: Utc::now(),
+                record_count: records.len() as u64,
+                distribution_rule: DistributionRule::default(),
+                proof_scheme: ProofScheme::default(),
+                root: member_tree.root(),
+            },
+            signing_key,
+        );
+
+        (salt, record_tree, member_tree, snapshot)
+    }
+
+    /// Proofs of `records` in a single member snapshot, as the zk-poc-service would hand out
+    fn prove(records: &[EnergyRecord], signing_key: &SigningKey) -> Vec<ValidationResult> {
+        let (salt, record_tree, member_tree, snapshot) = commit(records, signing_key);
+
+        records
+            .iter()
+            .enumerate()
+            .map(|(index, record)| ValidationResult {
+                proof: RecordInclusionProof {
+                    snapshot: snapshot.clone(),
+                    salt,
+                    record_path: record_tree.proof(index).unwrap(),
+                    member_path: member_tree.proof(0).unwrap(),
+                },
+                energy_record: record.clone(),
+            })
+            .collect()
+    }
+
+    /// The period all of `records` start within
+    fn period(records: &[EnergyRecord]) -> MemberPeriod {
+        MemberPeriod {
+            user_id: records[0].user_id,
+            community_id: records[0].community_id,
+            start: records[0].start,
+            end: records[records.len() - 1].start + Duration::minutes(15),
+        }
+    }
+
+    /// Proof of the totals of all of `records`, as the zk-poc-service would hand out
+    fn prove_period(records: &[EnergyRecord], signing_key: &SigningKey) -> PeriodTotalsProof {
+        let (salt, record_tree, member_tree, snapshot) = commit(records, signing_key);
+        let blindings = QuantityBlindings::derive(&salt);
+        let count = records.len() as u64;
+
+        PeriodTotalsProof {
+            statement: PeriodTotals {
+                period: period(records),
+                record_count: count,
+                generated: records.iter().map(|record| &record.generated).sum(),
+                consumed: records.iter().map(|record| &record.consumed).sum(),
+            },
+            records: RecordRangeProof {
+                snapshot,
+                records: records
+                    .iter()
+                    .map(|record| RecordCommitment::open(&salt, record).unwrap())
+                    .collect(),
+                record_path: record_tree.range_proof(0, records.len()).unwrap(),
+                member_path: member_tree.proof(0).unwrap(),
+            },
+            generated_blinding: blindings.generated * Scalar::from(count),
+            consumed_blinding: blindings.consumed * Scalar::from(count),
+        }
+    }
+
+    async fn spawn(app: Router) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url.parse().unwrap()
+    }
+
+    /// The backend consumes each token once and publishes the key tokens are signed with
+    async fn backend() -> Url {
+        let mut jwk = Jwk::from_encoding_key(
+            &EncodingKey::from_rsa_pem(TEST_VALIDATION_KEY).unwrap(),
+            Algorithm::RS256,
+        )
+        .unwrap();
+        jwk.common.key_id = Some(KEY_ID.to_string());
+        let jwks = JwkSet { keys: vec![jwk] };
+        let consumed = Arc::new(Mutex::new(HashSet::new()));
+
+        spawn(
+            Router::new()
+                .route(
+                    "/trusted-entity/validation-token/{id}/consume",
+                    post(move |Path(id): Path<Uuid>| async move {
+                        if consumed.lock().unwrap().insert(id) {
+                            StatusCode::NO_CONTENT
+                        } else {
+                            StatusCode::CONFLICT
+                        }
+                    }),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    Arc::new(backend_key()),
+                    authenticate_service,
+                ))
+                .route(
+                    "/.well-known/jwks.json",
+                    get(move || async move { Json(jwks) }),
+                ),
+        )
+        .await
+    }
+
+    /// The zk-poc-service proves the records it committed, and the rest are unavailable. It proves
+    /// the totals of a single period.
+    async fn zk_poc_service(
+        committed: Vec<ValidationResult>,
+        period_totals: Option<PeriodTotalsProof>,
+    ) -> Url {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Batch {
+            energy_record_ids: Vec<Uuid>,
+        }
+
+        spawn(
+            Router::new()
+                .route(
+                    "/validate-batch",
+                    post(move |Json(batch): Json<Batch>| async move {
+                        let (records, unavailable): (Vec<_>, Vec<_>) = batch
+                            .energy_record_ids
+                            .into_iter()
+                            .map(|id| {
+                                committed
+                                    .iter()
+                                    .find(|result| result.energy_record.id == id)
+                                    .ok_or(id)
+                            })
+                            .partition(Result::is_ok);
+                        Json(json!({
+                            "records": records.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+                            "unavailable": unavailable.into_iter().map(Result::unwrap_err).collect::<Vec<_>>(),
+                        }))
+                    }),
+                )
+                .route(
+                    "/prove/period-totals",
+                    post(move |Json(period): Json<MemberPeriod>| async move {
+                        match period_totals {
+                            Some(proof) if proof.statement.period == period => {
+                                Json(proof).into_response()
+                            }
+                            _ => StatusCode::NOT_FOUND.into_response(),
+                        }
+                    }),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    Arc::new(zk_key()),
+                    authenticate_service,
+                )),
+        )
+        .await
+    }
+
+    async fn trusted_entity(
+        backend_url: Url,
+        committed: Vec<ValidationResult>,
+        period_totals: Option<PeriodTotalsProof>,
+        snapshot_key: VerifyingKey,
+    ) -> TestServer {
+        let http = reqwest::Client::new();
+        let state = AppState::new(
+            TokenVerifier::new(
+                backend_url.join("/.well-known/jwks.json").unwrap(),
+                ISSUER,
+                AUDIENCE,
+                http.clone(),
+            ),
+            ServiceClient::new(backend_url, backend_key(), http.clone()),
+            ServiceClient::new(
+                zk_poc_service(committed, period_totals).await,
+                zk_key(),
+                http,
+            ),
+            snapshot_key,
+        );
+
+        TestServer::new(router(
+            state,
+            HeaderValue::from_static("http://localhost:5173"),
+        ))
+        .unwrap()
+    }
+
+    fn registered(audience: &str) -> RegisteredClaims {
+        let now = Utc::now();
+        RegisteredClaims {
+            jti: Uuid::new_v4(),
+            iss: ISSUER.to_string(),
+            aud: audience.to_string(),
+            iat: now.timestamp(),
+            exp: (now + Duration::minutes(5)).timestamp(),
+        }
+    }
+
+    fn sign(claims: &impl Serialize, key_id: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(key_id.to_string());
+        jsonwebtoken::encode(
+            &header,
+            claims,
+            &EncodingKey::from_rsa_pem(TEST_VALIDATION_KEY).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn validated_records_are_kept_in_the_session() {
+        let snapshot_key = SigningKey::from_bytes(&[7; 32]);
+        let records = records();
+        // The last record is not part of a committed snapshot yet
+        let committed = prove(&records[..3], &snapshot_key);
+        let server = trusted_entity(
+            backend().await,
+            committed,
+            None,
+            snapshot_key.verifying_key(),
+        )
+        .await;
+        let user_id = records[0].user_id;
+
+        let batch = sign(
+            &BatchValidationClaims {
+                uid: user_id,
+                eris: vec![records[0].id, records[1].id, records[3].id],
+                registered: registered(AUDIENCE),
+            },
+            KEY_ID,
+        );
+        let response = server
+            .post("/validate")
+            .json(&json!({ "token": batch }))
+            .await;
+        response.assert_status_ok();
+        let cookie = response.cookie(SESSION_COOKIE);
+        let ValidateResponse::Records {
+            query,
+            queries,
+            unavailable,
+        } = response.json()
+        else {
+            panic!("batch tokens validate records");
+        };
+        assert_eq!(query, records[0].id);
+        assert_eq!(queries, [records[0].id, records[1].id]);
+        assert_eq!(unavailable, [records[3].id]);
+
+        let result = server
+            .get(&format!("/validate?query={}", records[1].id))
+            .add_cookie(cookie.clone())
+            .await
+            .json::<ValidationResult>();
+        assert_eq!(result.energy_record, records[1]);
+
+        // The exported bundle checks out offline against the ZK service's key alone
+        let bundle = server
+            .get(&format!("/validate/bundle?query={}", records[1].id))
+            .add_cookie(cookie.clone())
+            .await
+            .json::<ProofBundle>();
+        assert!(
+            bundle
+                .verify(Some(&snapshot_key.verifying_key()))
+                .unwrap()
+                .valid
+        );
+
+        // Only records of the redeemed tokens, and only to the session holder
+        server
+            .get(&format!("/validate?query={}", records[2].id))
+            .add_cookie(cookie.clone())
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        server
+            .get(&format!("/validate?query={}", records[1].id))
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        // Tokens are single-use
+        server
+            .post("/validate")
+            .json(&json!({ "token": batch }))
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        // Later tokens of the same user add to their session
+        let single = sign(
+            &ValidationClaims {
+                uid: user_id,
+                eri: records[2].id,
+                registered: registered(AUDIENCE),
+            },
+            KEY_ID,
+        );
+        let response = server
+            .post("/validate")
+            .json(&json!({ "token": single }))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.cookie(SESSION_COOKIE).value(), cookie.value());
+        for record in &records[..3] {
+            server
+                .get(&format!("/validate?query={}", record.id))
+                .add_cookie(cookie.clone())
+                .await
+                .assert_status_ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn untrusted_tokens_and_proofs_are_rejected() {
+        let snapshot_key = SigningKey::from_bytes(&[7; 32]);
+        let records = records();
+        let backend_url = backend().await;
+        let server = trusted_entity(
+            backend_url.clone(),
+            prove(&records, &snapshot_key),
+            None,
+            snapshot_key.verifying_key(),
+        )
+        .await;
+        let claims = |audience| ValidationClaims {
+            uid: records[0].user_id,
+            eri: records[0].id,
+            registered: registered(audience),
+        };
+
+        for token in [
+            sign(&claims("someone-else"), KEY_ID),
+            sign(&claims(AUDIENCE), "unknown-key"),
+            "not-a-token".to_string(),
+        ] {
+            server
+                .post("/validate")
+                .json(&json!({ "token": token }))
+                .await
+                .assert_status(StatusCode::UNAUTHORIZED);
+        }
+
+        // Snapshots signed by anyone but the zk-poc-service are not trusted
+        let forged = SigningKey::from_bytes(&[8; 32]);
+        let forging = trusted_entity(
+            backend_url,
+            prove(&records, &forged),
+            None,
+            snapshot_key.verifying_key(),
+        )
+        .await;
+        let token = sign(&claims(AUDIENCE), KEY_ID);
+        forging
+            .post("/validate")
+            .json(&json!({ "token": token }))
+            .await
+            .assert_status(StatusCode::BAD_GATEWAY);
+
+        // Without burning the token, which still validates once the proofs check out
+        server
+            .post("/validate")
+            .json(&json!({ "token": token }))
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn period_totals_are_checked_against_the_token() {
+        let snapshot_key = SigningKey::from_bytes(&[7; 32]);
+        let records = records();
+        let proof = prove_period(&records, &snapshot_key);
+        let backend_url = backend().await;
+        let server = trusted_entity(
+            backend_url.clone(),
+            Vec::new(),
+            Some(proof.clone()),
+            snapshot_key.verifying_key(),
+        )
+        .await;
+        let period = period(&records);
+        let claims = |generated: BigDecimal| RangeValidationClaims {
+            uid: period.user_id,
+            cid: period.community_id,
+            start: period.start,
+            end: period.end,
+            cnt: records.len() as u64,
+            generated,
+            consumed: proof.statement.consumed.clone(),
+            registered: registered(AUDIENCE),
+        };
+
+        let token = claims(proof.statement.generated.clone());
+        let response = server
+            .post("/validate")
+            .json(&json!({ "token": sign(&token, KEY_ID) }))
+            .await;
+        response.assert_status_ok();
+        let cookie = response.cookie(SESSION_COOKIE);
+        let ValidateResponse::PeriodTotals { period_totals } = response.json() else {
+            panic!("range tokens validate period totals");
+        };
+        assert_eq!(period_totals, token.registered.jti);
+
+        let proven = server
+            .get(&format!("/validate/period-totals?query={period_totals}"))
+            .add_cookie(cookie.clone())
+            .await
+            .json::<PeriodTotalsProof>();
+        assert_eq!(proven.statement, token.statement());
+
+        let bundle = server
+            .get(&format!(
+                "/validate/period-totals/bundle?query={period_totals}"
+            ))
+            .add_cookie(cookie)
+            .await
+            .json::<ProofBundle>();
+        assert!(
+            bundle
+                .verify(Some(&snapshot_key.verifying_key()))
+                .unwrap()
+                .valid
+        );
+
+        // Totals the committed records don't add up to are refused
+        let inflated = claims(&proof.statement.generated + BigDecimal::from(1));
+        server
+            .post("/validate")
+            .json(&json!({ "token": sign(&inflated, KEY_ID) }))
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        // So are proofs against snapshots signed by anyone but the zk-poc-service
+        let forged = SigningKey::from_bytes(&[8; 32]);
+        let server = trusted_entity(
+            backend_url,
+            Vec::new(),
+            Some(prove_period(&records, &forged)),
+            snapshot_key.verifying_key(),
+        )
+        .await;
+        server
+            .post("/validate")
+            .json(&json!({ "token": sign(&claims(proof.statement.generated.clone()), KEY_ID) }))
+            .await
+            .assert_status(StatusCode::BAD_GATEWAY);
+    }
+}
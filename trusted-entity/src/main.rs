@@ -0,0 +1,103 @@
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::http::HeaderValue;
+use clap::Parser;
+use common::service_auth::ServiceKey;
+use ed25519_dalek::{VerifyingKey, pkcs8::DecodePublicKey};
+use tokio::net::TcpListener;
+use tracing::info;
+use trusted_entity::{AppState, ServiceClient, TokenVerifier, router};
+use url::Url;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Address to bind the listener to
+    #[arg(long, env, default_value = "0.0.0.0:3003")]
+    listener: SocketAddr,
+
+    /// Origin of the community frontend, which posts validation tokens here
+    #[arg(long, env, default_value = "http://localhost:5173")]
+    allowed_origin: HeaderValue,
+
+    /// URL of the key set validation tokens are signed with
+    #[arg(
+        long,
+        env,
+        default_value = "http://localhost:8080/.well-known/jwks.json"
+    )]
+    validation_jwks_url: Url,
+
+    /// Issuer validation tokens must name
+    #[arg(long, env, default_value = "petall-community-backend")]
+    validation_token_issuer: String,
+
+    /// Audience validation tokens must name
+    #[arg(long, env, default_value = "petall-trusted-entity")]
+    validation_token_audience: String,
+
+    /// URL of the community backend, which validation tokens are redeemed with
+    #[arg(long, env, default_value = "http://localhost:8080")]
+    backend_url: Url,
+
+    /// Hex encoded key shared with the community backend
+    #[arg(long, env, value_parser = load_service_key_from_file)]
+    backend_key: ServiceKey,
+
+    /// URL of the zk-poc-service, which proves the records
+    #[arg(long, env, default_value = "http://localhost:3002")]
+    zk_poc_service_url: Url,
+
+    /// Hex encoded key shared with the zk-poc-service
+    #[arg(long, env, value_parser = load_service_key_from_file)]
+    zk_service_key: ServiceKey,
+
+    /// Ed25519 public key (PEM) the zk-poc-service signs snapshot roots with
+    #[arg(long, env, value_parser = load_verifying_key_from_file)]
+    snapshot_public_key: VerifyingKey,
+}
+
+fn load_service_key_from_file(path: &str) -> anyhow::Result<ServiceKey> {
+    ServiceKey::load(path)
+}
+
+fn load_verifying_key_from_file(path: &str) -> anyhow::Result<VerifyingKey> {
+    Ok(VerifyingKey::from_public_key_pem(
+        &std::fs::read_to_string(path)?,
+    )?)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::parse();
+
+    let http = reqwest::Client::new();
+    let state = AppState::new(
+        TokenVerifier::new(
+            args.validation_jwks_url,
+            &args.validation_token_issuer,
+            &args.validation_token_audience,
+            http.clone(),
+        ),
+        ServiceClient::new(args.backend_url, args.backend_key, http.clone()),
+        ServiceClient::new(args.zk_poc_service_url, args.zk_service_key, http),
+        args.snapshot_public_key,
+    );
+    let app = router(state, args.allowed_origin);
+
+    info!("Starting trusted entity in {}", args.listener);
+
+    let listener = TcpListener::bind(args.listener)
+        .await
+        .context("Failed to bind listener")?;
+
+    tokio::select! {
+        _ = axum::serve(listener, app) => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Clients for the services the trusted entity talks to, authenticated with the keys shared with
+//! each of them, see [`common::service_auth`].
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    http::{Method, StatusCode, header::CONTENT_TYPE},
+};
+use common::service_auth::{ServiceKey, sign_request, verify_response};
+use url::Url;
+
+pub struct ServiceClient {
+    base_url: Url,
+    key: ServiceKey,
+    http: reqwest::Client,
+}
+
+impl ServiceClient {
+    pub fn new(base_url: Url, key: ServiceKey, http: reqwest::Client) -> Self {
+        Self {
+            base_url,
+            key,
+            http,
+        }
+    }
+
+    /// Sends a request signed with the shared key, and checks the response was signed with it
+    /// too. Error statuses are returned rather than failing, as long as they are signed.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+    ) -> anyhow::Result<(StatusCode, Bytes)> {
+        let url = self.base_url.join(path)?;
+        let path_and_query = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        let (headers, signature) = sign_request(&self.key, &method, &path_and_query, &body);
+
+        let response = self
+            .http
+            .request(method, url.as_str())
+            .headers(headers)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {url}"))?;
+        let status = response.status();
+        let response_headers = response.headers().clone();
+        let body = response.bytes().await?;
+
+        verify_response(&self.key, &signature, status, &response_headers, &body)
+            .with_context(|| format!("Response from {url} was not signed with the shared key"))?;
+
+        Ok((status, body))
+    }
+}
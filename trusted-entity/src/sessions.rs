@@ -0,0 +1,134 @@
+//! Validation results, kept per user in sessions so the user can look at them after being
+//! redirected here.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use common::{EnergyRecord, aggregate::PeriodTotalsProof, snapshot::RecordInclusionProof};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+
+pub const SESSION_DURATION: Duration = Duration::hours(24);
+
+/// A record along with the proof that it is part of a signed snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationResult {
+    pub proof: RecordInclusionProof,
+    pub energy_record: EnergyRecord,
+}
+
+struct Session {
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+    /// Results by energy record ID
+    results: HashMap<Uuid, ValidationResult>,
+    /// Proven period totals by the ID of the token they were redeemed with
+    period_totals: HashMap<Uuid, PeriodTotalsProof>,
+}
+
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: RwLock<HashMap<Uuid, Session>>,
+}
+
+impl SessionStore {
+    /// Adds results to the user's session, starting one if they don't have one yet. Either way
+    /// the session lasts [`SESSION_DURATION`] from now on. Returns the session ID.
+    pub async fn store_results(
+        &self,
+        user_id: Uuid,
+        results: impl IntoIterator<Item = ValidationResult>,
+    ) -> Uuid {
+        self.with_session(user_id, |session| {
+            session.results.extend(
+                results
+                    .into_iter()
+                    .map(|result| (result.energy_record.id, result)),
+            );
+        })
+        .await
+    }
+
+    /// Adds proven period totals to the user's session, like [`Self::store_results`]
+    pub async fn store_period_totals(
+        &self,
+        user_id: Uuid,
+        token_id: Uuid,
+        proof: PeriodTotalsProof,
+    ) -> Uuid {
+        self.with_session(user_id, |session| {
+            session.period_totals.insert(token_id, proof);
+        })
+        .await
+    }
+
+    async fn with_session(&self, user_id: Uuid, update: impl FnOnce(&mut Session)) -> Uuid {
+        let now = Utc::now();
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, session| session.expires_at > now);
+
+        let session_id = sessions
+            .iter()
+            .find(|(_, session)| session.user_id == user_id)
+            .map_or_else(Uuid::new_v4, |(&id, _)| id);
+        let session = sessions.entry(session_id).or_insert_with(|| Session {
+            user_id,
+            expires_at: now,
+            results: HashMap::new(),
+            period_totals: HashMap::new(),
+        });
+
+        session.expires_at = now + SESSION_DURATION;
+        update(session);
+
+        session_id
+    }
+
+    pub async fn get_result(
+        &self,
+        session_id: Uuid,
+        energy_record_id: Uuid,
+    ) -> AppResult<ValidationResult> {
+        self.read_session(session_id, |session| {
+            session
+                .results
+                .get(&energy_record_id)
+                .cloned()
+                .ok_or(AppError::ResultNotFound(energy_record_id))
+        })
+        .await
+    }
+
+    pub async fn get_period_totals(
+        &self,
+        session_id: Uuid,
+        token_id: Uuid,
+    ) -> AppResult<PeriodTotalsProof> {
+        self.read_session(session_id, |session| {
+            session
+                .period_totals
+                .get(&token_id)
+                .cloned()
+                .ok_or(AppError::PeriodTotalsNotFound(token_id))
+        })
+        .await
+    }
+
+    async fn read_session<T>(
+        &self,
+        session_id: Uuid,
+        read: impl FnOnce(&Session) -> AppResult<T>,
+    ) -> AppResult<T> {
+        let sessions = self.sessions.read().await;
+        let session = sessions
+            .get(&session_id)
+            .filter(|session| session.expires_at > Utc::now())
+            .ok_or(AppError::InvalidSession)?;
+
+        read(session)
+    }
+}
@@ -0,0 +1,528 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    debug_handler,
+    extract::{Path, Query, State},
+    http::{Method, header::CONTENT_TYPE},
+    middleware,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use common::{
+    EnergyRecord,
+    aggregate::{MemberPeriod, PeriodTotalsProof},
+    allocation::AllocationProof,
+    entitlement::{GenerationThreshold, GenerationThresholdProof},
+    merkle::Hash,
+    service_auth::{ServiceKey, authenticate_service, sign_request, verify_response},
+    snapshot::{CommittedRecords, RecordInclusionProof},
+    transparency::{ConsistencyProof, InclusionProof, LogEntry, SignedLogHead},
+    verify::{Verdict, verify_generation_threshold, verify_period_totals, verify_record_inclusion},
+};
+use ed25519_dalek::SigningKey;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    snapshot::{CommunitySettings, ProveError, SnapshotStore},
+    transparency::{LogError, TransparencyLog},
+};
+
+mod scheme;
+mod snapshot;
+mod transparency;
+
+pub use snapshot::{commit_snapshots, run_periodic_snapshot_task};
+
+/// Handles shared by the routes and the snapshot task
+#[derive(Clone)]
+pub struct AppState {
+    url: Arc<Url>,
+    communities_url: Arc<Url>,
+    commits_url: Arc<Url>,
+    backend_key: Arc<ServiceKey>,
+    http: reqwest::Client,
+    snapshots: Arc<SnapshotStore>,
+    log: Arc<TransparencyLog>,
+}
+
+impl AppState {
+    /// Opens the transparency log kept in `log_file`, and signs snapshots and log heads with
+    /// `signing_key`
+    pub fn new(
+        url: Url,
+        communities_url: Url,
+        commits_url: Url,
+        backend_key: ServiceKey,
+        signing_key: SigningKey,
+        log_file: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let log = TransparencyLog::open(signing_key.clone(), log_file)
+            .context("Failed to load the transparency log")?;
+        let log = Arc::new(log);
+
+        Ok(Self {
+            url: Arc::new(url),
+            communities_url: Arc::new(communities_url),
+            commits_url: Arc::new(commits_url),
+            backend_key: Arc::new(backend_key),
+            http: reqwest::Client::new(),
+            snapshots: Arc::new(SnapshotStore::new(signing_key, log.clone())),
+            log,
+        })
+    }
+
+    /// Sends a request to the community backend, signing it and checking the response was signed
+    /// by the backend
+    async fn backend_request(
+        &self,
+        method: Method,
+        url: &Url,
+        body: Vec<u8>,
+    ) -> anyhow::Result<Bytes> {
+        let path_and_query = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        let (headers, signature) = sign_request(&self.backend_key, &method, &path_and_query, &body);
+
+        let response = self
+            .http
+            .request(method, url.as_str())
+            .headers(headers)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        let status = response.status();
+        let response_headers = response.headers().clone();
+        let body = response.bytes().await?;
+
+        verify_response(
+            &self.backend_key,
+            &signature,
+            status,
+            &response_headers,
+            &body,
+        )
+        .context("Response was not signed by the community backend")?;
+
+        Ok(body)
+    }
+
+    /// Fetches JSON from the community backend
+    async fn fetch_from_backend<T: DeserializeOwned>(&self, url: &Url) -> anyhow::Result<T> {
+        let body = self.backend_request(Method::GET, url, Vec::new()).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Tells the community backend which records the committed snapshots cover, so it only
+    /// signs validation requests for records that can be proven
+    async fn report_committed_records(&self, committed: &CommittedRecords) -> anyhow::Result<()> {
+        self.backend_request(
+            Method::POST,
+            &self.commits_url,
+            serde_json::to_vec(committed)?,
+        )
+        .await
+        .context("Failed to report committed records")?;
+        Ok(())
+    }
+
+    async fn fetch_energy_records(&self) -> anyhow::Result<Vec<EnergyRecord>> {
+        self.fetch_from_backend(&self.url)
+            .await
+            .context("Failed to fetch energy records")
+    }
+
+    async fn fetch_community_settings(&self) -> anyhow::Result<HashMap<Uuid, CommunitySettings>> {
+        #[derive(Deserialize)]
+        struct Community {
+            id: Uuid,
+            #[serde(flatten)]
+            settings: CommunitySettings,
+        }
+
+        let communities: Vec<Community> = self
+            .fetch_from_backend(&self.communities_url)
+            .await
+            .context("Failed to fetch communities")?;
+
+        Ok(communities
+            .into_iter()
+            .map(|community| (community.id, community.settings))
+            .collect())
+    }
+}
+
+/// Routes proving records to the trusted entity, and verifying proofs and reading the log to anyone
+pub fn router(state: AppState, trusted_entity_key: ServiceKey) -> Router {
+    // Proofs reveal record data, so only the trusted entity may request them. Verifying proofs
+    // and fetching snapshot roots stays open to anyone.
+    let trusted_entity = Router::new()
+        .route("/validate/{uuid}", get(validate))
+        .route("/validate-batch", post(validate_batch))
+        .route(
+            "/prove/generation-threshold",
+            post(prove_generation_threshold),
+        )
+        .route("/prove/period-totals", post(prove_period_totals))
+        .route("/prove/allocation", post(prove_allocation))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(trusted_entity_key),
+            authenticate_service,
+        ));
+
+    Router::new()
+        .merge(trusted_entity)
+        .route("/verify", post(verify))
+        .route(
+            "/verify/generation-threshold",
+            post(verify_generation_threshold_proof),
+        )
+        .route("/verify/period-totals", post(verify_period_totals_proof))
+        .route("/verify/allocation", post(verify_allocation_proof))
+        .route("/snapshot/{community_id}", get(latest_snapshot))
+        .route("/public-key", get(public_key))
+        .route("/log/head", get(log_head))
+        .route("/log/entries", get(log_entries))
+        .route("/log/consistency", get(log_consistency))
+        .route("/log/inclusion/{root}", get(log_inclusion))
+        .with_state(state)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidateResponse {
+    proof: RecordInclusionProof,
+    energy_record: EnergyRecord,
+}
+
+#[debug_handler]
+async fn validate(
+    State(state): State<AppState>,
+    Path(energy_record_id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Records only become provable once they are part of a committed snapshot
+    let Some((energy_record, proof)) = state.snapshots.prove_record(energy_record_id).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    Json(ValidateResponse {
+        proof,
+        energy_record,
+    })
+    .into_response()
+}
+
+/// Most records a batch validation can cover, matching the backend's batch tokens
+const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidateBatchRequest {
+    energy_record_ids: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidateBatchResponse {
+    records: Vec<ValidateResponse>,
+    /// Records that are not part of any committed snapshot yet
+    unavailable: Vec<Uuid>,
+}
+
+#[debug_handler]
+async fn validate_batch(
+    State(state): State<AppState>,
+    Json(request): Json<ValidateBatchRequest>,
+) -> impl IntoResponse {
+    if request.energy_record_ids.len() > MAX_BATCH_SIZE {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Batches can have at most {MAX_BATCH_SIZE} records"),
+        )
+            .into_response();
+    }
+
+    let (proofs, unavailable) = state
+        .snapshots
+        .prove_records(&request.energy_record_ids)
+        .await;
+
+    Json(ValidateBatchResponse {
+        records: proofs
+            .into_iter()
+            .map(|(energy_record, proof)| ValidateResponse {
+                proof,
+                energy_record,
+            })
+            .collect(),
+        unavailable,
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyRequest {
+    proof: RecordInclusionProof,
+    energy_record: EnergyRecord,
+    /// Root of the snapshot the caller trusts the record to be in
+    snapshot_root: Hash,
+}
+
+#[debug_handler]
+async fn verify(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyRequest>,
+) -> Json<Verdict> {
+    Json(verify_record_inclusion(
+        &request.proof,
+        &request.energy_record,
+        &request.snapshot_root,
+        &state.snapshots.verifying_key(),
+    ))
+}
+
+impl IntoResponse for ProveError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, message) = match self {
+            ProveError::NoSnapshot => (
+                StatusCode::NOT_FOUND,
+                "No snapshot of the community has been committed yet",
+            ),
+            ProveError::UnknownMember => (
+                StatusCode::NOT_FOUND,
+                "User has no records in the latest snapshot of the community",
+            ),
+            ProveError::InvalidPeriod => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Period start is after its end",
+            ),
+            ProveError::InvalidThreshold => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Threshold must be non-negative with at most 4 decimal places",
+            ),
+            ProveError::ThresholdNotMet => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "User generated less than the threshold over the period",
+            ),
+            ProveError::UnsupportedScheme => (
+                StatusCode::NOT_IMPLEMENTED,
+                "The proof scheme of the community is not supported",
+            ),
+        };
+
+        (status, message).into_response()
+    }
+}
+
+/// Proves that a user generated at least the threshold over a period, without revealing how much
+#[debug_handler]
+async fn prove_generation_threshold(
+    State(state): State<AppState>,
+    Json(statement): Json<GenerationThreshold>,
+) -> Result<Json<GenerationThresholdProof>, ProveError> {
+    Ok(Json(
+        state
+            .snapshots
+            .prove_generation_threshold(statement)
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyGenerationThresholdRequest {
+    proof: GenerationThresholdProof,
+    /// Root of the snapshot the caller trusts the proof to be against
+    snapshot_root: Hash,
+}
+
+#[debug_handler]
+async fn verify_generation_threshold_proof(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyGenerationThresholdRequest>,
+) -> Json<Verdict> {
+    Json(verify_generation_threshold(
+        &request.proof,
+        &request.snapshot_root,
+        &state.snapshots.verifying_key(),
+    ))
+}
+
+/// Proves a user's totals over a period, so one proof covers a whole billing period
+#[debug_handler]
+async fn prove_period_totals(
+    State(state): State<AppState>,
+    Json(period): Json<MemberPeriod>,
+) -> Result<Json<PeriodTotalsProof>, ProveError> {
+    Ok(Json(state.snapshots.prove_period_totals(period).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyPeriodTotalsRequest {
+    proof: PeriodTotalsProof,
+    /// Root of the snapshot the caller trusts the proof to be against
+    snapshot_root: Hash,
+}
+
+#[debug_handler]
+async fn verify_period_totals_proof(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyPeriodTotalsRequest>,
+) -> Json<Verdict> {
+    Json(verify_period_totals(
+        &request.proof,
+        &request.snapshot_root,
+        &state.snapshots.verifying_key(),
+    ))
+}
+
+/// Proves a user's allocation over a period was computed by the community's distribution rule,
+/// with the community's proof scheme
+#[debug_handler]
+async fn prove_allocation(
+    State(state): State<AppState>,
+    Json(period): Json<MemberPeriod>,
+) -> Result<Json<AllocationProof>, ProveError> {
+    Ok(Json(state.snapshots.prove_allocation(period).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyAllocationRequest {
+    proof: AllocationProof,
+    /// Root of the snapshot the caller trusts the proof to be against
+    snapshot_root: Hash,
+}
+
+#[debug_handler]
+async fn verify_allocation_proof(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyAllocationRequest>,
+) -> Json<Verdict> {
+    Json(
+        state
+            .snapshots
+            .verify_allocation(&request.proof, &request.snapshot_root),
+    )
+}
+
+#[debug_handler]
+async fn latest_snapshot(
+    State(state): State<AppState>,
+    Path(community_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let Some(snapshot) = state.snapshots.latest(community_id).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    Json(snapshot.signed.clone()).into_response()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicKeyResponse {
+    key_id: String,
+    /// Hex encoded Ed25519 public key
+    public_key: String,
+}
+
+#[debug_handler]
+async fn public_key(State(state): State<AppState>) -> Json<PublicKeyResponse> {
+    let verifying_key = state.snapshots.verifying_key();
+
+    Json(PublicKeyResponse {
+        key_id: common::snapshot::key_id(&verifying_key),
+        public_key: hex::encode(verifying_key.as_bytes()),
+    })
+}
+
+impl IntoResponse for LogError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, message) = match self {
+            LogError::InvalidRange => (
+                StatusCode::BAD_REQUEST,
+                "Range is out of order or beyond the log",
+            ),
+            LogError::RangeTooLarge => (
+                StatusCode::BAD_REQUEST,
+                "Proofs cover at most 1000 entries, bridge longer spans with intermediate heads",
+            ),
+            LogError::UnknownRoot => (
+                StatusCode::NOT_FOUND,
+                "No snapshot in the log has this root",
+            ),
+        };
+
+        (status, message).into_response()
+    }
+}
+
+/// Current head of the transparency log, signed with the snapshot key
+#[debug_handler]
+async fn log_head(State(state): State<AppState>) -> Json<SignedLogHead> {
+    Json(state.log.head().await)
+}
+
+#[derive(Deserialize)]
+struct LogEntriesQuery {
+    start: u64,
+    end: u64,
+}
+
+/// Entries of the transparency log in `[start, end)`, a page at a time
+#[debug_handler]
+async fn log_entries(
+    State(state): State<AppState>,
+    Query(query): Query<LogEntriesQuery>,
+) -> Result<Json<Vec<LogEntry>>, LogError> {
+    Ok(Json(state.log.entries(query.start..query.end).await?))
+}
+
+#[derive(Deserialize)]
+struct LogConsistencyQuery {
+    from: u64,
+    to: u64,
+}
+
+/// Proves the transparency log at size `to` extends the log at size `from`
+#[debug_handler]
+async fn log_consistency(
+    State(state): State<AppState>,
+    Query(query): Query<LogConsistencyQuery>,
+) -> Result<Json<ConsistencyProof>, LogError> {
+    Ok(Json(state.log.consistency(query.from, query.to).await?))
+}
+
+#[derive(Deserialize)]
+struct LogInclusionQuery {
+    size: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogInclusionResponse {
+    entry: LogEntry,
+    proof: InclusionProof,
+}
+
+/// Proves the snapshot with `root` is in the transparency log at `size`
+#[debug_handler]
+async fn log_inclusion(
+    State(state): State<AppState>,
+    Path(root): Path<Hash>,
+    Query(query): Query<LogInclusionQuery>,
+) -> Result<Json<LogInclusionResponse>, LogError> {
+    let (entry, proof) = state.log.inclusion(&root, query.size).await?;
+    Ok(Json(LogInclusionResponse { entry, proof }))
+}
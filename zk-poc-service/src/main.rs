@@ -1,22 +1,13 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Context;
-use axum::{
-    Json, Router, debug_handler,
-    extract::{Path, State},
-    response::IntoResponse,
-    routing::get,
-};
-use common::EnergyRecord;
-use rand::distributions::{Alphanumeric, DistString};
-use reqwest::StatusCode;
-use serde::Serialize;
+use clap::Parser;
+use common::service_auth::ServiceKey;
+use ed25519_dalek::{SigningKey, pkcs8::DecodePrivateKey};
 use tokio::net::TcpListener;
 use tracing::info;
 use url::Url;
-use uuid::Uuid;
-
-use clap::Parser;
+use zk_poc_service::{AppState, router, run_periodic_snapshot_task};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -29,25 +20,49 @@ struct Args {
     #[arg(
         long,
         env,
-        default_value = "http://localhost:8080/energy-record-unauthenticated/"
+        default_value = "http://localhost:8080/service/energy-record/"
     )]
     url: Url,
+
+    /// URL to fetch communities, their distribution rules and proof schemes from
+    #[arg(long, env, default_value = "http://localhost:8080/service/community/")]
+    communities_url: Url,
+
+    /// URL to report the records covered by committed snapshots to
+    #[arg(
+        long,
+        env,
+        default_value = "http://localhost:8080/service/snapshot-commit"
+    )]
+    commits_url: Url,
+
+    /// Ed25519 private key (PKCS#8 PEM) used to sign snapshot roots
+    #[arg(long, env, value_parser = load_signing_key_from_file)]
+    snapshot_signing_key: SigningKey,
+
+    /// File the transparency log is kept in, created if it doesn't exist
+    #[arg(long, env, default_value = "transparency-log.jsonl")]
+    transparency_log_file: PathBuf,
+
+    /// Seconds between snapshot commits
+    #[arg(long, env, default_value_t = 900)]
+    snapshot_interval_seconds: u64,
+
+    /// Hex encoded key shared with the community backend, signing requests for its records
+    #[arg(long, env, value_parser = load_service_key_from_file)]
+    backend_key: ServiceKey,
+
+    /// Hex encoded key shared with the trusted entity, the only caller allowed to request proofs
+    #[arg(long, env, value_parser = load_service_key_from_file)]
+    trusted_entity_key: ServiceKey,
 }
 
-#[derive(Clone)]
-struct AppState {
-    url: Arc<Url>,
+fn load_signing_key_from_file(path: &str) -> anyhow::Result<SigningKey> {
+    Ok(SigningKey::from_pkcs8_pem(&std::fs::read_to_string(path)?)?)
 }
 
-impl AppState {
-    async fn fetch_energy_record(&self, energy_record_id: Uuid) -> anyhow::Result<EnergyRecord> {
-        reqwest::get(self.url.join(&energy_record_id.to_string())?)
-            .await
-            .context("Failed to fetch energy record")?
-            .json::<EnergyRecord>()
-            .await
-            .context("Failed to parse energy record")
-    }
+fn load_service_key_from_file(path: &str) -> anyhow::Result<ServiceKey> {
+    ServiceKey::load(path)
 }
 
 #[tokio::main]
@@ -56,13 +71,21 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
-    let state = AppState {
-        url: Arc::new(args.url),
-    };
+    let state = AppState::new(
+        args.url,
+        args.communities_url,
+        args.commits_url,
+        args.backend_key,
+        args.snapshot_signing_key,
+        &args.transparency_log_file,
+    )?;
 
-    let app = Router::new()
-        .route("/validate/{uuid}", get(validate))
-        .with_state(state);
+    let snapshotter = tokio::spawn(run_periodic_snapshot_task(
+        state.clone(),
+        Duration::from_secs(args.snapshot_interval_seconds),
+    ));
+
+    let app = router(state, args.trusted_entity_key);
 
     info!("Starting zk poc in {}", args.listener);
 
@@ -72,33 +95,9 @@ async fn main() -> anyhow::Result<()> {
 
     tokio::select! {
         _ = axum::serve(listener, app) => {}
+        _ = snapshotter => {}
         _ = tokio::signal::ctrl_c() => {}
     }
 
     Ok(())
 }
-
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ValidateResponse {
-    proof: String,
-    energy_record: EnergyRecord,
-}
-
-#[debug_handler]
-async fn validate(
-    State(state): State<AppState>,
-    Path(energy_record_id): Path<Uuid>,
-) -> impl IntoResponse {
-    let random_proof_string = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
-
-    let Ok(energy_record) = state.fetch_energy_record(energy_record_id).await else {
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-    };
-
-    Json(ValidateResponse {
-        proof: random_proof_string,
-        energy_record,
-    })
-    .into_response()
-}
@@ -0,0 +1,204 @@
+//! Schemes allocation proofs are made with.
+//!
+//! Each community picks a scheme alongside its distribution rule, and it is committed in the
+//! community's snapshots. Proving and verifying dispatch on the committed scheme, so supporting a
+//! new one only takes an [`AllocationScheme`] registered in [`ProofSchemes::new`].
+
+use std::collections::HashMap;
+
+use common::{
+    aggregate::MemberPeriod,
+    allocation::{AllocationProof, AttestedAllocationProof, ProofScheme},
+    merkle::Hash,
+    verify::{
+        Verdict, VerificationFailure, verify_aggregated_allocation, verify_attested_allocation,
+        verify_opened_allocation,
+    },
+};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use crate::snapshot::{CommunitySnapshot, ProveError};
+
+/// Makes and checks allocation proofs of one [`ProofScheme`]
+pub trait AllocationScheme: Send + Sync {
+    fn scheme(&self) -> ProofScheme;
+
+    /// Proves a member's allocation over a period against `snapshot`
+    fn prove(
+        &self,
+        snapshot: &CommunitySnapshot,
+        period: MemberPeriod,
+    ) -> Result<AllocationProof, ProveError>;
+
+    /// Checks a proof against the snapshot with root `snapshot_root` signed by `verifying_key`
+    fn verify(
+        &self,
+        proof: &AllocationProof,
+        snapshot_root: &Hash,
+        verifying_key: &VerifyingKey,
+    ) -> Verdict;
+}
+
+/// Rejects a proof made with another scheme than the one checking it
+fn scheme_mismatch(proof: &AllocationProof, committed: ProofScheme) -> Verdict {
+    Verdict::from_failures(vec![VerificationFailure::SchemeMismatch {
+        claimed: proof.scheme(),
+        committed,
+    }])
+}
+
+/// [`ProofScheme::CommitmentOpening`]
+pub struct CommitmentOpening;
+
+impl AllocationScheme for CommitmentOpening {
+    fn scheme(&self) -> ProofScheme {
+        ProofScheme::CommitmentOpening
+    }
+
+    fn prove(
+        &self,
+        snapshot: &CommunitySnapshot,
+        period: MemberPeriod,
+    ) -> Result<AllocationProof, ProveError> {
+        Ok(AllocationProof::CommitmentOpening(
+            snapshot.open_allocation(period)?,
+        ))
+    }
+
+    fn verify(
+        &self,
+        proof: &AllocationProof,
+        snapshot_root: &Hash,
+        verifying_key: &VerifyingKey,
+    ) -> Verdict {
+        match proof {
+            AllocationProof::CommitmentOpening(proof) => {
+                verify_opened_allocation(proof, snapshot_root, verifying_key)
+            }
+            _ => scheme_mismatch(proof, self.scheme()),
+        }
+    }
+}
+
+/// [`ProofScheme::Attestation`], signing with the snapshot key
+pub struct Attestation {
+    signing_key: SigningKey,
+}
+
+impl AllocationScheme for Attestation {
+    fn scheme(&self) -> ProofScheme {
+        ProofScheme::Attestation
+    }
+
+    fn prove(
+        &self,
+        snapshot: &CommunitySnapshot,
+        period: MemberPeriod,
+    ) -> Result<AllocationProof, ProveError> {
+        let statement = snapshot.allocation(period)?;
+        let proof =
+            AttestedAllocationProof::sign(statement, snapshot.signed.clone(), &self.signing_key)
+                .expect("allocations are computed in fixed point");
+        Ok(AllocationProof::Attestation(proof))
+    }
+
+    fn verify(
+        &self,
+        proof: &AllocationProof,
+        snapshot_root: &Hash,
+        verifying_key: &VerifyingKey,
+    ) -> Verdict {
+        match proof {
+            AllocationProof::Attestation(proof) => {
+                verify_attested_allocation(proof, snapshot_root, verifying_key)
+            }
+            _ => scheme_mismatch(proof, self.scheme()),
+        }
+    }
+}
+
+/// [`ProofScheme::AggregateCommitment`]
+pub struct AggregateCommitment;
+
+impl AllocationScheme for AggregateCommitment {
+    fn scheme(&self) -> ProofScheme {
+        ProofScheme::AggregateCommitment
+    }
+
+    fn prove(
+        &self,
+        snapshot: &CommunitySnapshot,
+        period: MemberPeriod,
+    ) -> Result<AllocationProof, ProveError> {
+        Ok(AllocationProof::AggregateCommitment(Box::new(
+            snapshot.aggregate_allocation(period)?,
+        )))
+    }
+
+    fn verify(
+        &self,
+        proof: &AllocationProof,
+        snapshot_root: &Hash,
+        verifying_key: &VerifyingKey,
+    ) -> Verdict {
+        match proof {
+            AllocationProof::AggregateCommitment(proof) => {
+                verify_aggregated_allocation(proof, snapshot_root, verifying_key)
+            }
+            _ => scheme_mismatch(proof, self.scheme()),
+        }
+    }
+}
+
+/// The schemes the service can prove and verify allocations with
+pub struct ProofSchemes {
+    schemes: HashMap<ProofScheme, Box<dyn AllocationScheme>>,
+}
+
+impl ProofSchemes {
+    /// Every supported scheme, signing attestations with the snapshot key
+    pub fn new(signing_key: &SigningKey) -> Self {
+        let mut schemes = Self {
+            schemes: HashMap::new(),
+        };
+        schemes.register(CommitmentOpening);
+        schemes.register(Attestation {
+            signing_key: signing_key.clone(),
+        });
+        schemes.register(AggregateCommitment);
+        schemes
+    }
+
+    fn register(&mut self, scheme: impl AllocationScheme + 'static) {
+        self.schemes.insert(scheme.scheme(), Box::new(scheme));
+    }
+
+    /// Proves with the scheme committed in the snapshot
+    pub fn prove(
+        &self,
+        snapshot: &CommunitySnapshot,
+        period: MemberPeriod,
+    ) -> Result<AllocationProof, ProveError> {
+        self.schemes
+            .get(&snapshot.signed.header.proof_scheme)
+            .ok_or(ProveError::UnsupportedScheme)?
+            .prove(snapshot, period)
+    }
+
+    /// Verifies with the scheme committed in the snapshot the proof is against, so a proof can't
+    /// pick a weaker scheme than the community's. The snapshot signature is checked by the scheme.
+    pub fn verify(
+        &self,
+        proof: &AllocationProof,
+        snapshot_root: &Hash,
+        verifying_key: &VerifyingKey,
+    ) -> Verdict {
+        let committed = proof.snapshot().header.proof_scheme;
+        match self.schemes.get(&committed) {
+            Some(scheme) => scheme.verify(proof, snapshot_root, verifying_key),
+            None => Verdict::from_failures(vec![VerificationFailure::UnsupportedScheme {
+                scheme: committed,
+            }]),
+        }
+    }
+}
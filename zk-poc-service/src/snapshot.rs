@@ -0,0 +1,1400 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::{AddAssign, Range},
+    sync::Arc,
+    time::Duration,
+};
+
+use bulletproofs::{PedersenGens, RangeProof};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use common::{
+    EnergyRecord,
+    aggregate::{MemberPeriod, PeriodTotals, PeriodTotalsProof},
+    allocation::{
+        AggregateOpening, AggregatedAllocationProof, Allocation, AllocationProof, CommunityTotals,
+        DistributionRule, MemberRecordRange, MemberShareInputs, OpenedAllocationProof, ProofScheme,
+    },
+    entitlement::{
+        GenerationThreshold, GenerationThresholdProof, RANGE_PROOF_BITS, range_proof_generators,
+    },
+    from_fixed_point,
+    merkle::{Hash, MerkleTree},
+    snapshot::{
+        CommittedRecords, MemberAggregate, QuantityBlindings, RecordCommitment,
+        RecordInclusionProof, RecordRangeProof, SignedSnapshot, SnapshotHeader, aggregate_day,
+        aggregates_leaf, day_leaf, member_leaf,
+    },
+    to_fixed_point,
+    verify::Verdict,
+};
+use curve25519_dalek::Scalar;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{AppState, scheme::ProofSchemes, transparency::TransparencyLog};
+
+struct MemberRecords {
+    user_id: Uuid,
+    records: Vec<EnergyRecord>,
+    salts: Vec<Hash>,
+    commitments: Vec<RecordCommitment>,
+    tree: MerkleTree,
+}
+
+impl MemberRecords {
+    /// Indices of the records starting within `[start, end)`
+    fn period(&self, start: NaiveDateTime, end: NaiveDateTime) -> Range<usize> {
+        let low = self.records.partition_point(|record| record.start < start);
+        let high = self.records.partition_point(|record| record.start < end);
+        low..high
+    }
+
+    /// Sums the quantities of the records at `indices`, along with the blindings of their
+    /// commitments
+    fn totals(&self, indices: Range<usize>) -> Totals {
+        let mut totals = Totals::default();
+        for index in indices {
+            let record = &self.records[index];
+            let blindings = QuantityBlindings::derive(&self.salts[index]);
+            let fixed_point =
+                |value| to_fixed_point(value).expect("records with invalid quantities are skipped");
+
+            totals.generated += fixed_point(&record.generated);
+            totals.consumed += fixed_point(&record.consumed);
+            totals.generated_blinding += blindings.generated;
+            totals.consumed_blinding += blindings.consumed;
+        }
+        totals
+    }
+
+    /// Commitments to the records at `indices` and one record on either side, which show the
+    /// period they were picked for is covered completely
+    fn record_range(&self, indices: &Range<usize>) -> MemberRecordRange {
+        let first = indices.start.saturating_sub(1);
+        let end = (indices.end + 1).min(self.records.len());
+
+        MemberRecordRange {
+            user_id: self.user_id,
+            records: self.commitments[first..end].to_vec(),
+            record_path: self
+                .tree
+                .range_proof(first, end - first)
+                .expect("members have at least one record"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    generated: u64,
+    consumed: u64,
+    generated_blinding: Scalar,
+    consumed_blinding: Scalar,
+}
+
+impl AddAssign<&Totals> for Totals {
+    fn add_assign(&mut self, other: &Totals) {
+        self.generated += other.generated;
+        self.consumed += other.consumed;
+        self.generated_blinding += other.generated_blinding;
+        self.consumed_blinding += other.consumed_blinding;
+    }
+}
+
+/// A member's aggregate of one day, along with the blindings added on top of their records
+struct DayAggregate {
+    member_index: usize,
+    commitment: MemberAggregate,
+    offsets: QuantityBlindings,
+}
+
+/// Aggregates of the members active on a day, in tree order
+struct DayAggregates {
+    /// Index of the day in the tree of days
+    index: usize,
+    aggregates: Vec<DayAggregate>,
+}
+
+/// Members' daily totals under [`ProofScheme::AggregateCommitment`]
+struct Aggregates {
+    days: BTreeMap<NaiveDate, DayAggregates>,
+    tree: MerkleTree,
+}
+
+impl Aggregates {
+    fn build(community_id: Uuid, members: &[MemberRecords], signing_key: &SigningKey) -> Self {
+        let pedersen = PedersenGens::default();
+        let mut days: BTreeMap<NaiveDate, Vec<DayAggregate>> = BTreeMap::new();
+
+        for (member_index, member) in members.iter().enumerate() {
+            let mut first = 0;
+            while first < member.records.len() {
+                let day = member.records[first].start.date();
+                let day_start = day.and_time(NaiveTime::MIN);
+                let indices = member.period(day_start, day_start + chrono::Duration::days(1));
+                let totals = member.totals(indices.clone());
+                let offsets =
+                    QuantityBlindings::derive(&aggregate_salt(signing_key, member.user_id, day));
+
+                days.entry(day).or_default().push(DayAggregate {
+                    member_index,
+                    commitment: MemberAggregate {
+                        generated: pedersen
+                            .commit(
+                                Scalar::from(totals.generated),
+                                totals.generated_blinding + offsets.generated,
+                            )
+                            .compress(),
+                        consumed: pedersen
+                            .commit(
+                                Scalar::from(totals.consumed),
+                                totals.consumed_blinding + offsets.consumed,
+                            )
+                            .compress(),
+                    },
+                    offsets,
+                });
+                first = indices.end;
+            }
+        }
+
+        let days: BTreeMap<_, _> = days
+            .into_iter()
+            .enumerate()
+            .map(|(index, (day, mut aggregates))| {
+                // Ordered by leaf, which says nothing about the member
+                aggregates.sort_by_cached_key(|aggregate| aggregate.commitment.leaf().0);
+                (day, DayAggregates { index, aggregates })
+            })
+            .collect();
+        let tree = MerkleTree::new(
+            days.iter()
+                .map(|(&day, aggregates)| {
+                    let tree = aggregates.tree();
+                    day_leaf(community_id, day, &tree.root(), tree.len())
+                })
+                .collect(),
+        );
+
+        Self { days, tree }
+    }
+}
+
+impl DayAggregates {
+    fn tree(&self) -> MerkleTree {
+        MerkleTree::new(
+            self.aggregates
+                .iter()
+                .map(|aggregate| aggregate.commitment.leaf())
+                .collect(),
+        )
+    }
+}
+
+/// Reasons a statement can't be proven
+#[derive(Debug, PartialEq)]
+pub enum ProveError {
+    /// No snapshot of the community has been committed yet
+    NoSnapshot,
+    /// The user has no records in the latest snapshot of the community
+    UnknownMember,
+    InvalidPeriod,
+    InvalidThreshold,
+    /// The statement is false, so there is nothing to prove
+    ThresholdNotMet,
+    /// The community's proofs are made with a scheme this service doesn't support
+    UnsupportedScheme,
+}
+
+/// How a community shares generation and proves allocations, as configured in the backend
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct CommunitySettings {
+    pub distribution_rule: DistributionRule,
+    pub proof_scheme: ProofScheme,
+}
+
+/// Committed snapshot of a community, along with everything needed to prove records against it
+pub struct CommunitySnapshot {
+    pub signed: SignedSnapshot,
+    members: Vec<MemberRecords>,
+    /// Only committed under [`ProofScheme::AggregateCommitment`]
+    aggregates: Option<Aggregates>,
+    member_tree: MerkleTree,
+    /// Record ID -> (member index, record index)
+    positions: HashMap<Uuid, (usize, usize)>,
+}
+
+impl CommunitySnapshot {
+    fn build(
+        community_id: Uuid,
+        sequence: u64,
+        taken_at: DateTime<Utc>,
+        settings: CommunitySettings,
+        records: Vec<EnergyRecord>,
+        signing_key: &SigningKey,
+    ) -> Self {
+        let mut records_by_member: BTreeMap<Uuid, Vec<EnergyRecord>> = BTreeMap::new();
+        for record in records {
+            records_by_member
+                .entry(record.user_id)
+                .or_default()
+                .push(record);
+        }
+
+        let mut members = Vec::with_capacity(records_by_member.len());
+        let mut positions = HashMap::new();
+
+        for (user_id, mut member_records) in records_by_member {
+            member_records.sort_by_key(|record| (record.start, record.id));
+
+            let mut records = Vec::with_capacity(member_records.len());
+            let mut salts = Vec::with_capacity(member_records.len());
+            let mut commitments = Vec::with_capacity(member_records.len());
+
+            for record in member_records {
+                let salt = record_salt(signing_key, record.id);
+                let Some(commitment) = RecordCommitment::open(&salt, &record) else {
+                    warn!(
+                        "Skipping energy record {} with invalid quantities",
+                        record.id
+                    );
+                    continue;
+                };
+                positions.insert(record.id, (members.len(), records.len()));
+                records.push(record);
+                salts.push(salt);
+                commitments.push(commitment);
+            }
+            if records.is_empty() {
+                continue;
+            }
+
+            let tree = MerkleTree::new(
+                commitments
+                    .iter()
+                    .map(|commitment| commitment.leaf(user_id, community_id))
+                    .collect(),
+            );
+
+            members.push(MemberRecords {
+                user_id,
+                records,
+                salts,
+                commitments,
+                tree,
+            });
+        }
+
+        let aggregates = (settings.proof_scheme == ProofScheme::AggregateCommitment)
+            .then(|| Aggregates::build(community_id, &members, signing_key));
+        let member_tree = MerkleTree::new(
+            members
+                .iter()
+                .map(|member| member_leaf(member.user_id, &member.tree.root(), member.tree.len()))
+                .chain(
+                    aggregates
+                        .iter()
+                        .map(|aggregates| aggregates_leaf(&aggregates.tree.root())),
+                )
+                .collect(),
+        );
+
+        let header = SnapshotHeader {
+            community_id,
+            sequence,
+            taken_at,
+            record_count: positions.len() as u64,
+            distribution_rule: settings.distribution_rule,
+            proof_scheme: settings.proof_scheme,
+            root: member_tree.root(),
+        };
+
+        Self {
+            signed: SignedSnapshot::sign(header, signing_key),
+            members,
+            aggregates,
+            member_tree,
+            positions,
+        }
+    }
+
+    /// IDs of the records in the snapshot, leaving out those that were skipped
+    pub fn record_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.positions.keys().copied()
+    }
+
+    pub fn prove_record(&self, record_id: Uuid) -> Option<(EnergyRecord, RecordInclusionProof)> {
+        let &(member_index, record_index) = self.positions.get(&record_id)?;
+        let member = &self.members[member_index];
+
+        let proof = RecordInclusionProof {
+            snapshot: self.signed.clone(),
+            salt: member.salts[record_index],
+            record_path: member.tree.proof(record_index)?,
+            member_path: self.member_tree.proof(member_index)?,
+        };
+
+        Some((member.records[record_index].clone(), proof))
+    }
+
+    /// Proves the records of a member at `indices`, along with the neighbouring records that show
+    /// the period is covered completely
+    fn prove_period(&self, member_index: usize, indices: &Range<usize>) -> RecordRangeProof {
+        let range = self.members[member_index].record_range(indices);
+
+        RecordRangeProof {
+            snapshot: self.signed.clone(),
+            records: range.records,
+            record_path: range.record_path,
+            member_path: self
+                .member_tree
+                .proof(member_index)
+                .expect("member index is in range"),
+        }
+    }
+
+    /// Finds the member and the indices of their records starting within `[start, end)`
+    fn member_period(
+        &self,
+        user_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(usize, Range<usize>), ProveError> {
+        if start > end {
+            return Err(ProveError::InvalidPeriod);
+        }
+
+        let member_index = self
+            .members
+            .binary_search_by_key(&user_id, |member| member.user_id)
+            .map_err(|_| ProveError::UnknownMember)?;
+        Ok((member_index, self.members[member_index].period(start, end)))
+    }
+
+    pub fn prove_generation_threshold(
+        &self,
+        statement: GenerationThreshold,
+    ) -> Result<GenerationThresholdProof, ProveError> {
+        let mut transcript = statement
+            .transcript(&self.signed.header.root)
+            .ok_or(ProveError::InvalidThreshold)?;
+        let threshold = to_fixed_point(&statement.threshold).ok_or(ProveError::InvalidThreshold)?;
+
+        let (member_index, period) =
+            self.member_period(statement.user_id, statement.start, statement.end)?;
+        let totals = self.members[member_index].totals(period.clone());
+        let surplus = totals
+            .generated
+            .checked_sub(threshold)
+            .ok_or(ProveError::ThresholdNotMet)?;
+
+        let (bp_gens, pc_gens) = range_proof_generators();
+        let (range_proof, _) = RangeProof::prove_single(
+            &bp_gens,
+            &pc_gens,
+            &mut transcript,
+            surplus,
+            &totals.generated_blinding,
+            RANGE_PROOF_BITS,
+        )
+        .expect("a u64 always fits in a 64 bit range proof");
+
+        Ok(GenerationThresholdProof {
+            records: self.prove_period(member_index, &period),
+            statement,
+            range_proof,
+        })
+    }
+
+    pub fn prove_period_totals(
+        &self,
+        period: MemberPeriod,
+    ) -> Result<PeriodTotalsProof, ProveError> {
+        let (member_index, indices) =
+            self.member_period(period.user_id, period.start, period.end)?;
+        let totals = self.members[member_index].totals(indices.clone());
+
+        Ok(PeriodTotalsProof {
+            records: self.prove_period(member_index, &indices),
+            statement: PeriodTotals {
+                period,
+                record_count: indices.len() as u64,
+                generated: from_fixed_point(totals.generated),
+                consumed: from_fixed_point(totals.consumed),
+            },
+            generated_blinding: totals.generated_blinding,
+            consumed_blinding: totals.consumed_blinding,
+        })
+    }
+
+    /// Computes the member's allocation over a period under the community's rule, along with the
+    /// member and community totals it was computed from
+    fn allocation_totals(
+        &self,
+        period: MemberPeriod,
+    ) -> Result<(Allocation, Totals, Totals), ProveError> {
+        let (member_index, indices) =
+            self.member_period(period.user_id, period.start, period.end)?;
+        let member_totals = self.members[member_index].totals(indices.clone());
+
+        let mut community_totals = Totals::default();
+        let mut active_members = 0;
+        for member in &self.members {
+            let indices = member.period(period.start, period.end);
+            if !indices.is_empty() {
+                active_members += 1;
+            }
+            community_totals += &member.totals(indices);
+        }
+
+        let rule = self.signed.header.distribution_rule;
+        let allocated = rule.allocate(
+            &CommunityTotals {
+                generated: community_totals.generated,
+                consumed: community_totals.consumed,
+                active_members,
+            },
+            &MemberShareInputs {
+                record_count: indices.len() as u64,
+                consumed: member_totals.consumed,
+            },
+        );
+
+        let statement = Allocation {
+            member: PeriodTotals {
+                period,
+                record_count: indices.len() as u64,
+                generated: from_fixed_point(member_totals.generated),
+                consumed: from_fixed_point(member_totals.consumed),
+            },
+            rule,
+            active_members,
+            community_generated: from_fixed_point(community_totals.generated),
+            community_consumed: from_fixed_point(community_totals.consumed),
+            allocated: from_fixed_point(allocated),
+        };
+
+        Ok((statement, member_totals, community_totals))
+    }
+
+    /// The member's allocation over a period under the community's rule
+    pub fn allocation(&self, period: MemberPeriod) -> Result<Allocation, ProveError> {
+        Ok(self.allocation_totals(period)?.0)
+    }
+
+    /// Proves the member's allocation over a period by opening commitments. Every member's
+    /// records are included through their commitments, so the community totals can be checked
+    /// without revealing them.
+    pub fn open_allocation(
+        &self,
+        period: MemberPeriod,
+    ) -> Result<OpenedAllocationProof, ProveError> {
+        let (start, end) = (period.start, period.end);
+        let (statement, member_totals, community_totals) = self.allocation_totals(period)?;
+
+        Ok(OpenedAllocationProof {
+            statement,
+            snapshot: self.signed.clone(),
+            members: self
+                .members
+                .iter()
+                .map(|member| member.record_range(&member.period(start, end)))
+                .collect(),
+            member_generated_blinding: member_totals.generated_blinding,
+            member_consumed_blinding: member_totals.consumed_blinding,
+            community_generated_blinding: community_totals.generated_blinding,
+            community_consumed_blinding: community_totals.consumed_blinding,
+        })
+    }
+
+    /// Proves the member's allocation over a day from the aggregates of the members active that day,
+    /// opening only the member's own. Days without any records can't be proven.
+    pub fn aggregate_allocation(
+        &self,
+        period: MemberPeriod,
+    ) -> Result<AggregatedAllocationProof, ProveError> {
+        let aggregates = self
+            .aggregates
+            .as_ref()
+            .ok_or(ProveError::UnsupportedScheme)?;
+        let day = aggregate_day(period.start, period.end)
+            .and_then(|day| aggregates.days.get(&day))
+            .ok_or(ProveError::InvalidPeriod)?;
+        let (member_index, indices) =
+            self.member_period(period.user_id, period.start, period.end)?;
+        let (statement, member_totals, community_totals) = self.allocation_totals(period)?;
+
+        let member_aggregate = day
+            .aggregates
+            .iter()
+            .position(|aggregate| aggregate.member_index == member_index)
+            .map(|index| {
+                let offsets = &day.aggregates[index].offsets;
+                AggregateOpening {
+                    index,
+                    generated_blinding: member_totals.generated_blinding + offsets.generated,
+                    consumed_blinding: member_totals.consumed_blinding + offsets.consumed,
+                }
+            });
+        let offsets = |offset: fn(&QuantityBlindings) -> Scalar| -> Scalar {
+            day.aggregates
+                .iter()
+                .map(|aggregate| offset(&aggregate.offsets))
+                .sum()
+        };
+
+        Ok(AggregatedAllocationProof {
+            statement,
+            records: self.prove_period(member_index, &indices),
+            member_generated_blinding: member_totals.generated_blinding,
+            member_consumed_blinding: member_totals.consumed_blinding,
+            aggregates: day
+                .aggregates
+                .iter()
+                .map(|aggregate| aggregate.commitment.clone())
+                .collect(),
+            member_aggregate,
+            day_path: aggregates
+                .tree
+                .proof(day.index)
+                .expect("day index is in range"),
+            aggregates_path: self
+                .member_tree
+                .proof(self.members.len())
+                .expect("the aggregates leaf follows the members"),
+            community_generated_blinding: community_totals.generated_blinding
+                + offsets(|offsets| offsets.generated),
+            community_consumed_blinding: community_totals.consumed_blinding
+                + offsets(|offsets| offsets.consumed),
+        })
+    }
+}
+
+/// Blindings added on top of a member's records in their aggregate of `day` are derived from the
+/// signing key, like record salts
+fn aggregate_salt(signing_key: &SigningKey, user_id: Uuid, day: NaiveDate) -> Hash {
+    let mut data = Vec::with_capacity(64);
+    data.extend_from_slice(b"petall/aggregate-salt");
+    data.extend_from_slice(signing_key.as_bytes());
+    data.extend_from_slice(user_id.as_bytes());
+    data.extend_from_slice(
+        &day.and_time(NaiveTime::MIN)
+            .and_utc()
+            .timestamp()
+            .to_be_bytes(),
+    );
+    Hash::digest(&data)
+}
+
+/// Salts are derived from the signing key so rebuilding a snapshot yields the same leaves
+fn record_salt(signing_key: &SigningKey, record_id: Uuid) -> Hash {
+    let mut data = Vec::with_capacity(64);
+    data.extend_from_slice(b"petall/salt");
+    data.extend_from_slice(signing_key.as_bytes());
+    data.extend_from_slice(record_id.as_bytes());
+    Hash::digest(&data)
+}
+
+/// Latest committed snapshot of every community
+pub struct SnapshotStore {
+    signing_key: SigningKey,
+    schemes: ProofSchemes,
+    /// Every snapshot is appended to the log before it is served, which is also where sequences
+    /// resume from after a restart
+    log: Arc<TransparencyLog>,
+    snapshots: RwLock<HashMap<Uuid, Arc<CommunitySnapshot>>>,
+}
+
+impl SnapshotStore {
+    pub fn new(signing_key: SigningKey, log: Arc<TransparencyLog>) -> Self {
+        Self {
+            schemes: ProofSchemes::new(&signing_key),
+            signing_key,
+            log,
+            snapshots: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Commits a new snapshot for every community present in `records`. Records of communities
+    /// without settings in `communities` are skipped. Nothing is committed unless the snapshots
+    /// could be appended to the log.
+    pub async fn commit(
+        &self,
+        taken_at: DateTime<Utc>,
+        records: Vec<EnergyRecord>,
+        communities: &HashMap<Uuid, CommunitySettings>,
+    ) -> anyhow::Result<Vec<Arc<CommunitySnapshot>>> {
+        let mut records_by_community: HashMap<Uuid, Vec<EnergyRecord>> = HashMap::new();
+        for record in records {
+            records_by_community
+                .entry(record.community_id)
+                .or_default()
+                .push(record);
+        }
+
+        let mut snapshots = self.snapshots.write().await;
+        let sequences = self.log.latest_sequences().await;
+        let mut committed = Vec::with_capacity(records_by_community.len());
+
+        for (community_id, records) in records_by_community {
+            let Some(&settings) = communities.get(&community_id) else {
+                warn!("Skipping records of unknown community {community_id}");
+                continue;
+            };
+            let sequence = sequences
+                .get(&community_id)
+                .map_or(1, |previous| previous + 1);
+
+            committed.push(Arc::new(CommunitySnapshot::build(
+                community_id,
+                sequence,
+                taken_at,
+                settings,
+                records,
+                &self.signing_key,
+            )));
+        }
+
+        // Every root goes into the log, so clients can check the roots they are shown against it
+        self.log
+            .append(committed.iter().map(|snapshot| snapshot.signed.clone()))
+            .await?;
+        for snapshot in &committed {
+            snapshots.insert(snapshot.signed.header.community_id, snapshot.clone());
+        }
+
+        Ok(committed)
+    }
+
+    pub async fn latest(&self, community_id: Uuid) -> Option<Arc<CommunitySnapshot>> {
+        self.snapshots.read().await.get(&community_id).cloned()
+    }
+
+    pub async fn prove_record(
+        &self,
+        record_id: Uuid,
+    ) -> Option<(EnergyRecord, RecordInclusionProof)> {
+        self.snapshots
+            .read()
+            .await
+            .values()
+            .find_map(|snapshot| snapshot.prove_record(record_id))
+    }
+
+    /// Proves each record against the snapshots it's in. Returns the proofs, and the IDs of the
+    /// records that are in no snapshot.
+    pub async fn prove_records(
+        &self,
+        record_ids: &[Uuid],
+    ) -> (Vec<(EnergyRecord, RecordInclusionProof)>, Vec<Uuid>) {
+        let snapshots = self.snapshots.read().await;
+
+        let mut proofs = Vec::with_capacity(record_ids.len());
+        let mut unavailable = Vec::new();
+        for &record_id in record_ids {
+            match snapshots
+                .values()
+                .find_map(|snapshot| snapshot.prove_record(record_id))
+            {
+                Some(proof) => proofs.push(proof),
+                None => unavailable.push(record_id),
+            }
+        }
+
+        (proofs, unavailable)
+    }
+
+    /// Proves the statement against the latest snapshot of its community
+    pub async fn prove_generation_threshold(
+        &self,
+        statement: GenerationThreshold,
+    ) -> Result<GenerationThresholdProof, ProveError> {
+        self.latest(statement.community_id)
+            .await
+            .ok_or(ProveError::NoSnapshot)?
+            .prove_generation_threshold(statement)
+    }
+
+    /// Proves the totals of a member over a period against the latest snapshot of the community
+    pub async fn prove_period_totals(
+        &self,
+        period: MemberPeriod,
+    ) -> Result<PeriodTotalsProof, ProveError> {
+        self.latest(period.community_id)
+            .await
+            .ok_or(ProveError::NoSnapshot)?
+            .prove_period_totals(period)
+    }
+
+    /// Proves a member's allocation over a period against the latest snapshot of the community,
+    /// with the scheme committed in it
+    pub async fn prove_allocation(
+        &self,
+        period: MemberPeriod,
+    ) -> Result<AllocationProof, ProveError> {
+        let snapshot = self
+            .latest(period.community_id)
+            .await
+            .ok_or(ProveError::NoSnapshot)?;
+        self.schemes.prove(&snapshot, period)
+    }
+
+    /// Checks an allocation proof with the scheme committed in the snapshot it is against
+    pub fn verify_allocation(&self, proof: &AllocationProof, snapshot_root: &Hash) -> Verdict {
+        self.schemes
+            .verify(proof, snapshot_root, &self.verifying_key())
+    }
+}
+
+/// Periodically fetches every energy record and commits a new snapshot of each community
+pub async fn run_periodic_snapshot_task(state: AppState, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = commit_snapshots(&state, period).await {
+            error!("Error committing snapshots: {e:#}");
+        }
+    }
+}
+
+/// Commits a new snapshot of each community from the records the backend currently has, and
+/// reports the committed records back to it. `period` is the time until the next commit.
+pub async fn commit_snapshots(state: &AppState, period: Duration) -> anyhow::Result<()> {
+    let taken_at = Utc::now();
+    let records = state.fetch_energy_records().await?;
+    // Fetched after the records, so every community with records is known unless it was deleted
+    let communities = state.fetch_community_settings().await?;
+
+    let committed = state
+        .snapshots
+        .commit(taken_at, records, &communities)
+        .await?;
+    for snapshot in &committed {
+        let header = &snapshot.signed.header;
+        info!(
+            "Committed snapshot {} of community {} with {} records: {}",
+            header.sequence, header.community_id, header.record_count, header.root
+        );
+    }
+    // Only the records that made it into a snapshot, skipped ones can't be proven
+    state
+        .report_committed_records(&CommittedRecords {
+            committed_at: taken_at,
+            next_commit_at: Utc::now() + period,
+            energy_record_ids: committed
+                .iter()
+                .flat_map(|snapshot| snapshot.record_ids())
+                .collect(),
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use bigdecimal::BigDecimal;
+    use chrono::Duration;
+    use common::verify::{
+        Quantity, VerificationFailure, verify_allocation, verify_generation_threshold,
+        verify_period_totals,
+    };
+
+    use super::*;
+
+    /// Three hours of records for each of three members
+    fn community_records(
+        community_id: Uuid,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Vec<EnergyRecord> {
+        let mut records = Vec::new();
+        for _ in 0..3 {
+            let mut member_records =
+                EnergyRecord::random_vec(Uuid::new_v4(), community_id, start, end);
+            for record in &mut member_records {
+                // Values are stored with 4 decimal places
+                record.generated = record.generated.with_scale(4);
+                record.consumed = record.consumed.with_scale(4);
+                record.consumer_price = record.consumer_price.with_scale(4);
+                record.seller_price = record.seller_price.with_scale(4);
+            }
+            records.extend(member_records);
+        }
+        records
+    }
+
+    fn temp_log_path() -> PathBuf {
+        std::env::temp_dir().join(format!("petall-log-{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn store_with_log(log_path: &Path) -> SnapshotStore {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let log = TransparencyLog::open(signing_key.clone(), log_path).unwrap();
+        SnapshotStore::new(signing_key, Arc::new(log))
+    }
+
+    fn store() -> SnapshotStore {
+        store_with_log(&temp_log_path())
+    }
+
+    fn communities(
+        community_id: Uuid,
+        settings: CommunitySettings,
+    ) -> HashMap<Uuid, CommunitySettings> {
+        HashMap::from([(community_id, settings)])
+    }
+
+    #[tokio::test]
+    async fn every_record_proves_against_signed_root() {
+        let store = store();
+        let community_id = Uuid::new_v4();
+        let end = Utc::now().naive_utc();
+        let start = end - Duration::hours(3);
+        let records = community_records(community_id, start, end);
+
+        let committed = store
+            .commit(
+                Utc::now(),
+                records.clone(),
+                &communities(community_id, CommunitySettings::default()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(committed.len(), 1);
+        assert!(committed[0].signed.verify_signature(&store.verifying_key()));
+
+        for record in &records {
+            let (proven_record, proof) = store.prove_record(record.id).await.unwrap();
+            assert_eq!(&proven_record, record);
+            assert_eq!(proof.compute_root(record), Some(proof.snapshot.header.root));
+        }
+
+        // Batches prove every record they can, and list the ones no snapshot has
+        let missing = Uuid::new_v4();
+        let mut ids: Vec<_> = records.iter().map(|record| record.id).collect();
+        ids.push(missing);
+        let (proofs, unavailable) = store.prove_records(&ids).await;
+        assert_eq!(proofs.len(), records.len());
+        assert_eq!(unavailable, vec![missing]);
+        for (record, proof) in &proofs {
+            assert_eq!(proof.compute_root(record), Some(proof.snapshot.header.root));
+        }
+
+        let mut tampered = records[0].clone();
+        tampered.generated += 1;
+        let (_, proof) = store.prove_record(tampered.id).await.unwrap();
+        assert_ne!(
+            proof.compute_root(&tampered),
+            Some(proof.snapshot.header.root)
+        );
+    }
+
+    #[tokio::test]
+    async fn records_with_invalid_quantities_are_left_out() {
+        let store = store();
+        let community_id = Uuid::new_v4();
+        let end = Utc::now().naive_utc();
+        let start = end - Duration::hours(3);
+        let mut records = community_records(community_id, start, end);
+        records[0].generated = BigDecimal::from(-1);
+        // A member whose every record is skipped is left out too
+        let mut skipped_member = community_records(community_id, start, end)[0].clone();
+        skipped_member.user_id = Uuid::new_v4();
+        skipped_member.consumed = "0.00001".parse().unwrap();
+
+        let committed = store
+            .commit(
+                Utc::now(),
+                [records.clone(), vec![skipped_member.clone()]].concat(),
+                &communities(community_id, CommunitySettings::default()),
+            )
+            .await
+            .unwrap();
+
+        let mut record_ids: Vec<_> = committed[0].record_ids().collect();
+        record_ids.sort();
+        let mut expected: Vec<_> = records[1..].iter().map(|record| record.id).collect();
+        expected.sort();
+        assert_eq!(record_ids, expected);
+        assert_eq!(
+            committed[0].signed.header.record_count,
+            expected.len() as u64
+        );
+        assert!(store.prove_record(records[0].id).await.is_none());
+        assert!(store.prove_record(skipped_member.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sequences_resume_from_the_log_after_a_restart() {
+        let log_path = temp_log_path();
+        let community_id = Uuid::new_v4();
+        let end = Utc::now().naive_utc();
+        let start = end - Duration::hours(3);
+        let records = community_records(community_id, start, end);
+        let communities = communities(community_id, CommunitySettings::default());
+
+        let store = store_with_log(&log_path);
+        for sequence in 1..=2 {
+            let committed = store
+                .commit(Utc::now(), records.clone(), &communities)
+                .await
+                .unwrap();
+            assert_eq!(committed[0].signed.header.sequence, sequence);
+        }
+        drop(store);
+
+        let restarted = store_with_log(&log_path);
+        let committed = restarted
+            .commit(Utc::now(), records, &communities)
+            .await
+            .unwrap();
+        assert_eq!(committed[0].signed.header.sequence, 3);
+
+        std::fs::remove_file(log_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn generation_threshold_proofs() {
+        let store = store();
+        let community_id = Uuid::new_v4();
+        let end = Utc::now().naive_utc();
+        let start = end - Duration::hours(3);
+        let records = community_records(community_id, start, end);
+        let snapshot = store
+            .commit(
+                Utc::now(),
+                records.clone(),
+                &communities(community_id, CommunitySettings::default()),
+            )
+            .await
+            .unwrap()
+            .remove(0)
+            .signed
+            .clone();
+        let verifying_key = store.verifying_key();
+
+        // Prove over the middle hour so there are records on both sides of the period
+        let user_id = records[0].user_id;
+        let (period_start, period_end) = (start + Duration::hours(1), start + Duration::hours(2));
+        let generated: BigDecimal = records
+            .iter()
+            .filter(|record| {
+                record.user_id == user_id
+                    && record.start >= period_start
+                    && record.start < period_end
+            })
+            .map(|record| &record.generated)
+            .sum();
+
+        let statement = GenerationThreshold {
+            user_id,
+            community_id,
+            start: period_start,
+            end: period_end,
+            threshold: generated.clone(),
+        };
+
+        let proof = store
+            .prove_generation_threshold(statement.clone())
+            .await
+            .unwrap();
+        let verdict = verify_generation_threshold(&proof, &snapshot.header.root, &verifying_key);
+        assert!(verdict.valid, "{verdict:?}");
+        assert!(serde_json::to_string(&proof).unwrap().len() < 8192);
+
+        // Claiming more than was generated can be neither proven nor passed off
+        let mut overclaim = statement.clone();
+        overclaim.threshold = &generated + BigDecimal::new(1.into(), 4);
+        assert_eq!(
+            store
+                .prove_generation_threshold(overclaim.clone())
+                .await
+                .unwrap_err(),
+            ProveError::ThresholdNotMet
+        );
+
+        let mut forged = proof.clone();
+        forged.statement = overclaim;
+        let verdict = verify_generation_threshold(&forged, &snapshot.header.root, &verifying_key);
+        assert_eq!(verdict.failures, [VerificationFailure::InvalidRangeProof]);
+
+        // Leaving out the first record of the period
+        let mut truncated = proof.clone();
+        truncated.records.records.remove(0);
+        truncated.records.record_path.first += 1;
+        let verdict =
+            verify_generation_threshold(&truncated, &snapshot.header.root, &verifying_key);
+        assert!(!verdict.valid);
+    }
+
+    #[tokio::test]
+    async fn period_totals_proofs() {
+        let store = store();
+        let community_id = Uuid::new_v4();
+        let end = Utc::now().naive_utc();
+        let start = end - Duration::hours(3);
+        let records = community_records(community_id, start, end);
+        let snapshot = store
+            .commit(
+                Utc::now(),
+                records.clone(),
+                &communities(community_id, CommunitySettings::default()),
+            )
+            .await
+            .unwrap()
+            .remove(0)
+            .signed
+            .clone();
+        let verifying_key = store.verifying_key();
+
+        let user_id = records[0].user_id;
+        let period = MemberPeriod {
+            user_id,
+            community_id,
+            start: start + Duration::hours(1),
+            end: start + Duration::hours(2),
+        };
+        let in_period: Vec<_> = records
+            .iter()
+            .filter(|record| {
+                record.user_id == user_id
+                    && record.start >= period.start
+                    && record.start < period.end
+            })
+            .collect();
+
+        let proof = store.prove_period_totals(period).await.unwrap();
+        assert_eq!(proof.statement.record_count, in_period.len() as u64);
+        assert_eq!(
+            proof.statement.generated,
+            in_period
+                .iter()
+                .map(|record| &record.generated)
+                .sum::<BigDecimal>()
+        );
+        assert_eq!(
+            proof.statement.consumed,
+            in_period
+                .iter()
+                .map(|record| &record.consumed)
+                .sum::<BigDecimal>()
+        );
+
+        let verdict = verify_period_totals(&proof, &snapshot.header.root, &verifying_key);
+        assert!(verdict.valid, "{verdict:?}");
+
+        let mut forged = proof.clone();
+        forged.statement.consumed -= BigDecimal::new(1.into(), 4);
+        let verdict = verify_period_totals(&forged, &snapshot.header.root, &verifying_key);
+        assert_eq!(
+            verdict.failures,
+            [VerificationFailure::TotalMismatch {
+                quantity: Quantity::Consumed
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn allocation_proofs() {
+        for rule in [
+            DistributionRule::Equal,
+            DistributionRule::ProportionalToConsumption,
+        ] {
+            let store = store();
+            let community_id = Uuid::new_v4();
+            let end = Utc::now().naive_utc();
+            let start = end - Duration::hours(3);
+            let records = community_records(community_id, start, end);
+            let settings = CommunitySettings {
+                distribution_rule: rule,
+                proof_scheme: ProofScheme::CommitmentOpening,
+            };
+            let snapshot = store
+                .commit(
+                    Utc::now(),
+                    records.clone(),
+                    &communities(community_id, settings),
+                )
+                .await
+                .unwrap()
+                .remove(0)
+                .signed
+                .clone();
+            let root = snapshot.header.root;
+
+            let mut members: Vec<_> = records.iter().map(|record| record.user_id).collect();
+            members.dedup();
+
+            let mut allocated = BigDecimal::from(0);
+            for &user_id in &members {
+                let proof = store
+                    .prove_allocation(MemberPeriod {
+                        user_id,
+                        community_id,
+                        start: start + Duration::hours(1),
+                        end: start + Duration::hours(2),
+                    })
+                    .await
+                    .unwrap();
+                let AllocationProof::CommitmentOpening(opened) = &proof else {
+                    panic!("proof is not made with the committed scheme: {proof:?}");
+                };
+                assert_eq!(opened.statement.rule, rule);
+                assert_eq!(opened.statement.active_members, 3);
+
+                let verdict = store.verify_allocation(&proof, &root);
+                assert!(verdict.valid, "{verdict:?}");
+
+                let mut forged = opened.clone();
+                forged.statement.allocated += BigDecimal::new(1.into(), 4);
+                let verdict =
+                    store.verify_allocation(&AllocationProof::CommitmentOpening(forged), &root);
+                assert!(matches!(
+                    verdict.failures[..],
+                    [VerificationFailure::AllocationMismatch { .. }]
+                ));
+
+                // Hiding a member would change the snapshot root
+                let mut missing_member = opened.clone();
+                missing_member
+                    .members
+                    .retain(|member| member.user_id == user_id);
+                let verdict = store
+                    .verify_allocation(&AllocationProof::CommitmentOpening(missing_member), &root);
+                assert!(!verdict.valid);
+
+                allocated += &opened.statement.allocated;
+                if user_id == members[2] {
+                    // Rounding down never hands out more than was generated
+                    assert!(allocated <= opened.statement.community_generated);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn allocation_proofs_follow_the_committed_scheme() {
+        let store = store();
+        let community_id = Uuid::new_v4();
+        let end = Utc::now().naive_utc();
+        let start = end - Duration::hours(3);
+        let records = community_records(community_id, start, end);
+        let settings = CommunitySettings {
+            distribution_rule: DistributionRule::Equal,
+            proof_scheme: ProofScheme::Attestation,
+        };
+        let snapshot = store
+            .commit(
+                Utc::now(),
+                records.clone(),
+                &communities(community_id, settings),
+            )
+            .await
+            .unwrap()
+            .remove(0)
+            .signed
+            .clone();
+        let root = snapshot.header.root;
+        let verifying_key = store.verifying_key();
+        let period = MemberPeriod {
+            user_id: records[0].user_id,
+            community_id,
+            start,
+            end,
+        };
+
+        let proof = store.prove_allocation(period.clone()).await.unwrap();
+        let AllocationProof::Attestation(attested) = &proof else {
+            panic!("proof is not made with the committed scheme: {proof:?}");
+        };
+        let latest = store.latest(community_id).await.unwrap();
+        assert_eq!(
+            attested.statement,
+            latest.allocation(period.clone()).unwrap()
+        );
+
+        // Offline verifiers dispatch on the proof itself and reach the same verdict
+        let verdict = store.verify_allocation(&proof, &root);
+        assert!(verdict.valid, "{verdict:?}");
+        assert_eq!(verify_allocation(&proof, &root, &verifying_key), verdict);
+
+        let mut forged = attested.clone();
+        forged.statement.allocated += BigDecimal::new(1.into(), 4);
+        let verdict = store.verify_allocation(&AllocationProof::Attestation(forged), &root);
+        assert_eq!(verdict.failures, [VerificationFailure::InvalidAttestation]);
+
+        // A proof can't pick another scheme than the one the community committed to
+        let opened = AllocationProof::CommitmentOpening(latest.open_allocation(period).unwrap());
+        let mismatch = [VerificationFailure::SchemeMismatch {
+            claimed: ProofScheme::CommitmentOpening,
+            committed: ProofScheme::Attestation,
+        }];
+        assert_eq!(store.verify_allocation(&opened, &root).failures, mismatch);
+        assert_eq!(
+            verify_allocation(&opened, &root, &verifying_key).failures,
+            mismatch
+        );
+    }
+
+    #[tokio::test]
+    async fn aggregated_allocation_proofs_only_open_the_member() {
+        let store = store();
+        let community_id = Uuid::new_v4();
+        let start = NaiveDate::from_ymd_opt(2025, 10, 1)
+            .unwrap()
+            .and_time(NaiveTime::MIN);
+        let next_day = start + Duration::days(1);
+        // Three members with records on both days, and a fourth joining on the second
+        let mut records = community_records(community_id, start, next_day + Duration::hours(3));
+        let late = community_records(community_id, next_day, next_day + Duration::hours(1));
+        let late_member = late[0].user_id;
+        records.extend(
+            late.into_iter()
+                .filter(|record| record.user_id == late_member),
+        );
+        let settings = CommunitySettings {
+            distribution_rule: DistributionRule::Equal,
+            proof_scheme: ProofScheme::AggregateCommitment,
+        };
+        let root = store
+            .commit(
+                Utc::now(),
+                records.clone(),
+                &communities(community_id, settings),
+            )
+            .await
+            .unwrap()[0]
+            .signed
+            .header
+            .root;
+        let latest = store.latest(community_id).await.unwrap();
+
+        let mut members: Vec<_> = records.iter().map(|record| record.user_id).collect();
+        members.dedup();
+
+        for (day_start, active_members) in [(start, 3), (next_day, 4)] {
+            let mut allocated = BigDecimal::from(0);
+            for &user_id in &members {
+                let period = MemberPeriod {
+                    user_id,
+                    community_id,
+                    start: day_start,
+                    end: day_start + Duration::days(1),
+                };
+                let proof = store.prove_allocation(period.clone()).await.unwrap();
+                let AllocationProof::AggregateCommitment(aggregated) = &proof else {
+                    panic!("proof is not made with the committed scheme: {proof:?}");
+                };
+                assert_eq!(aggregated.statement, latest.allocation(period).unwrap());
+                assert_eq!(aggregated.statement.active_members, active_members);
+                // Members without records that day have no aggregate, and no share
+                let active = aggregated.statement.member.record_count > 0;
+                assert_eq!(aggregated.member_aggregate.is_some(), active);
+                if !active {
+                    assert_eq!(aggregated.statement.allocated, BigDecimal::from(0));
+                }
+
+                let verdict = store.verify_allocation(&proof, &root);
+                assert!(verdict.valid, "{verdict:?}");
+                assert_eq!(
+                    verify_allocation(&proof, &root, &store.verifying_key()),
+                    verdict
+                );
+
+                // Nothing in the proof names another member
+                let json = serde_json::to_string(&proof).unwrap();
+                for other in members.iter().filter(|&&other| other != user_id) {
+                    assert!(!json.contains(&other.to_string()));
+                }
+
+                let mut forged = aggregated.clone();
+                forged.statement.allocated += BigDecimal::new(1.into(), 4);
+                let verdict =
+                    store.verify_allocation(&AllocationProof::AggregateCommitment(forged), &root);
+                assert!(matches!(
+                    verdict.failures[..],
+                    [VerificationFailure::AllocationMismatch { .. }]
+                ));
+
+                // Leaving out a member changes the snapshot root
+                let mut missing_member = aggregated.clone();
+                missing_member.aggregates.pop();
+                let verdict = store.verify_allocation(
+                    &AllocationProof::AggregateCommitment(missing_member),
+                    &root,
+                );
+                assert!(!verdict.valid);
+
+                allocated += &aggregated.statement.allocated;
+            }
+            // Rounding down never hands out more than was generated
+            let proof = store
+                .prove_allocation(MemberPeriod {
+                    user_id: members[0],
+                    community_id,
+                    start: day_start,
+                    end: day_start + Duration::days(1),
+                })
+                .await
+                .unwrap();
+            assert!(allocated <= proof.statement().community_generated);
+        }
+
+        // A member can't pass off another member's aggregate as their own
+        let proof = latest
+            .aggregate_allocation(MemberPeriod {
+                user_id: members[0],
+                community_id,
+                start,
+                end: next_day,
+            })
+            .unwrap();
+        let mut swapped = proof.clone();
+        let opening = swapped.member_aggregate.as_mut().unwrap();
+        opening.index = (opening.index + 1) % swapped.aggregates.len();
+        let verdict = store.verify_allocation(
+            &AllocationProof::AggregateCommitment(Box::new(swapped)),
+            &root,
+        );
+        assert!(
+            verdict
+                .failures
+                .contains(&VerificationFailure::TotalMismatch {
+                    quantity: Quantity::Generated
+                })
+        );
+
+        // Aggregates only cover whole days
+        let period = MemberPeriod {
+            user_id: members[0],
+            community_id,
+            start,
+            end: start + Duration::hours(12),
+        };
+        assert_eq!(
+            latest.aggregate_allocation(period.clone()).unwrap_err(),
+            ProveError::InvalidPeriod
+        );
+        let mut unaligned = proof;
+        unaligned.statement.member.period = period;
+        assert!(
+            store
+                .verify_allocation(
+                    &AllocationProof::AggregateCommitment(Box::new(unaligned)),
+                    &root
+                )
+                .failures
+                .contains(&VerificationFailure::UnalignedPeriod)
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    ops::Range,
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::{Context, bail};
+use chrono::Utc;
+use common::{
+    merkle::Hash,
+    snapshot::SignedSnapshot,
+    transparency::{
+        ConsistencyProof, EMPTY_HEAD, InclusionProof, LogEntry, LogHead, SignedLogHead, chain,
+        snapshot_digest,
+    },
+};
+use ed25519_dalek::SigningKey;
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Most entries returned at once, and most digests a proof carries
+pub const MAX_ENTRIES: u64 = 1000;
+
+/// Reasons a log query can't be answered
+#[derive(Debug, PartialEq)]
+pub enum LogError {
+    /// The requested sizes or indices are beyond the log, or out of order
+    InvalidRange,
+    /// The proof would carry more than [`MAX_ENTRIES`] digests
+    RangeTooLarge,
+    /// No entry has the requested snapshot root
+    UnknownRoot,
+}
+
+/// Append-only log of every snapshot committed, see [`common::transparency`]. Entries are kept in
+/// a file, one JSON line each, so the log survives restarts instead of starting over under the
+/// same key, which would look just like showing clients a forked history.
+pub struct TransparencyLog {
+    signing_key: SigningKey,
+    entries: RwLock<Vec<LogEntry>>,
+    file: Mutex<File>,
+}
+
+impl TransparencyLog {
+    /// Opens the log kept in `path`, creating it if it doesn't exist. Every entry is checked to
+    /// chain onto the one before and to be signed with `signing_key`.
+    pub fn open(signing_key: SigningKey, path: &Path) -> anyhow::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        // A crash while appending can only leave the last line cut short
+        let complete = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |last| last + 1);
+        if complete < contents.len() {
+            warn!("Dropping the incomplete last entry of {}", path.display());
+            file.set_len(complete as u64)?;
+        }
+
+        let verifying_key = signing_key.verifying_key();
+        let mut entries: Vec<LogEntry> = Vec::new();
+        for line in contents[..complete].split_inclusive(|&byte| byte == b'\n') {
+            let previous = entries.last().map_or(EMPTY_HEAD, |entry| entry.head);
+            match serde_json::from_slice::<LogEntry>(line) {
+                Ok(entry)
+                    if entry.index == entries.len() as u64
+                        && entry.head == chain(&previous, &snapshot_digest(&entry.snapshot))
+                        && entry.snapshot.verify_signature(&verifying_key) =>
+                {
+                    entries.push(entry)
+                }
+                _ => bail!("Entry {} of {} is corrupted", entries.len(), path.display()),
+            }
+        }
+
+        Ok(Self {
+            signing_key,
+            entries: RwLock::new(entries),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends the snapshots, once they are written to the log's file
+    pub async fn append(
+        &self,
+        snapshots: impl IntoIterator<Item = SignedSnapshot>,
+    ) -> anyhow::Result<()> {
+        let mut entries = self.entries.write().await;
+        let mut appended: Vec<LogEntry> = Vec::new();
+        let mut lines = Vec::new();
+        for snapshot in snapshots {
+            let previous = appended
+                .last()
+                .or(entries.last())
+                .map_or(EMPTY_HEAD, |entry| entry.head);
+            let entry = LogEntry {
+                index: (entries.len() + appended.len()) as u64,
+                head: chain(&previous, &snapshot_digest(&snapshot)),
+                snapshot,
+            };
+            serde_json::to_writer(&mut lines, &entry)?;
+            lines.push(b'\n');
+            appended.push(entry);
+        }
+
+        let mut file = self.file.lock().unwrap();
+        let len = file.metadata()?.len();
+        if let Err(e) = file.write_all(&lines).and_then(|()| file.sync_data()) {
+            // Leave no partial entry for the next append to follow
+            file.set_len(len).ok();
+            return Err(e).context("Failed to write to the transparency log");
+        }
+        entries.extend(appended);
+        Ok(())
+    }
+
+    /// Sequence of the latest snapshot of each community in the log
+    pub async fn latest_sequences(&self) -> HashMap<Uuid, u64> {
+        let mut sequences = HashMap::new();
+        for entry in self.entries.read().await.iter() {
+            let header = &entry.snapshot.header;
+            sequences.insert(header.community_id, header.sequence);
+        }
+        sequences
+    }
+
+    /// Current head of the log, freshly signed
+    pub async fn head(&self) -> SignedLogHead {
+        let entries = self.entries.read().await;
+        SignedLogHead::sign(
+            LogHead {
+                size: entries.len() as u64,
+                head: entries.last().map_or(EMPTY_HEAD, |entry| entry.head),
+                signed_at: Utc::now(),
+            },
+            &self.signing_key,
+        )
+    }
+
+    /// Entries in `range`, up to [`MAX_ENTRIES`] of them
+    pub async fn entries(&self, range: Range<u64>) -> Result<Vec<LogEntry>, LogError> {
+        let entries = self.entries.read().await;
+        let range = checked_range(range, entries.len())?;
+        let end = range.end.min(range.start + MAX_ENTRIES as usize);
+        Ok(entries[range.start..end].to_vec())
+    }
+
+    /// Proves the log at `to_size` extends the log at `from_size`
+    pub async fn consistency(
+        &self,
+        from_size: u64,
+        to_size: u64,
+    ) -> Result<ConsistencyProof, LogError> {
+        let entries = self.entries.read().await;
+        let range = checked_range(from_size..to_size, entries.len())?;
+        // Longer spans can be bridged with the heads of intermediate entries
+        if range.len() as u64 > MAX_ENTRIES {
+            return Err(LogError::RangeTooLarge);
+        }
+
+        Ok(ConsistencyProof {
+            from_size,
+            to_size,
+            digests: entries[range]
+                .iter()
+                .map(|entry| snapshot_digest(&entry.snapshot))
+                .collect(),
+        })
+    }
+
+    /// Proves the snapshot with `root` is in the log at `size`
+    pub async fn inclusion(
+        &self,
+        root: &Hash,
+        size: u64,
+    ) -> Result<(LogEntry, InclusionProof), LogError> {
+        let entries = self.entries.read().await;
+        let entries = &entries[checked_range(0..size, entries.len())?];
+        let entry = entries
+            .iter()
+            .find(|entry| entry.snapshot.header.root == *root)
+            .ok_or(LogError::UnknownRoot)?;
+        let index = entry.index as usize;
+        // A proof at a smaller size can be extended with consistency proofs
+        if (entries.len() - index - 1) as u64 > MAX_ENTRIES {
+            return Err(LogError::RangeTooLarge);
+        }
+
+        let proof = InclusionProof {
+            index: entry.index,
+            previous_head: index
+                .checked_sub(1)
+                .map_or(EMPTY_HEAD, |previous| entries[previous].head),
+            later: entries[index + 1..]
+                .iter()
+                .map(|entry| snapshot_digest(&entry.snapshot))
+                .collect(),
+        };
+        Ok((entry.clone(), proof))
+    }
+}
+
+fn checked_range(range: Range<u64>, len: usize) -> Result<Range<usize>, LogError> {
+    if range.start > range.end || range.end > len as u64 {
+        return Err(LogError::InvalidRange);
+    }
+    Ok(range.start as usize..range.end as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        allocation::{DistributionRule, ProofScheme},
+        snapshot::SnapshotHeader,
+    };
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn snapshot(sequence: u64, signing_key: &SigningKey) -> SignedSnapshot {
+        SignedSnapshot::sign(
+            SnapshotHeader {
+                community_id: Uuid::new_v4(),
+                sequence,
+                taken_at: Utc::now(),
+                record_count: 1,
+                distribution_rule: DistributionRule::default(),
+                proof_scheme: ProofScheme::default(),
+                root: Hash::digest(&sequence.to_be_bytes()),
+            },
+            signing_key,
+        )
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("petall-log-{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn temp_log(signing_key: &SigningKey) -> TransparencyLog {
+        TransparencyLog::open(signing_key.clone(), &temp_path()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn heads_are_consistent_as_the_log_grows() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let verifying_key = signing_key.verifying_key();
+        let log = temp_log(&signing_key);
+        let snapshots: Vec<_> = (1..=5)
+            .map(|sequence| snapshot(sequence, &signing_key))
+            .collect();
+
+        let empty = log.head().await;
+        log.append(snapshots[..2].to_vec()).await.unwrap();
+        let small = log.head().await;
+        log.append(snapshots[2..].to_vec()).await.unwrap();
+        let large = log.head().await;
+        assert!(large.verify_signature(&verifying_key));
+        assert_eq!(large.head.size, 5);
+
+        for (from, to) in [
+            (&empty, &small),
+            (&small, &large),
+            (&empty, &large),
+            (&large, &large),
+        ] {
+            let proof = log.consistency(from.head.size, to.head.size).await.unwrap();
+            assert!(proof.verify(&from.head, &to.head));
+        }
+
+        // A proof doesn't link heads of other sizes, nor a head that was rewritten
+        let proof = log.consistency(2, 5).await.unwrap();
+        assert!(!proof.verify(&empty.head, &large.head));
+        let mut rewritten = large.head.clone();
+        rewritten.head = Hash::digest(b"fork");
+        assert!(!proof.verify(&small.head, &rewritten));
+
+        assert_eq!(log.consistency(3, 2).await, Err(LogError::InvalidRange));
+        assert_eq!(log.consistency(0, 6).await, Err(LogError::InvalidRange));
+
+        let entries = log.entries(1..4).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].snapshot, snapshots[1]);
+        assert_eq!(entries[2].head, log.entries(3..4).await.unwrap()[0].head);
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_proven_included() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let log = temp_log(&signing_key);
+        let snapshots: Vec<_> = (1..=4)
+            .map(|sequence| snapshot(sequence, &signing_key))
+            .collect();
+        log.append(snapshots.clone()).await.unwrap();
+        let head = log.head().await;
+
+        for snapshot in &snapshots {
+            let (entry, proof) = log.inclusion(&snapshot.header.root, 4).await.unwrap();
+            assert_eq!(entry.snapshot, *snapshot);
+            assert!(proof.verify(snapshot, &head.head));
+            assert!(!proof.verify(&snapshots[(entry.index as usize + 1) % 4], &head.head));
+        }
+
+        // Only entries the requested size covers
+        assert_eq!(
+            log.inclusion(&snapshots[3].header.root, 3)
+                .await
+                .unwrap_err(),
+            LogError::UnknownRoot
+        );
+    }
+
+    #[tokio::test]
+    async fn forked_logs_are_caught() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let verifying_key = signing_key.verifying_key();
+        let shared = snapshot(1, &signing_key);
+
+        // The same service shows two clients logs that diverge after the first entry
+        let (log, fork) = (temp_log(&signing_key), temp_log(&signing_key));
+        log.append([shared.clone(), snapshot(2, &signing_key)])
+            .await
+            .unwrap();
+        fork.append([shared, snapshot(2, &signing_key)])
+            .await
+            .unwrap();
+
+        let (head, forked_head) = (log.head().await, fork.head().await);
+        assert!(head.equivocates(&forked_head, &verifying_key));
+        assert!(!head.equivocates(&log.head().await, &verifying_key));
+    }
+
+    #[tokio::test]
+    async fn the_log_is_reloaded_from_its_file() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let path = temp_path();
+        let log = TransparencyLog::open(signing_key.clone(), &path).unwrap();
+        let snapshots: Vec<_> = (1..=3)
+            .map(|sequence| snapshot(sequence, &signing_key))
+            .collect();
+        log.append(snapshots[..2].to_vec()).await.unwrap();
+        log.append(snapshots[2..].to_vec()).await.unwrap();
+        let head = log.head().await;
+        drop(log);
+
+        // A crash while appending leaves a cut short line, which is dropped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"index":3,"snap"#).unwrap();
+
+        let reloaded = TransparencyLog::open(signing_key.clone(), &path).unwrap();
+        let reloaded_head = reloaded.head().await;
+        assert_eq!(reloaded_head.head.size, 3);
+        assert_eq!(reloaded_head.head.head, head.head.head);
+
+        // And the log grows on from there
+        reloaded.append([snapshot(4, &signing_key)]).await.unwrap();
+        let grown = TransparencyLog::open(signing_key.clone(), &path).unwrap();
+        let proof = grown.consistency(3, 4).await.unwrap();
+        assert!(proof.verify(&head.head, &grown.head().await.head));
+
+        // Entries signed with another key, or rewritten, are refused
+        assert!(TransparencyLog::open(SigningKey::from_bytes(&[2; 32]), &path).is_err());
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replacen("\"index\":1", "\"index\":7", 1)).unwrap();
+        assert!(TransparencyLog::open(signing_key, &path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn proofs_are_capped() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let log = temp_log(&signing_key);
+        let snapshots: Vec<_> = (1..=MAX_ENTRIES + 2)
+            .map(|sequence| snapshot(sequence, &signing_key))
+            .collect();
+        log.append(snapshots.clone()).await.unwrap();
+        let size = snapshots.len() as u64;
+
+        assert!(log.consistency(2, size).await.is_ok());
+        assert_eq!(log.consistency(1, size).await, Err(LogError::RangeTooLarge));
+        assert!(log.inclusion(&snapshots[1].header.root, size).await.is_ok());
+        assert_eq!(
+            log.inclusion(&snapshots[0].header.root, size)
+                .await
+                .unwrap_err(),
+            LogError::RangeTooLarge
+        );
+    }
+}